@@ -0,0 +1,215 @@
+//! Key and controller bindings, read at startup from `rusty_gbc.cfg` in the working directory or the file given with --config.
+//!
+//! The file has a `[keyboard]` and a `[controller]` section binding actions to comma separated
+//! SDL key or game controller button names, and an `[options]` section. Binding an action replaces
+//! its default bindings, an empty list leaves it unbound. Anything after a `#` is a comment.
+//!
+//! ```text
+//! [keyboard]
+//! a = S, X
+//! b = A, Z
+//! quit = Escape
+//!
+//! [controller]
+//! # SDL names: a b x y back guide start leftstick rightstick leftshoulder rightshoulder dpup ...
+//! a = b
+//! b = a
+//! tileset_viewer = leftshoulder
+//!
+//! [options]
+//! stick = left           # left, right or none
+//! deadzone = 8000        # 0 - 32767
+//! filter_opposing = true # hide left+right and up+down pressed together
+//! ```
+use super::rusty_gbc::gbc::gpu::Layer;
+use super::rusty_gbc::gbc::input;
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "rusty_gbc.cfg";
+
+/// What a key or controller button does
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Action {
+    Joypad(input::Keycode),
+    Quit,
+    ToggleLog,
+    ToggleLayer(Layer),
+    HighlightLayers,
+    /// Shows or hides a debug window, in the order the frontend opens them
+    DebugWindow(usize)
+}
+
+const ACTIONS: [(&str, Action); 18] = [
+    ("a", Action::Joypad(input::Keycode::A)),
+    ("b", Action::Joypad(input::Keycode::B)),
+    ("start", Action::Joypad(input::Keycode::Start)),
+    ("select", Action::Joypad(input::Keycode::Select)),
+    ("up", Action::Joypad(input::Keycode::Up)),
+    ("down", Action::Joypad(input::Keycode::Down)),
+    ("left", Action::Joypad(input::Keycode::Left)),
+    ("right", Action::Joypad(input::Keycode::Right)),
+    ("quit", Action::Quit),
+    ("toggle_log", Action::ToggleLog),
+    ("toggle_background", Action::ToggleLayer(Layer::Background)),
+    ("toggle_window", Action::ToggleLayer(Layer::Window)),
+    ("toggle_sprites", Action::ToggleLayer(Layer::Sprites)),
+    ("highlight_layers", Action::HighlightLayers),
+    ("tileset_viewer", Action::DebugWindow(0)),
+    ("tile_map_viewer", Action::DebugWindow(1)),
+    ("oam_viewer", Action::DebugWindow(2)),
+    ("palette_viewer", Action::DebugWindow(3))
+];
+
+pub struct Config {
+    keys: HashMap<Keycode, Action>,
+    buttons: HashMap<Button, Action>,
+    /// Horizontal and vertical axes of the analog stick driving the D-pad
+    pub stick: Option<(Axis, Axis)>,
+    /// How far the stick has to move from the center to press a direction
+    pub deadzone: i16,
+    pub filter_opposing: bool
+}
+
+impl Config {
+    /// Reads the config file, falling back to the defaults if there is none
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("Could not read {}: {}", path.display(), e))
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut section = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1 .. line.len() - 1].trim().to_string();
+                continue;
+            }
+            config.parse_line(&section, line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        }
+        Ok(config)
+    }
+
+    fn parse_line(&mut self, section: &str, line: &str) -> Result<(), String> {
+        let mut parts = line.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().ok_or_else(|| format!("expected name = value, found \"{}\"", line))?.trim();
+        match section {
+            "keyboard" => {
+                let keys = parse_list(value, "key", Keycode::from_name)?;
+                bind(&mut self.keys, action(name)?, keys);
+            },
+            "controller" => {
+                let buttons = parse_list(value, "controller button", Button::from_string)?;
+                bind(&mut self.buttons, action(name)?, buttons);
+            },
+            "options" => match name {
+                "stick" => self.stick = match value {
+                    "left" => Some((Axis::LeftX, Axis::LeftY)),
+                    "right" => Some((Axis::RightX, Axis::RightY)),
+                    "none" => None,
+                    _ => return Err(format!("stick must be left, right or none, found \"{}\"", value))
+                },
+                "deadzone" => self.deadzone = value.parse().map_err(|_| format!("invalid deadzone \"{}\"", value))?,
+                "filter_opposing" => self.filter_opposing = value.parse().map_err(|_| format!("filter_opposing must be true or false, found \"{}\"", value))?,
+                _ => return Err(format!("unknown option \"{}\"", name))
+            },
+            "" => return Err("binding outside of a section".to_string()),
+            _ => return Err(format!("unknown section [{}]", section))
+        }
+        Ok(())
+    }
+
+    pub fn key_action(&self, key: Keycode) -> Option<Action> {
+        self.keys.get(&key).cloned()
+    }
+
+    pub fn button_action(&self, button: Button) -> Option<Action> {
+        self.buttons.get(&button).cloned()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let keys = [
+            (Keycode::S, "a"), (Keycode::A, "b"), (Keycode::Return, "start"), (Keycode::Space, "select"),
+            (Keycode::Up, "up"), (Keycode::Down, "down"), (Keycode::Left, "left"), (Keycode::Right, "right"),
+            (Keycode::Escape, "quit"), (Keycode::L, "toggle_log"),
+            (Keycode::Num1, "toggle_background"), (Keycode::Num2, "toggle_window"),
+            (Keycode::Num3, "toggle_sprites"), (Keycode::Num4, "highlight_layers"),
+            (Keycode::F1, "tileset_viewer"), (Keycode::F2, "tile_map_viewer"),
+            (Keycode::F3, "oam_viewer"), (Keycode::F4, "palette_viewer")
+        ];
+        // By position rather than label, the right face button is A like on a Game Boy
+        let buttons = [
+            (Button::B, "a"), (Button::A, "b"), (Button::Start, "start"), (Button::Back, "select"),
+            (Button::DPadUp, "up"), (Button::DPadDown, "down"), (Button::DPadLeft, "left"), (Button::DPadRight, "right")
+        ];
+        Config {
+            keys: keys.iter().map(|&(key, name)| (key, action(name).unwrap())).collect(),
+            buttons: buttons.iter().map(|&(button, name)| (button, action(name).unwrap())).collect(),
+            stick: Some((Axis::LeftX, Axis::LeftY)),
+            deadzone: 8000,
+            filter_opposing: false
+        }
+    }
+}
+
+fn action(name: &str) -> Result<Action, String> {
+    ACTIONS.iter()
+        .find(|(action_name, _)| *action_name == name)
+        .map(|&(_, action)| action)
+        .ok_or_else(|| format!("unknown action \"{}\"", name))
+}
+
+fn parse_list<T, F: Fn(&str) -> Option<T>>(value: &str, kind: &str, parse: F) -> Result<Vec<T>, String> {
+    value.split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| parse(name).ok_or_else(|| format!("unknown {} \"{}\"", kind, name)))
+        .collect()
+}
+
+/// Replaces the inputs bound to `action`
+fn bind<T: Eq + Hash>(bindings: &mut HashMap<T, Action>, action: Action, inputs: Vec<T>) {
+    bindings.retain(|_, bound| *bound != action);
+    for input in inputs {
+        bindings.insert(input, action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bindings_replace_defaults() {
+        let config = Config::parse("[keyboard]\na = Z, x # both\nquit =\n[controller]\nselect = guide\n").unwrap();
+        assert_eq!(config.key_action(Keycode::Z), Some(Action::Joypad(input::Keycode::A)));
+        assert_eq!(config.key_action(Keycode::X), Some(Action::Joypad(input::Keycode::A)));
+        assert_eq!(config.key_action(Keycode::S), None);
+        assert_eq!(config.key_action(Keycode::Escape), None);
+        assert_eq!(config.key_action(Keycode::Return), Some(Action::Joypad(input::Keycode::Start)));
+        assert_eq!(config.button_action(Button::Guide), Some(Action::Joypad(input::Keycode::Select)));
+        assert_eq!(config.button_action(Button::Back), None);
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        assert_eq!(Config::parse("[keyboard]\n\na = NotAKey").err().unwrap(), "line 3: unknown key \"NotAKey\"");
+        assert_eq!(Config::parse("[options]\nstick = middle").err().unwrap(), "line 2: stick must be left, right or none, found \"middle\"");
+        assert!(Config::parse("a = S").is_err());
+    }
+}
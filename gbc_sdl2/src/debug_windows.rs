@@ -0,0 +1,134 @@
+use super::rusty_gbc::DebugImage;
+use super::rusty_gbc::gbc::gpu::Gpu;
+use sdl2::VideoSubsystem;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
+
+const SCALE: u32 = 2;
+
+#[derive(Copy, Clone)]
+pub enum DebugView {
+    TileSet,
+    TileMaps,
+    Oam,
+    Palettes
+}
+
+/// Creates the hidden window of a `DebugWindow`, sized for the current image of `view`
+pub fn create_canvas(video_subsystem: &VideoSubsystem, view: DebugView, gpu: &Gpu) -> Result<WindowCanvas, String> {
+    let title = match view {
+        DebugView::TileSet => "Tileset",
+        DebugView::TileMaps => "Tile Maps",
+        DebugView::Oam => "OAM",
+        DebugView::Palettes => "Palettes"
+    };
+    let image = view_image(view, gpu);
+    let window = video_subsystem.window(title, image.width as u32 * SCALE, image.height as u32 * SCALE)
+        .resizable()
+        .hidden()
+        .build()
+        .map_err(|e| e.to_string())?;
+    window.into_canvas()
+        .target_texture()
+        .accelerated()
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// A hidden by default window showing one of the GPU debug images
+pub struct DebugWindow<'a> {
+    pub canvas: WindowCanvas,
+    tc: &'a TextureCreator<WindowContext>,
+    /// Updated every frame, only recreated if the image changes size
+    texture: Texture<'a>,
+    view: DebugView,
+    visible: bool
+}
+
+impl<'a> DebugWindow<'a> {
+    /// `tc` is the texture creator of `canvas`
+    pub fn new(canvas: WindowCanvas, tc: &'a TextureCreator<WindowContext>, view: DebugView, gpu: &Gpu) -> Result<Self, String> {
+        let image = view_image(view, gpu);
+        let texture = create_texture(tc, &image)?;
+        Ok(DebugWindow {
+            canvas,
+            tc,
+            texture,
+            view,
+            visible: false
+        })
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn toggle(&mut self) {
+        self.set_visible(!self.visible);
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+        if visible {
+            self.canvas.window_mut().show();
+        } else {
+            self.canvas.window_mut().hide();
+        }
+    }
+
+    pub fn update(&mut self, gpu: &Gpu) -> Result<(), String> {
+        if !self.visible {
+            return Ok(());
+        }
+        let image = view_image(self.view, gpu);
+        let query = self.texture.query();
+        if query.width != image.width as u32 || query.height != image.height as u32 {
+            self.texture = create_texture(self.tc, &image)?;
+        }
+        self.texture.update(None, &image.to_rgba8888(), image.width * 4).map_err(|e| e.to_string())?;
+        self.canvas.copy(&self.texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+fn view_image(view: DebugView, gpu: &Gpu) -> DebugImage {
+    match view {
+        DebugView::TileSet => tile_set_image(gpu),
+        DebugView::TileMaps => tile_maps_image(gpu),
+        DebugView::Oam => gpu.oam_image(),
+        DebugView::Palettes => gpu.palette_image()
+    }
+}
+
+fn create_texture<'a>(tc: &'a TextureCreator<WindowContext>, image: &DebugImage) -> Result<Texture<'a>, String> {
+    tc.create_texture_streaming(PixelFormatEnum::ABGR8888, image.width as u32, image.height as u32)
+        .map_err(|e| e.to_string())
+}
+
+/// Both VRAM banks side by side
+fn tile_set_image(gpu: &Gpu) -> DebugImage {
+    side_by_side(&gpu.tile_set_image(0), &gpu.tile_set_image(1))
+}
+
+/// The 9800 and 9C00 maps side by side
+fn tile_maps_image(gpu: &Gpu) -> DebugImage {
+    side_by_side(&gpu.tile_map_image(false), &gpu.tile_map_image(true))
+}
+
+fn side_by_side(left: &DebugImage, right: &DebugImage) -> DebugImage {
+    let gap = 4;
+    let mut image = DebugImage::new(left.width + gap + right.width, left.height.max(right.height));
+    for y in 0 .. left.height {
+        for x in 0 .. left.width {
+            image.set_pixel(x, y, left.pixel(x, y));
+        }
+    }
+    for y in 0 .. right.height {
+        for x in 0 .. right.width {
+            image.set_pixel(left.width + gap + x, y, right.pixel(x, y));
+        }
+    }
+    image
+}
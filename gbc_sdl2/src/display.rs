@@ -1,4 +1,4 @@
-use super::rusty_gbc::{Display, Color, SCREEN_WIDTH, SCREEN_HEIGHT, BYTES_PER_PIXEL};
+use super::rusty_gbc::{Color, Display, FrameBuffer, BYTES_PER_PIXEL};
 use sdl2::render::{Texture, TextureCreator, WindowCanvas};
 use sdl2::video::WindowContext;
 
@@ -8,11 +8,12 @@ pub struct SdlDisplay<'a> {
 }
 
 impl SdlDisplay<'_> {
-    pub fn new(canvas: WindowCanvas, tc: &'_ TextureCreator<WindowContext>) -> SdlDisplay<'_> {
+    /// `width` and `height` are the size of the emulated screen, larger than the LCD with an SGB border
+    pub fn new(canvas: WindowCanvas, tc: &'_ TextureCreator<WindowContext>, width: u32, height: u32) -> SdlDisplay<'_> {
         let tex = tc.create_texture_streaming(
             sdl2::pixels::PixelFormatEnum::ABGR8888,
-            SCREEN_WIDTH as u32,
-            SCREEN_HEIGHT as u32,
+            width,
+            height,
         ).unwrap();
         SdlDisplay {
             canvas: canvas,
@@ -24,24 +25,31 @@ impl SdlDisplay<'_> {
         let t: &str = &(title.to_owned())[..];
         self.canvas.window_mut().set_title(t).unwrap();
     }
-}
 
-impl Display for SdlDisplay<'_> {
-    fn render_frame(&mut self) {
+    /// Shows rows of `width` pixels, like the SGB output with its border
+    pub fn render_pixels(&mut self, pixels: &[Color], width: usize) {
+        self.texture.with_lock(None, |sdl_buffer: &mut [u8], pitch: usize| {
+            for (y, row) in pixels.chunks(width).enumerate() {
+                for (x, color) in row.iter().enumerate() {
+                    let buf_idx = (y * pitch) + (x * BYTES_PER_PIXEL as usize);
+                    sdl_buffer[buf_idx] = color.r;
+                    sdl_buffer[buf_idx + 1] = color.g;
+                    sdl_buffer[buf_idx + 2] = color.b;
+                    sdl_buffer[buf_idx + 3] = 0xFF;
+                }
+            }
+        }).unwrap();
         self.canvas.copy(&self.texture, None, None).unwrap();
         self.canvas.present();
     }
+}
 
-    fn update_line_from_buffer(&mut self, buffer: [Color; SCREEN_WIDTH as usize], pixel_y: u8) {
+impl Display for SdlDisplay<'_> {
+    fn render_frame(&mut self, frame: &FrameBuffer) {
         self.texture.with_lock(None, |sdl_buffer: &mut [u8], pitch: usize| {
-            for pixel_x in 0 .. SCREEN_WIDTH {
-                let buf_idx = (pixel_y as usize * pitch) + (pixel_x as usize * BYTES_PER_PIXEL as usize);
-                let color = buffer[pixel_x as usize];
-                sdl_buffer[buf_idx] = color.r;
-                sdl_buffer[buf_idx + 1] = color.g;
-                sdl_buffer[buf_idx + 2] = color.b;
-                sdl_buffer[buf_idx + 3] = 0xFF;
-            }
+            frame.write_rgba8888(sdl_buffer, pitch);
         }).unwrap();
+        self.canvas.copy(&self.texture, None, None).unwrap();
+        self.canvas.present();
     }
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+use super::config::Config;
+use super::rusty_gbc::gbc::input::{Input, InputSource, Keycode, MAX_PLAYERS};
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, GameController};
+
+/// Connected game controllers, each driving the joypad of the player matching its connection order.
+/// Without the SDL game controller subsystem none are ever connected
+pub struct Gamepads {
+    subsystem: Option<GameControllerSubsystem>,
+    controllers: Vec<Controller>
+}
+
+struct Controller {
+    controller: GameController,
+    /// Direction the analog stick is pushed on each axis, -1, 0 or 1
+    stick: [i8; 2]
+}
+
+impl Gamepads {
+    pub fn new(subsystem: Option<GameControllerSubsystem>) -> Self {
+        Gamepads {
+            subsystem,
+            controllers: Vec::new()
+        }
+    }
+
+    /// Opens a newly connected controller, SDL reports the ones already connected at startup too
+    pub fn add(&mut self, joystick_index: u32) {
+        let subsystem = match &self.subsystem {
+            Some(subsystem) if subsystem.is_game_controller(joystick_index) => subsystem,
+            _ => return
+        };
+        match subsystem.open(joystick_index) {
+            Ok(controller) => {
+                println!("Controller {} connected: {}", self.controllers.len() + 1, controller.name());
+                self.controllers.push(Controller { controller, stick: [0; 2] });
+            },
+            Err(e) => println!("Could not open controller {}: {}", joystick_index, e)
+        }
+    }
+
+    pub fn remove(&mut self, id: u32, input: &mut Input) {
+        self.controllers.retain(|c| c.controller.instance_id() as u32 != id);
+        // Later controllers move up a player, so nothing they held is still valid
+        input.release_all(InputSource::Gamepad);
+        for controller in self.controllers.iter_mut() {
+            controller.stick = [0; 2];
+        }
+    }
+
+    /// The joypad a controller plays on, later controllers share the last one
+    pub fn player(&self, id: u32) -> usize {
+        let index = self.controllers.iter().position(|c| c.controller.instance_id() as u32 == id).unwrap_or(0);
+        index.min(MAX_PLAYERS - 1)
+    }
+
+    /// Presses the D-pad direction the analog stick is pushed towards
+    pub fn axis_motion(&mut self, id: u32, axis: Axis, value: i16, config: &Config, input: &mut Input) {
+        let (x_axis, y_axis) = match config.stick {
+            Some(axes) => axes,
+            None => return
+        };
+        let (index, negative, positive) = if axis == x_axis {
+            (0, Keycode::Left, Keycode::Right)
+        } else if axis == y_axis {
+            (1, Keycode::Up, Keycode::Down)
+        } else {
+            return
+        };
+        let player = self.player(id);
+        let controller = match self.controllers.iter_mut().find(|c| c.controller.instance_id() as u32 == id) {
+            Some(controller) => controller,
+            None => return
+        };
+        let deadzone = config.deadzone.max(0);
+        let direction = if value < -deadzone { -1 } else if value > deadzone { 1 } else { 0 };
+        let previous = controller.stick[index];
+        if direction == previous {
+            return;
+        }
+        // Only changes on crossing the deadzone, so a resting stick leaves the D-pad buttons alone
+        match previous {
+            -1 => input.release(InputSource::Gamepad, player, negative),
+            1 => input.release(InputSource::Gamepad, player, positive),
+            _ => {}
+        }
+        match direction {
+            -1 => input.press(InputSource::Gamepad, player, negative),
+            1 => input.press(InputSource::Gamepad, player, positive),
+            _ => {}
+        }
+        controller.stick[index] = direction;
+    }
+}
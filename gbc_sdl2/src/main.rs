@@ -1,153 +1,348 @@
 extern crate rusty_gbc;
 
-use rusty_gbc::gbc::Cpu;
+use rusty_gbc::gbc::{BootMode, Cpu, CpuStatus, Model, header_check};
+use rusty_gbc::gbc::sgb::{SGB_WIDTH, SGB_HEIGHT};
 use rusty_gbc::debugger::Debugger;
-use rusty_gbc::{SCREEN_HEIGHT, SCREEN_WIDTH};
-use rusty_gbc::gbc::gpu::Gpu;
+use rusty_gbc::{Display, SCREEN_HEIGHT, SCREEN_WIDTH};
+use rusty_gbc::gbc::gpu::{Gpu, Layer};
+use rusty_gbc::gbc::input::InputSource;
+use rusty_gbc::gbc::printer::Printer;
 use std::env;
 
 extern crate sdl2;
-use sdl2::keyboard::Keycode;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 
 use std::fs::File;
-use std::io::prelude::*;
-use std::time::Instant;
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::process;
 
 mod display;
+mod debug_windows;
+mod config;
+mod gamepad;
+mod options;
 use display::SdlDisplay;
+use debug_windows::{DebugWindow, DebugView};
+use config::{Action, Config};
+use gamepad::Gamepads;
+use options::{Options, USAGE};
 
-fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() > 1 {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-
-        let window = video_subsystem.window("Rusty GBC", SCREEN_WIDTH as u32 * 3, SCREEN_HEIGHT as u32 * 3)
-            .position_centered()
-            .resizable()
-            .build()
-            .unwrap();
-
-        let canvas = window.into_canvas()
-            .target_texture()
-            .present_vsync()
-            .accelerated()
-            .build()
-            .unwrap();
-
-        let tc = canvas.texture_creator();
-        let mut display = SdlDisplay::new(canvas, &tc);
-
-        // let tiles_window = video_subsystem.window("Tileset", 16 * 8 * 2, (384 / 16) * 8 * 2)
-        //     .position_centered()
-        //     .opengl()
-        //     .resizable()
-        //     .build()
-        //     .unwrap();
-        // let mut tile_canvas = tiles_window.into_canvas()
-        //     .target_texture()
-        //     .present_vsync()
-        //     .accelerated()
-        //     .build()
-        //     .unwrap(); 
-
-        let mut file = File::open(&args[1]).unwrap();
-        let mut buffer = Vec::<u8>::new();
-        file.read_to_end(&mut buffer).unwrap();
-        
-        let color_mode = buffer[0x143] & 0x80 == 0x80 || buffer[0x143] & 0xC0 == 0xC0;
-        let gpu = Gpu::new(color_mode).unwrap();
-        let mut gbc = Cpu::new(buffer, gpu);
+/// 4194304 cycles per second over 70224 cycles per frame
+const FRAME_RATE: f64 = 4_194_304.0 / 70_224.0;
 
-        if args.len() > 2 {
-            let debugger = Debugger::new(&args[2]);
-            gbc.attatch_debugger(debugger);
+fn main() {
+    match Options::parse(env::args().skip(1)) {
+        Ok(Some(options)) => {
+            if let Err(e) = run(&options) {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        },
+        Ok(None) => println!("{}", USAGE),
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            process::exit(2);
         }
-        
-        let mut event_pump = sdl_context.event_pump()?;
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom = load_rom(&options.rom)?;
+    let model = options.model.unwrap_or_else(|| Model::detect(&rom));
+    if !header_check(&rom, model) {
+        println!("Warning: {} has an invalid header logo or checksum, the boot ROM will lock up", options.rom.display());
+    }
+
+    let mut gpu = Gpu::with_model(model, model.color_mode_for(&rom, false))?;
+    if let Some(palette) = options.palette {
+        gpu.set_monochrome_palette(palette);
+    }
+    let boot_mode = match &options.boot_rom {
+        Some(path) => BootMode::External(std::fs::read(path).map_err(|e| format!("Could not read boot ROM {}: {}", path.display(), e))?),
+        None if options.skip_boot => BootMode::Skip,
+        None => BootMode::BuiltIn
+    };
+    let mut gbc = Cpu::with_boot_mode(rom, gpu, boot_mode)
+        .map_err(|e| format!("Could not start {}: {}", options.rom.display(), e))?;
+
+    if let Some(breakpoints) = &options.breakpoints {
+        gbc.attatch_debugger(Debugger::new(&breakpoints.join(" ")));
+    }
+    if let Some(path) = &options.log_file {
+        let file = File::create(path).map_err(|e| format!("Could not create log file {}: {}", path.display(), e))?;
+        gbc.set_log_output(Box::new(BufWriter::new(file)));
+        gbc.log = true;
+    }
+
+    if let Some(dir) = &options.printer {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Could not create printer directory {}: {}", dir.display(), e))?;
+        gbc.mem.connect_serial(Box::new(Printer::new(Some(dir.clone()))));
+    }
+
+    let save_path = save_path(options);
+    if let Ok(save) = std::fs::read(&save_path) {
+        gbc.mem.load_save_data(&save);
+    }
+
+    // Headless runs as fast as it can unless asked otherwise, the window follows the display refresh
+    let limiter = FrameLimiter::new(options.speed.unwrap_or(0.0));
+    let result = if options.headless {
+        run_headless(&mut gbc, options, limiter)
+    } else {
+        run_window(&mut gbc, options, limiter)
+    };
+
+    if let Some(save) = gbc.mem.save_data() {
+        if let Some(dir) = save_path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(&save_path, save).map_err(|e| format!("Could not write {}: {}", save_path.display(), e))?;
+    }
+    result
+}
+
+/// Reads a ROM, explaining why if it cannot be run
+fn load_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let rom = match std::fs::read(path) {
+        Ok(rom) => rom,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Err(format!("ROM {} not found", path.display())),
+        Err(e) => return Err(format!("Could not read ROM {}: {}", path.display(), e))
+    };
+    // The header ends at 0x150
+    if rom.len() < 0x150 {
+        return Err(format!("{} is not a Game Boy ROM, it is only {} bytes long", path.display(), rom.len()));
+    }
+    Ok(rom)
+}
+
+/// `<rom>.sav` next to the ROM or in the save directory
+fn save_path(options: &Options) -> PathBuf {
+    let path = options.rom.with_extension("sav");
+    match (&options.save_dir, path.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => path
+    }
+}
+
+/// Runs without a window or input until the frame limit, if there is one. Serial output goes
+/// to stdout unless a printer is connected
+fn run_headless(gbc: &mut Cpu, options: &Options, mut limiter: FrameLimiter) -> Result<(), String> {
+    let mut frames_run = 0;
+    while options.frames.map_or(true, |frames| frames_run < frames) {
+        gbc.run_until_frame();
+        // Test ROMs rarely end their output with a newline, show it as it arrives
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        limiter.wait();
+        if let CpuStatus::Locked { opcode, address } = gbc.status() {
+            return Err(format!("Locked up by illegal opcode ${:02X} at ${:04X}", opcode, address));
+        }
+        frames_run += 1;
+    }
+    Ok(())
+}
+
+fn run_window(gbc: &mut Cpu, options: &Options, mut limiter: FrameLimiter) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let (width, height) = if gbc.mem.sgb().is_some() {
+        (SGB_WIDTH as u32, SGB_HEIGHT as u32)
+    } else {
+        (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+    };
+    let mut window_builder = video_subsystem.window("Rusty GBC", width * options.scale, height * options.scale);
+    window_builder.position_centered().resizable();
+    if options.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().map_err(|e| e.to_string())?;
 
-        let mut timer = Instant::now();
-        let mut framecount = 0;
+    let mut canvas_builder = window.into_canvas().target_texture().accelerated();
+    if options.speed.is_none() {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let canvas = canvas_builder.build().map_err(|e| e.to_string())?;
+
+    let tc = canvas.texture_creator();
+    let mut display = SdlDisplay::new(canvas, &tc, width, height);
+
+    let config = Config::load(&options.config)?;
+    gbc.mem.input.set_filter_opposing(config.filter_opposing);
+    // Keyboard play still works without game controller support
+    let mut gamepads = Gamepads::new(sdl_context.game_controller().ok());
+
+    let main_window_id = display.canvas.window().id();
+    // In the order of the tileset, tile map, OAM and palette viewer actions
+    let views = [DebugView::TileSet, DebugView::TileMaps, DebugView::Oam, DebugView::Palettes];
+    let mut canvases = Vec::new();
+    for view in views.iter() {
+        canvases.push(debug_windows::create_canvas(&video_subsystem, *view, &gbc.mem.gpu)?);
+    }
+    let texture_creators: Vec<_> = canvases.iter().map(|canvas| canvas.texture_creator()).collect();
+    let mut debug_windows = Vec::new();
+    for ((canvas, tc), view) in canvases.into_iter().zip(texture_creators.iter()).zip(views.iter()) {
+        debug_windows.push(DebugWindow::new(canvas, tc, *view, &gbc.mem.gpu)?);
+    }
+
+    let mut event_pump = sdl_context.event_pump()?;
+
+    // Rumble carts drive the haptics of the first joystick if it has any, without the
+    // joystick or haptic subsystems there is simply no rumble
+    let _joystick_subsystem = sdl_context.joystick().ok();
+    let mut haptic = sdl_context.haptic().ok().and_then(|haptic| haptic.open_from_joystick_id(0).ok());
+    let mut rumbling = false;
+
+    let mut timer = Instant::now();
+    let mut framecount = 0;
+    let mut frames_run = 0;
 
-        'main: loop {
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit {..} |
-                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+    'main: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} => break 'main,
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } => {
+                    if window_id == main_window_id {
                         break 'main
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::L), .. } => {
-                        gbc.log = !gbc.log;
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::A);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::S), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::A);
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::B);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::A), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::B);
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::Start);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Return), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::Start);
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::Select);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Space), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::Select);
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::Left);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Left), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::Left);
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::Right);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Right), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::Right);
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::Down);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Down), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::Down);
-                    },
-                    Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-                        gbc.mem.input.key_pressed(rusty_gbc::gbc::input::Keycode::Up);
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Up), .. } => {
-                        gbc.mem.input.key_released(rusty_gbc::gbc::input::Keycode::Up);
-                    },
-                    Event::MouseButtonDown { .. } => {
-                        println!("PC: {:04X} op {:02X}", gbc.regs.pc, gbc.mem.read(gbc.regs.pc));
                     }
-                    _ => {}
+                    for debug_window in debug_windows.iter_mut().filter(|w| w.window_id() == window_id) {
+                        debug_window.set_visible(false);
+                    }
+                },
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    if let Some(action) = config.key_action(key) {
+                        if !perform(action, true, InputSource::Keyboard, 0, gbc, &mut debug_windows) {
+                            break 'main
+                        }
+                    }
+                },
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    if let Some(action) = config.key_action(key) {
+                        perform(action, false, InputSource::Keyboard, 0, gbc, &mut debug_windows);
+                    }
+                },
+                Event::ControllerDeviceAdded { which, .. } => gamepads.add(which),
+                Event::ControllerDeviceRemoved { which, .. } => gamepads.remove(which, &mut gbc.mem.input),
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(action) = config.button_action(button) {
+                        if !perform(action, true, InputSource::Gamepad, gamepads.player(which), gbc, &mut debug_windows) {
+                            break 'main
+                        }
+                    }
+                },
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(action) = config.button_action(button) {
+                        perform(action, false, InputSource::Gamepad, gamepads.player(which), gbc, &mut debug_windows);
+                    }
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    gamepads.axis_motion(which, axis, value, &config, &mut gbc.mem.input);
+                },
+                Event::MouseButtonDown { .. } => {
+                    println!("PC: {:04X} op {:02X}", gbc.regs.pc, gbc.mem.peek(gbc.regs.pc));
+                },
+                // The mouse position relative to the window center tilts accelerometer carts
+                Event::MouseMotion { window_id, x, y, .. } if window_id == main_window_id => {
+                    let (width, height) = display.canvas.window().size();
+                    let tilt = |position: i32, size: u32| (position as f32 / size as f32) * 2.0 - 1.0;
+                    gbc.mem.set_accelerometer(tilt(x, width), tilt(y, height));
                 }
+                _ => {}
             }
-            
-            gbc.run_one_frame(&mut display);
-            framecount += 1;
-            // To reintroduce fps count use below
-            let elapsed = timer.elapsed().as_millis();
-            if elapsed > 1000 {
-                timer = Instant::now();
-                display.set_title(format!("Rusty GBC - {:.0} fps", framecount as f32 / (elapsed as f32 / 1000.0)));
-                framecount = 0;
+        }
+        
+        gbc.run_until_frame();
+        match gbc.render_sgb() {
+            Some(pixels) => display.render_pixels(&pixels, SGB_WIDTH),
+            None => display.render_frame(gbc.frame())
+        }
+        limiter.wait();
+        let rumble = gbc.mem.take_rumble();
+        if let Some(haptic) = haptic.as_mut() {
+            if rumble > 0.0 {
+                // Long enough to last until the next frame updates it
+                haptic.rumble_play(rumble, 100);
+                rumbling = true;
+            } else if rumbling {
+                haptic.rumble_stop();
+                rumbling = false;
+            }
+        }
+        for debug_window in debug_windows.iter_mut() {
+            debug_window.update(&gbc.mem.gpu)?;
+        }
+        framecount += 1;
+        let elapsed = timer.elapsed().as_millis();
+        if elapsed > 1000 {
+            timer = Instant::now();
+            let title = match gbc.status() {
+                CpuStatus::Locked { opcode, address } => {
+                    format!("Rusty GBC - locked up by illegal opcode ${:02X} at ${:04X}", opcode, address)
+                },
+                _ => format!("Rusty GBC - {:.0} fps", framecount as f32 / (elapsed as f32 / 1000.0))
+            };
+            display.set_title(title);
+            framecount = 0;
+        }
+        frames_run += 1;
+        if options.frames.map_or(false, |frames| frames_run >= frames) {
+            break 'main
+        }
+    }
+    Ok(())
+}
+
+/// Runs a bound action when its key or button is pressed or released, returning false to quit
+fn perform(action: Action, pressed: bool, source: InputSource, player: usize, gbc: &mut Cpu, debug_windows: &mut [DebugWindow]) -> bool {
+    match action {
+        Action::Joypad(key) if pressed => gbc.mem.input.press(source, player, key),
+        Action::Joypad(key) => gbc.mem.input.release(source, player, key),
+        // Hotkeys act on press only
+        _ if !pressed => {},
+        Action::Quit => return false,
+        Action::ToggleLog => gbc.log = !gbc.log,
+        Action::ToggleLayer(layer) => toggle_layer(&mut gbc.mem.gpu, layer),
+        Action::HighlightLayers => {
+            let highlight = gbc.mem.gpu.highlight_layers();
+            gbc.mem.gpu.set_highlight_layers(!highlight);
+        },
+        Action::DebugWindow(index) => debug_windows[index].toggle()
+    }
+    true
+}
+
+fn toggle_layer(gpu: &mut Gpu, layer: Layer) {
+    let enabled = gpu.layer_enabled(layer);
+    gpu.set_layer_enabled(layer, !enabled);
+}
+
+/// Sleeps between frames to run at a multiple of the Game Boy frame rate
+struct FrameLimiter {
+    /// `None` when unthrottled
+    frame_time: Option<Duration>,
+    next_frame: Instant
+}
+
+impl FrameLimiter {
+    /// A `speed` of 0 does not throttle at all
+    fn new(speed: f32) -> Self {
+        FrameLimiter {
+            frame_time: if speed > 0.0 { Some(Duration::from_secs_f64(1.0 / (FRAME_RATE * speed as f64))) } else { None },
+            next_frame: Instant::now()
+        }
+    }
+
+    fn wait(&mut self) {
+        if let Some(frame_time) = self.frame_time {
+            self.next_frame += frame_time;
+            let now = Instant::now();
+            if self.next_frame > now {
+                std::thread::sleep(self.next_frame - now);
+            } else {
+                // Fell behind, catching up would only run too fast afterwards
+                self.next_frame = now;
             }
         }
-        Ok(())
-    } else {
-        panic!("No cartridge found");
     }
 }
@@ -0,0 +1,198 @@
+use super::config::CONFIG_FILE;
+use super::rusty_gbc::gbc::Model;
+use super::rusty_gbc::gbc::palette::MonochromePalette;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: gbc_sdl2 [OPTIONS] <ROM>
+
+Options:
+  -s, --scale <N>        Window scale, 1 to 10 (default 3)
+  -f, --fullscreen       Start in fullscreen
+  -m, --model <MODEL>    Emulate dmg, mgb, sgb, cgb or agb (default: cgb for color cartridges, dmg otherwise)
+  -b, --boot-rom <FILE>  Run a boot ROM dump instead of the built-in boot ROM
+      --skip-boot        Start at the cartridge entry point without running a boot ROM
+      --save-dir <DIR>   Where battery saves are kept (default: next to the ROM)
+  -p, --palette <NAME>   Shades of monochrome games: default, green, pocket, cgb or grey
+      --speed <X>        Emulation speed multiplier, 0 for unthrottled (default: 1, unthrottled when headless)
+  -d, --debug            Enable the debugger
+      --break <ADDR>     Stop in the debugger at a hex address, can be repeated, implies --debug
+      --log-file <FILE>  Start with the CPU trace enabled, writing it to FILE instead of stdout
+      --printer <DIR>    Connect a Game Boy Printer, saving printed pages to DIR as PNG images
+      --headless         Run without a window, printing what test ROMs send over the serial port to stdout
+      --frames <N>       Exit after N frames
+      --config <FILE>    Key and controller bindings (default: rusty_gbc.cfg)
+  -h, --help             Print this help";
+
+pub struct Options {
+    pub rom: PathBuf,
+    pub scale: u32,
+    pub fullscreen: bool,
+    /// Overrides the model detected from the cartridge header
+    pub model: Option<Model>,
+    pub boot_rom: Option<PathBuf>,
+    pub skip_boot: bool,
+    pub save_dir: Option<PathBuf>,
+    pub palette: Option<MonochromePalette>,
+    /// Multiple of the Game Boy frame rate, `None` to follow the display refresh
+    pub speed: Option<f32>,
+    /// Breakpoints as four hex digits, `Some` when the debugger is enabled
+    pub breakpoints: Option<Vec<String>>,
+    pub log_file: Option<PathBuf>,
+    /// Where printed pages go, the link port prints to stdout without a printer
+    pub printer: Option<PathBuf>,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub config: PathBuf
+}
+
+impl Options {
+    /// Parses the arguments following the program name, `None` when help was asked for
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+        let mut rom = None;
+        let mut options = Options {
+            rom: PathBuf::new(),
+            scale: 3,
+            fullscreen: false,
+            model: None,
+            boot_rom: None,
+            skip_boot: false,
+            save_dir: None,
+            palette: None,
+            speed: None,
+            breakpoints: None,
+            log_file: None,
+            printer: None,
+            headless: false,
+            frames: None,
+            config: PathBuf::from(CONFIG_FILE)
+        };
+        while let Some(arg) = args.next() {
+            // Long options also take their value as --name=value
+            let (name, mut inline_value) = match arg.find('=') {
+                Some(index) if arg.starts_with("--") => (arg[.. index].to_string(), Some(arg[index + 1 ..].to_string())),
+                _ => (arg.clone(), None)
+            };
+            let takes_value = match name.as_str() {
+                "-s" | "--scale" | "-m" | "--model" | "-b" | "--boot-rom" | "--save-dir" | "-p" | "--palette" |
+                "--speed" | "--break" | "--log-file" | "--printer" | "--frames" | "--config" => true,
+                _ => false
+            };
+            let value = if takes_value {
+                match inline_value.take().or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => return Err(format!("{} needs a value", name))
+                }
+            } else {
+                String::new()
+            };
+            if inline_value.is_some() {
+                return Err(format!("{} does not take a value", name));
+            }
+            match name.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-s" | "--scale" => {
+                    options.scale = match value.parse() {
+                        Ok(scale) if (1 ..= 10).contains(&scale) => scale,
+                        _ => return Err(format!("scale must be a whole number from 1 to 10, found \"{}\"", value))
+                    };
+                },
+                "-f" | "--fullscreen" => options.fullscreen = true,
+                "-m" | "--model" => options.model = Some(parse_model(&value)?),
+                "-b" | "--boot-rom" => options.boot_rom = Some(PathBuf::from(value)),
+                "--skip-boot" => options.skip_boot = true,
+                "--save-dir" => options.save_dir = Some(PathBuf::from(value)),
+                "-p" | "--palette" => options.palette = Some(parse_palette(&value)?),
+                "--speed" => {
+                    options.speed = match value.parse::<f32>() {
+                        Ok(speed) if speed >= 0.0 && speed.is_finite() => Some(speed),
+                        _ => return Err(format!("speed must be a number of at least 0, found \"{}\"", value))
+                    };
+                },
+                "-d" | "--debug" => {
+                    options.breakpoints.get_or_insert_with(Vec::new);
+                },
+                "--break" => {
+                    let address = parse_address(&value)?;
+                    options.breakpoints.get_or_insert_with(Vec::new).push(format!("{:04X}", address));
+                },
+                "--log-file" => options.log_file = Some(PathBuf::from(value)),
+                "--printer" => options.printer = Some(PathBuf::from(value)),
+                "--headless" => options.headless = true,
+                "--frames" => {
+                    options.frames = Some(value.parse().map_err(|_| format!("frames must be a whole number, found \"{}\"", value))?);
+                },
+                "--config" => options.config = PathBuf::from(value),
+                _ if name.starts_with('-') && name.len() > 1 => return Err(format!("unknown option {}", name)),
+                _ if rom.is_some() => return Err(format!("unexpected argument \"{}\", only one ROM can be run", arg)),
+                _ => rom = Some(PathBuf::from(arg))
+            }
+        }
+        if options.boot_rom.is_some() && options.skip_boot {
+            return Err("--boot-rom and --skip-boot cannot be used together".to_string());
+        }
+        options.rom = rom.ok_or_else(|| "no ROM given".to_string())?;
+        Ok(Some(options))
+    }
+}
+
+fn parse_model(name: &str) -> Result<Model, String> {
+    match name.to_lowercase().as_str() {
+        "dmg" => Ok(Model::Dmg),
+        "mgb" => Ok(Model::Mgb),
+        "sgb" => Ok(Model::Sgb),
+        "cgb" => Ok(Model::Cgb),
+        "agb" => Ok(Model::Agb),
+        _ => Err(format!("model must be dmg, mgb, sgb, cgb or agb, found \"{}\"", name))
+    }
+}
+
+fn parse_palette(name: &str) -> Result<MonochromePalette, String> {
+    match name.to_lowercase().as_str() {
+        "default" => Ok(MonochromePalette::default()),
+        "green" => Ok(MonochromePalette::original_green()),
+        "pocket" => Ok(MonochromePalette::pocket_grey()),
+        "cgb" => Ok(MonochromePalette::cgb_compatibility()),
+        "grey" | "gray" => Ok(MonochromePalette::greyscale()),
+        _ => Err(format!("palette must be default, green, pocket, cgb or grey, found \"{}\"", name))
+    }
+}
+
+/// Accepts addresses like 0150, $150 or 0x150
+fn parse_address(value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches('$').trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("breakpoint must be a hex address from 0000 to FFFF, found \"{}\"", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse(&["-s", "4", "--model=sgb", "game.gb", "--break", "$150", "--break=C000", "--speed", "2",
+            "--printer", "prints"]).unwrap().unwrap();
+        assert_eq!(options.rom, PathBuf::from("game.gb"));
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.model, Some(Model::Sgb));
+        assert_eq!(options.breakpoints, Some(vec!["0150".to_string(), "C000".to_string()]));
+        assert_eq!(options.speed, Some(2.0));
+        assert_eq!(options.printer, Some(PathBuf::from("prints")));
+        assert!(!options.headless);
+        assert!(parse(&["game.gb", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert_eq!(parse(&[]).err().unwrap(), "no ROM given");
+        assert_eq!(parse(&["game.gb", "--scale"]).err().unwrap(), "--scale needs a value");
+        assert_eq!(parse(&["game.gb", "--scale", "0"]).err().unwrap(), "scale must be a whole number from 1 to 10, found \"0\"");
+        assert_eq!(parse(&["game.gb", "--headless=yes"]).err().unwrap(), "--headless does not take a value");
+        assert_eq!(parse(&["game.gb", "other.gb"]).err().unwrap(), "unexpected argument \"other.gb\", only one ROM can be run");
+        assert!(parse(&["game.gb", "--break", "10000"]).is_err());
+        assert!(parse(&["game.gb", "-b", "boot.bin", "--skip-boot"]).is_err());
+    }
+}
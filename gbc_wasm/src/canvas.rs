@@ -2,7 +2,7 @@ use stdweb::traits::*;
 use stdweb::unstable::TryInto;
 use stdweb::web::html_element::CanvasElement;
 use stdweb::web::{document, CanvasRenderingContext2d, ImageData};
-use rusty_gbc::{Display, Color, SCREEN_WIDTH, SCREEN_HEIGHT, BYTES_PER_PIXEL};
+use rusty_gbc::{Display, FrameBuffer, SCREEN_WIDTH, SCREEN_HEIGHT, BYTES_PER_PIXEL};
 use stdweb::{js, Array};
 
 const PIXEL_BUFFER_SIZE: usize =  SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize * BYTES_PER_PIXEL as usize;
@@ -46,22 +46,12 @@ impl Canvas {
 }
 
 impl Display for Canvas {
-    fn render_frame(&mut self) {
-        self.frame_count = if self.frame_count > 6 { 0 } else { self.frame_count + 1 };
-        self.ctx.put_image_data(self.image_data(&self.pixel_buffer), 0.0, 0.0).unwrap();
-    }
-
-    fn update_line_from_buffer(&mut self, buffer: [Color; SCREEN_WIDTH as usize], pixel_y: u8) {
+    fn render_frame(&mut self, frame: &FrameBuffer) {
         if self.frame_count == 0 {
-            for pixel_x in 0..SCREEN_WIDTH {
-                let color = buffer[pixel_x as usize];
-                let buf_idx = ((pixel_y as usize * SCREEN_WIDTH as usize) + (pixel_x as usize)) * BYTES_PER_PIXEL as usize;
-                self.pixel_buffer[buf_idx] = color.r;
-                self.pixel_buffer[buf_idx + 1] = color.g;
-                self.pixel_buffer[buf_idx + 2] = color.b;
-                self.pixel_buffer[buf_idx + 3] = 0xFF;
-            }
+            frame.write_rgba8888(&mut self.pixel_buffer, SCREEN_WIDTH as usize * BYTES_PER_PIXEL as usize);
         }
+        self.frame_count = if self.frame_count > 6 { 0 } else { self.frame_count + 1 };
+        self.ctx.put_image_data(self.image_data(&self.pixel_buffer), 0.0, 0.0).unwrap();
     }
 }
 
@@ -3,7 +3,7 @@ extern crate stdweb;
 mod canvas;
 
 use canvas::Canvas;
-use rusty_gbc::gbc::Cpu;
+use rusty_gbc::gbc::{Cpu, CpuStatus, Model};
 //use debugger::Debugger;
 use rusty_gbc::{Display};
 use rusty_gbc::gbc::gpu::Gpu;
@@ -32,9 +32,9 @@ fn run_rom(file_reader: FileReader) {
                 };
                 let canvas = Canvas::new("#canvas");
                 
-                let color_mode = bytes[0x143] & 0x80 == 0x80 || bytes[0x143] & 0xC0 == 0xC0;
-                let gpu = Gpu::new(color_mode).unwrap();
-                let gbc = Rc::new(RefCell::new(Cpu::new(bytes, gpu)));
+                let model = Model::detect(&bytes);
+                let gpu = Gpu::with_model(model, model.color_mode_for(&bytes, false)).unwrap();
+                let gbc = Rc::new(RefCell::new(Cpu::new(bytes, gpu).unwrap()));
 
                 stdweb::web::document().add_event_listener({
                     let gbc = gbc.clone();
@@ -81,8 +81,21 @@ fn run_rom(file_reader: FileReader) {
 fn game_loop(gbc: Rc<RefCell<Cpu>>, canvas: Rc<RefCell<dyn Display>>, time: u32) {
     stdweb::web::set_timeout(
         move || {
-            game_loop(gbc.clone(), canvas.clone(), time);
             gbc.borrow_mut().run_one_frame(&mut *canvas.borrow_mut());
+            let rumble = gbc.borrow_mut().mem.take_rumble();
+            if rumble > 0.0 {
+                // The Vibration API has no strength, so vibrate for the part of the frame the motor was on
+                let duration = (rumble * 17.0).ceil() as u32;
+                js! { if (navigator.vibrate) { navigator.vibrate(@{duration}); } }
+            }
+            let status = gbc.borrow().status();
+            if let CpuStatus::Locked { opcode, address } = status {
+                // Leave the last frame on screen instead of looping over a hung CPU
+                let message = format!("CPU locked up by illegal opcode ${:02X} at ${:04X}", opcode, address);
+                js! { console.error(@{message}); }
+                return;
+            }
+            game_loop(gbc.clone(), canvas.clone(), time);
         },
         time,
     );
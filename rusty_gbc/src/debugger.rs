@@ -5,6 +5,7 @@ use text_io::read;
 use std::collections::HashSet;
 use super::gbc::Mmu;
 use super::gbc::Registers;
+use super::gbc::opcodes::disassemble;
 
 pub struct Debugger {
     pub breakpoints: HashSet<u16>
@@ -28,7 +29,8 @@ impl Debugger {
             addr, regs.a, regs.b, regs.c, regs.d, regs.e, regs.f, regs.h, regs.l, regs.sp);
         println!("flags: Z: {}, H: {}, C: {}, N: {}", 
             regs.zero_flag(), regs.half_carry_flag(), regs.carry_flag(), regs.subtract_flag());
-        println!("op {:02X} {:02X} {:02X}", mem.read(regs.pc), mem.read(regs.pc+1), mem.read(regs.pc+2));
+        let bytes = [mem.peek(regs.pc), mem.peek(regs.pc.wrapping_add(1)), mem.peek(regs.pc.wrapping_add(2))];
+        println!("op {:02X} {:02X} {:02X}: {}", bytes[0], bytes[1], bytes[2], disassemble(&bytes));
         let line: String = read!("{}\n");
         for _part in line.split_whitespace() {
 
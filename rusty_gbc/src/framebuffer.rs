@@ -0,0 +1,156 @@
+use super::{Color, SCREEN_WIDTH, SCREEN_HEIGHT, BYTES_PER_PIXEL};
+
+const PIXEL_COUNT: usize = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize;
+
+/// Index bit set for pixels drawn from a sprite palette in color mode
+pub const SPRITE_PALETTE_INDEX: u8 = 0b00100000;
+
+/// A complete 160x144 frame as drawn by the `Gpu`
+pub struct FrameBuffer {
+    pixels: Vec<Color>,
+    indices: Vec<u8>
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        FrameBuffer {
+            pixels: vec![Default::default(); PIXEL_COUNT],
+            indices: vec![0; PIXEL_COUNT]
+        }
+    }
+
+    /// Pixels in row major order
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// Raw palette index of each pixel in row major order.
+    /// In DMG mode this is the shade (0-3) after BGP/OBP0/OBP1 mapping,
+    /// in color mode bits 0-1 are the color number, bits 2-4 the palette
+    /// number and `SPRITE_PALETTE_INDEX` is set for sprite palettes
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    pub fn pixel(&self, x: u8, y: u8) -> Color {
+        self.pixels[y as usize * SCREEN_WIDTH as usize + x as usize]
+    }
+
+    pub fn index(&self, x: u8, y: u8) -> u8 {
+        self.indices[y as usize * SCREEN_WIDTH as usize + x as usize]
+    }
+
+    pub(crate) fn set_pixel(&mut self, x: u8, y: u8, color: Color, index: u8) {
+        let idx = y as usize * SCREEN_WIDTH as usize + x as usize;
+        self.pixels[idx] = color;
+        self.indices[idx] = index;
+    }
+
+    pub(crate) fn clear(&mut self, color: Color) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = color;
+        }
+        for index in self.indices.iter_mut() {
+            *index = 0;
+        }
+    }
+
+    /// Writes the frame as RGBA8888 into `buffer` where each row starts `pitch` bytes after the last
+    pub fn write_rgba8888(&self, buffer: &mut [u8], pitch: usize) {
+        for (y, row) in self.pixels.chunks(SCREEN_WIDTH as usize).enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                let buf_idx = (y * pitch) + (x * BYTES_PER_PIXEL as usize);
+                buffer[buf_idx] = color.r;
+                buffer[buf_idx + 1] = color.g;
+                buffer[buf_idx + 2] = color.b;
+                buffer[buf_idx + 3] = 0xFF;
+            }
+        }
+    }
+
+    pub fn to_rgba8888(&self) -> Vec<u8> {
+        let pitch = SCREEN_WIDTH as usize * BYTES_PER_PIXEL as usize;
+        let mut buffer = vec![0; pitch * SCREEN_HEIGHT as usize];
+        self.write_rgba8888(&mut buffer, pitch);
+        buffer
+    }
+
+    pub fn to_rgb565(&self) -> Vec<u16> {
+        self.pixels.iter().map(|color| {
+            (color.r as u16 >> 3) << 11 | (color.g as u16 >> 2) << 5 | (color.b as u16 >> 3)
+        }).collect()
+    }
+}
+
+/// An arbitrarily sized image used by the VRAM, OAM and palette viewers
+pub struct DebugImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>
+}
+
+impl DebugImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        DebugImage {
+            width,
+            height,
+            pixels: vec![Default::default(); width * height]
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Fills a `width` by `height` rectangle with its top left corner at `x`, `y`
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        for rect_y in y .. y + height {
+            for rect_x in x .. x + width {
+                self.set_pixel(rect_x, rect_y, color);
+            }
+        }
+    }
+
+    pub fn to_rgba8888(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.pixels.len() * BYTES_PER_PIXEL as usize);
+        for color in self.pixels.iter() {
+            buffer.extend_from_slice(&[color.r, color.g, color.b, 0xFF]);
+        }
+        buffer
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        FrameBuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_formats() {
+        let mut frame = FrameBuffer::new();
+        frame.set_pixel(1, 0, Color::rgb(0xFF, 0x80, 0x08), 2);
+        frame.set_pixel(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1, Color::rgb(0xFF, 0xFF, 0xFF), 3);
+
+        let rgba = frame.to_rgba8888();
+        assert_eq!(&rgba[4..8], &[0xFF, 0x80, 0x08, 0xFF]);
+        assert_eq!(&rgba[rgba.len() - 4..], &[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let rgb565 = frame.to_rgb565();
+        assert_eq!(rgb565[1], 0b11111_100000_00001);
+        assert_eq!(rgb565[rgb565.len() - 1], 0xFFFF);
+
+        assert_eq!(frame.index(1, 0), 2);
+        assert_eq!(frame.indices()[0], 0);
+    }
+}
@@ -1,3 +1,80 @@
+use super::Registers;
+use super::model::Model;
+
+/// How the `Cpu` gets from power on to the cartridge entry point at 0x100
+pub enum BootMode {
+    /// Run the boot ROM embedded in rusty_gbc
+    BuiltIn,
+    /// Run a boot ROM dump, 256 bytes for DMG/MGB or 2304 bytes for CGB/AGB
+    External(Vec<u8>),
+    /// Start at 0x100 with registers, IO and the PPU as the boot ROM of the model leaves them
+    Skip
+}
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// Logo every cartridge header must contain at 0x104-0x133
+pub(crate) const NINTENDO_LOGO: [u8; 0x30] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E
+];
+
+/// The (R) symbol drawn after the logo by the DMG boot ROM
+pub(crate) const REGISTERED_TILE: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+/// Checksum of the header bytes 0x134-0x14C, compared by the boot ROM with the byte at 0x14D
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134 ..= 0x14C].iter().fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+/// Whether the boot ROM of `model` would start the cartridge or lock up. DMG and MGB
+/// compare the whole logo, CGB and AGB only its first half, all check the header checksum
+pub fn header_check(rom: &[u8], model: Model) -> bool {
+    if rom.len() < 0x150 {
+        return false;
+    }
+    let logo_len = if model.is_color() { 0x18 } else { 0x30 };
+    rom[0x104 .. 0x104 + logo_len] == NINTENDO_LOGO[.. logo_len] && header_checksum(rom) == rom[0x14D]
+}
+
+/// CPU registers when the boot ROM of `model` jumps to 0x100, `color_mode` is false for DMG cartridges on CGB/AGB
+pub(crate) fn post_boot_registers(model: Model, color_mode: bool, rom: &[u8]) -> Registers {
+    let mut regs = Registers::new();
+    match model {
+        Model::Dmg | Model::Mgb => {
+            regs.a = if model == Model::Mgb { 0xFF } else { 0x01 };
+            // Half carry and carry are left set unless the header checksum is 0
+            regs.f = if rom.get(0x14D).cloned().unwrap_or(0) == 0 { 0x80 } else { 0xB0 };
+            regs.set_bc(0x0013);
+            regs.set_de(0x00D8);
+            regs.set_hl(0x014D);
+        },
+        Model::Sgb => {
+            regs.a = 0x01;
+            regs.set_bc(0x0014);
+            regs.set_hl(0xC060);
+        },
+        Model::Cgb | Model::Agb => {
+            regs.a = 0x11;
+            regs.f = if model == Model::Agb { 0x00 } else { 0x80 };
+            regs.b = if model == Model::Agb { 0x01 } else { 0x00 };
+            if color_mode {
+                regs.set_de(0xFF56);
+                regs.set_hl(0x000D);
+            } else {
+                regs.set_de(0x0008);
+                regs.set_hl(0x007C);
+            }
+        }
+    }
+    regs.sp = 0xFFFE;
+    regs.pc = 0x100;
+    regs
+}
+
 pub fn load_rom () -> Vec<u8> {
     vec![
         // LD SP,$fffe		; $0000  Setup Stack
@@ -0,0 +1,194 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Resolution of the Pocket Camera sensor
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+const FRAME_SIZE: usize = CAMERA_WIDTH * CAMERA_HEIGHT;
+
+/// Supplies the pictures the Pocket Camera sensor sees
+pub trait ImageSource {
+    /// Fills `pixels` with a `CAMERA_WIDTH` by `CAMERA_HEIGHT` grayscale image in row major
+    /// order, 0 is black and 255 white
+    fn capture(&mut self, pixels: &mut [u8]);
+}
+
+/// A diagonal gradient with a bar that moves every capture, the default when no source is set
+pub struct TestPattern {
+    frame: usize
+}
+
+impl TestPattern {
+    pub fn new() -> Self {
+        TestPattern { frame: 0 }
+    }
+}
+
+impl Default for TestPattern {
+    fn default() -> Self {
+        TestPattern::new()
+    }
+}
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        let bar = self.frame % CAMERA_WIDTH;
+        for (index, pixel) in pixels.iter_mut().enumerate().take(FRAME_SIZE) {
+            let (x, y) = (index % CAMERA_WIDTH, index / CAMERA_WIDTH);
+            *pixel = if x / 8 == bar / 8 { 0 } else { ((x + y) * 255 / (CAMERA_WIDTH + CAMERA_HEIGHT - 2)) as u8 };
+        }
+        self.frame += 1;
+    }
+}
+
+/// The same picture on every capture
+pub struct StaticImage {
+    pixels: Vec<u8>
+}
+
+impl StaticImage {
+    /// Scales a grayscale image of any size to the sensor resolution
+    pub fn new(width: usize, height: usize, luminance: &[u8]) -> Result<Self, String> {
+        if width == 0 || height == 0 || luminance.len() < width * height {
+            return Err(format!("Expected {} bytes for a {}x{} image, got {}", width * height, width, height, luminance.len()));
+        }
+        let pixels = (0 .. FRAME_SIZE).map(|index| {
+            let x = index % CAMERA_WIDTH * width / CAMERA_WIDTH;
+            let y = index / CAMERA_WIDTH * height / CAMERA_HEIGHT;
+            luminance[y * width + x]
+        }).collect();
+        Ok(StaticImage { pixels })
+    }
+
+    /// Loads a binary PGM (P5) or PPM (P6) image with 8 bit samples
+    pub fn from_pnm(bytes: &[u8]) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        let mut position = 0;
+        // Magic number, width, height and maximum value separated by whitespace and comments
+        while fields.len() < 4 {
+            while position < bytes.len() && (bytes[position].is_ascii_whitespace() || bytes[position] == b'#') {
+                if bytes[position] == b'#' {
+                    while position < bytes.len() && bytes[position] != b'\n' {
+                        position += 1;
+                    }
+                } else {
+                    position += 1;
+                }
+            }
+            let start = position;
+            while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if start == position {
+                return Err("Truncated PNM header".to_string());
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start .. position]).into_owned());
+        }
+        // A single whitespace character separates the header from the samples
+        let data = &bytes[(position + 1).min(bytes.len()) ..];
+        let number = |field: &str| field.parse::<usize>().map_err(|_| format!("Invalid PNM header value {}", field));
+        let (width, height, max) = (number(&fields[1])?, number(&fields[2])?, number(&fields[3])?);
+        if max == 0 || max > 255 {
+            return Err(format!("Unsupported PNM maximum value {}", max));
+        }
+        let scale = |sample: u8| (sample as usize * 255 / max) as u8;
+        let luminance: Vec<u8> = match fields[0].as_str() {
+            "P5" => data.iter().take(width * height).map(|sample| scale(*sample)).collect(),
+            "P6" => data.chunks(3).take(width * height).filter(|rgb| rgb.len() == 3).map(|rgb| {
+                scale(((rgb[0] as usize * 299 + rgb[1] as usize * 587 + rgb[2] as usize * 114) / 1000) as u8)
+            }).collect(),
+            magic => return Err(format!("Unsupported PNM format {}, expected P5 or P6", magic))
+        };
+        StaticImage::new(width, height, &luminance)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let bytes = std::fs::read(path.as_ref()).map_err(|e| format!("Could not read {}: {}", path.as_ref().display(), e))?;
+        StaticImage::from_pnm(&bytes)
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        pixels[.. FRAME_SIZE].copy_from_slice(&self.pixels);
+    }
+}
+
+/// Consecutive frames of raw 8 bit grayscale video at the sensor resolution, looping at the end.
+/// Any video can be converted with
+/// `ffmpeg -i input.mp4 -vf scale=128:112 -pix_fmt gray -f rawvideo frames.raw`
+pub struct VideoFrames {
+    file: File,
+    frame_count: u64,
+    frame: u64
+}
+
+impl VideoFrames {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+        let len = file.metadata().map_err(|e| e.to_string())?.len();
+        let frame_count = len / FRAME_SIZE as u64;
+        if frame_count == 0 {
+            return Err(format!("{} is smaller than one {}x{} frame", path.display(), CAMERA_WIDTH, CAMERA_HEIGHT));
+        }
+        Ok(VideoFrames { file, frame_count, frame: 0 })
+    }
+}
+
+impl ImageSource for VideoFrames {
+    fn capture(&mut self, pixels: &mut [u8]) {
+        let offset = self.frame * FRAME_SIZE as u64;
+        self.frame = (self.frame + 1) % self.frame_count;
+        let read = self.file.seek(SeekFrom::Start(offset)).and_then(|_| self.file.read_exact(&mut pixels[.. FRAME_SIZE]));
+        if read.is_err() {
+            // The file changed underneath us, show black rather than stopping the game
+            for pixel in pixels.iter_mut() {
+                *pixel = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pgm_is_scaled_to_sensor() {
+        let mut pgm = b"P5\n# two columns\n2 1\n255\n".to_vec();
+        pgm.extend_from_slice(&[0x10, 0xF0]);
+        let mut image = StaticImage::from_pnm(&pgm).unwrap();
+        let mut pixels = vec![0; FRAME_SIZE];
+        image.capture(&mut pixels);
+        assert_eq!(pixels[0], 0x10);
+        assert_eq!(pixels[CAMERA_WIDTH - 1], 0xF0);
+        assert_eq!(pixels[FRAME_SIZE - 1], 0xF0);
+    }
+
+    #[test]
+    fn test_ppm_converts_to_gray() {
+        let mut ppm = b"P6 1 1 255 ".to_vec();
+        ppm.extend_from_slice(&[255, 255, 255]);
+        let mut pixels = vec![0; FRAME_SIZE];
+        StaticImage::from_pnm(&ppm).unwrap().capture(&mut pixels);
+        assert_eq!(pixels[0], 255);
+        assert!(StaticImage::from_pnm(b"P3 1 1 255 0 0 0").is_err());
+    }
+
+    #[test]
+    fn test_video_frames_loop() {
+        let path = std::env::temp_dir().join("rusty_gbc_camera_frames.raw");
+        let mut video = vec![0x11; FRAME_SIZE];
+        video.extend(vec![0x22; FRAME_SIZE]);
+        std::fs::write(&path, video).unwrap();
+        let mut frames = VideoFrames::open(&path).unwrap();
+        let mut pixels = vec![0; FRAME_SIZE];
+        for expected in [0x11, 0x22, 0x11].iter() {
+            frames.capture(&mut pixels);
+            assert_eq!(pixels[0], *expected);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
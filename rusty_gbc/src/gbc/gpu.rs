@@ -1,6 +1,9 @@
-use crate::{Color, Display};
+use crate::{Color, FrameBuffer, DebugImage};
+use crate::framebuffer::SPRITE_PALETTE_INDEX;
 use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use super::{V_BLANK_INTERRUPT, STAT_INTERRUPT};
+use super::palette::{MonochromePalette, ColorCorrection};
+use super::model::Model;
 
 const SPRITE_OBJ_TO_BG_PRIORITY: u8 = 0b10000000; // (0=OBJ Above BG, 1=OBJ Behind BG color 1-3) //(Used for both BG and Window. BG color 0 is always behind OBJ)
 const SPRITE_Y_FLIP: u8 = 0b01000000; // (0=Normal, 1=Vertically mirrored)
@@ -9,17 +12,15 @@ const SPRITE_PALETTE_NUM: u8 = 0b00010000; // **Non CGB Mode Only** (0=OBP0, 1=O
 
 const WINDOW_X_SHIFT: u8 = 7;
 
+const CYCLES_PER_LINE: usize = 456;
+const CYCLES_PER_FRAME: usize = 70_224;
+const LAST_LINE: u8 = 153;
+
 const H_BLANK_MODE: u8 = 0;
 const V_BLANK_MODE: u8 = 1;
 const OAM_SEARCH_MODE: u8 = 2;
 const LCD_TRANSFER_MODE: u8 = 3;
 
-const WHITE: Color = Color::rgb(0xE6, 0xFF, 0xE6);
-const DARK_GRAY: Color = Color::rgb(0x40, 0x80, 0x00);
-const LIGHT_GRAY: Color = Color::rgb(0x70, 0xDB, 0x70);
-const BLACK: Color = Color::rgb(0x00, 0x00, 0x00);
-const RED: Color = Color::rgb(0xFF, 0x00, 0x00);
-
 pub struct Gpu {
     vram: [[u8; 0x8000]; 2],
     vram_bank_1_selected: bool,
@@ -56,6 +57,7 @@ pub struct Gpu {
     bgp: u8,
     obp0: u8,
     obp1: u8,
+    model: Model,
     pub color_mode: bool,
     color_bg_palette_index: u8,
     color_bg_palette_auto_increment: bool,
@@ -63,18 +65,59 @@ pub struct Gpu {
     color_obj_palette_index: u8,
     color_obj_palette_auto_increment: bool,
     color_obj_palettes: [u8; 0x40],
+    monochrome_palette: MonochromePalette,
+    color_correction: ColorCorrection,
+    /// RGB value of every 15 bit CGB color under the current color correction
+    color_lut: Vec<Color>,
     cycle_count: usize,
     pub interrupts: u8,
+    frame: FrameBuffer,
+    frame_ready: bool,
+    /// The LCD was just enabled and line 0 skips OAM search
+    first_line_after_enable: bool,
+    /// The LCD was just enabled and the current frame is output blank
+    skip_frame: bool,
+    debug_layers: DebugLayers,
+    /// Block CPU access to VRAM and OAM while the PPU is using them
+    access_locking: bool,
     updated: bool
 }
 
 type Tile = [[u8; 8]; 8];
 
-struct Sprite {
-    y: u8,
-    x: u8,
-    tile_number: u8,
-    flags: u8
+/// The layer a pixel was drawn from
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites
+}
+
+/// Debug switches for hiding layers or tinting each pixel by the layer that drew it
+struct DebugLayers {
+    background: bool,
+    window: bool,
+    sprites: bool,
+    highlight: bool
+}
+
+/// A single OAM entry, positions are as stored (y + 16, x + 8)
+#[derive(Copy, Clone)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile_number: u8,
+    pub flags: u8
+}
+
+impl Sprite {
+    pub fn behind_bg(&self) -> bool { self.flags & SPRITE_OBJ_TO_BG_PRIORITY > 0 }
+    pub fn y_flip(&self) -> bool { self.flags & SPRITE_Y_FLIP > 0 }
+    pub fn x_flip(&self) -> bool { self.flags & SPRITE_X_FLIP > 0 }
+    /// OBP0 or OBP1 in DMG mode
+    pub fn dmg_palette(&self) -> u8 { if self.flags & SPRITE_PALETTE_NUM > 0 { 1 } else { 0 } }
+    pub fn vram_bank(&self) -> u8 { (self.flags & 0b1000) >> 3 }
+    pub fn cgb_palette(&self) -> u8 { self.flags & 0b111 }
 }
 
 fn empty_tile() -> Tile {
@@ -82,7 +125,21 @@ fn empty_tile() -> Tile {
 }
 
 impl Gpu {
+    /// Creates a GPU for a DMG, or a CGB if `color_mode` is set
     pub fn new(color_mode: bool) -> Result<Box<Gpu>, String> {
+        Gpu::with_model(if color_mode { Model::Cgb } else { Model::Dmg }, color_mode)
+    }
+
+    /// Creates a GPU for `model`, `color_mode` is false on color models running DMG cartridges
+    pub fn with_model(model: Model, color_mode: bool) -> Result<Box<Gpu>, String> {
+        if color_mode && !model.is_color() {
+            return Err(format!("{:?} has no color mode", model));
+        }
+        let monochrome_palette = match model {
+            Model::Mgb => MonochromePalette::pocket_grey(),
+            Model::Cgb | Model::Agb => MonochromePalette::cgb_compatibility(),
+            Model::Dmg | Model::Sgb => MonochromePalette::default()
+        };
         Ok(Box::new(Gpu {
             oam: [0; 0xA0],
             vram: [[0; 0x8000]; 2],
@@ -116,6 +173,7 @@ impl Gpu {
             bgp: 0,
             obp0: 0,
             obp1: 0,
+            model,
             color_mode,
             color_bg_palette_index: 0,
             color_bg_palette_auto_increment: false,
@@ -123,40 +181,61 @@ impl Gpu {
             color_obj_palette_index: 0,
             color_obj_palette_auto_increment: false,
             color_obj_palettes: [0; 0x40],
+            monochrome_palette,
+            color_correction: ColorCorrection::default(),
+            color_lut: ColorCorrection::default().lookup_table(),
             cycle_count: 0,
             interrupts: 0,
+            frame: FrameBuffer::new(),
+            frame_ready: false,
+            first_line_after_enable: false,
+            skip_frame: false,
+            debug_layers: DebugLayers {
+                background: true,
+                window: true,
+                sprites: true,
+                highlight: false
+            },
+            access_locking: true,
             updated: true
         }))
     }
 
-    pub fn gpu_step(&mut self, display: &mut dyn Display, cycles: u8) {
+    pub fn gpu_step(&mut self, cycles: u8) {
+        self.cycle_count += cycles as usize;
         if !self.lcd_enable {
+            // Nothing is drawn while the LCD is off but frames are still presented at the normal rate
+            if self.cycle_count >= CYCLES_PER_FRAME {
+                self.cycle_count -= CYCLES_PER_FRAME;
+                self.blank_frame();
+            }
             return;
         }
-        self.cycle_count += cycles as usize;
+
+        if self.cycle_count >= CYCLES_PER_LINE {
+            self.cycle_count -= CYCLES_PER_LINE;
+            self.first_line_after_enable = false;
+            self.ly = if self.ly == LAST_LINE { 0 } else { self.ly + 1 };
+            self.update_coincidence();
+        }
 
         if self.ly < SCREEN_HEIGHT {
             match self.cycle_count {
-                0..=80 => {
+                0 ..= 79 => {
                     /* OAM SEARCH */
-                    if self.get_lcdc_mode() != OAM_SEARCH_MODE {
-                        self.get_sprites_for_current_scanline();
+                    // The first line after the LCD is enabled starts in H-Blank rather than OAM search
+                    if self.get_lcdc_mode() != OAM_SEARCH_MODE && !self.first_line_after_enable {
                         self.set_lcdc_mode(OAM_SEARCH_MODE);
                         if self.oam_interrupt_enabled {
                             self.interrupts |= STAT_INTERRUPT;
                         }
-
-                        if self.coincidence_interrupt_enabled && self.lyc == self.ly  {
-                            self.coincidence_flag = true;
-                            self.interrupts |= STAT_INTERRUPT;
-                            // TODO set all STAT interrupts in same place and verify that is correct place
-                        }
                     }
                 },
-                80..=252 => {
+                80 ..= 251 => {
                     /* SCANLINE*/ 
                     if self.get_lcdc_mode() != LCD_TRANSFER_MODE {
                         self.set_lcdc_mode(LCD_TRANSFER_MODE);
+                        self.get_sprites_for_current_scanline();
                         if self.updated {
                             if self.window_enable && self.ly == self.wy && self.window_internal_line_counter.is_none() {
                                 self.window_internal_line_counter = Some(0);
@@ -164,11 +243,11 @@ impl Gpu {
                                 self.window_internal_line_counter = Some(self.window_internal_line_counter.unwrap() + 1);
                             }
 
-                            self.draw_scanline(display);
+                            self.draw_scanline();
                         }
                     }
                 },
-                252..=456 => {
+                _ => {
                     /* H-Blank*/ 
                     if self.get_lcdc_mode() != H_BLANK_MODE {
                         self.set_lcdc_mode(H_BLANK_MODE);
@@ -176,36 +255,63 @@ impl Gpu {
                             self.interrupts |= STAT_INTERRUPT;
                         }
                     }
-
-                },
-                _ => {
-                    self.ly += 1;
-                    self.cycle_count = 0;
                 }
             };
-        } else {
-            if self.get_lcdc_mode() != V_BLANK_MODE {
-                self.set_lcdc_mode(V_BLANK_MODE);
-                self.interrupts |= V_BLANK_INTERRUPT;
-                
-                self.window_internal_line_counter = None;
-
-                if self.updated {
-                    display.render_frame();
-                }
-                
-                self.updated = false;
-            }
-            if self.cycle_count > 456 {
-                self.ly += 1;
-                self.cycle_count = 0;
+        } else if self.get_lcdc_mode() != V_BLANK_MODE {
+            self.set_lcdc_mode(V_BLANK_MODE);
+            self.interrupts |= V_BLANK_INTERRUPT;
+            if self.v_blank_interrupt_enabled {
+                self.interrupts |= STAT_INTERRUPT;
             }
-            if self.ly > SCREEN_HEIGHT + 10 {
-                self.ly = 0;
+
+            self.window_internal_line_counter = None;
+            if self.skip_frame {
+                // The frame started by enabling the LCD is never shown
+                self.skip_frame = false;
+                self.blank_frame();
+            } else {
+                self.frame_ready = true;
             }
+            self.updated = false;
         }
     }
 
+    /// Presents a frame filled with the color shown when the LCD is off
+    fn blank_frame(&mut self) {
+        let white = if self.color_mode { Color::rgb(0xFF, 0xFF, 0xFF) } else { self.monochrome_palette.bg[0] };
+        self.frame.clear(white);
+        self.frame_ready = true;
+    }
+
+    fn update_coincidence(&mut self) {
+        self.coincidence_flag = self.ly == self.lyc;
+        if self.coincidence_flag && self.coincidence_interrupt_enabled {
+            self.interrupts |= STAT_INTERRUPT;
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// The 4KB of tile data the background currently uses, which the SGB reads for VRAM transfers
+    pub(crate) fn background_tile_data(&self) -> &[u8] {
+        let start = if self.bg_window_tile_data { 0 } else { 0x800 };
+        &self.vram[0][start .. start + 0x1000]
+    }
+
+    /// The most recently drawn frame, complete once `take_frame_ready` returns true
+    pub fn frame(&self) -> &FrameBuffer {
+        &self.frame
+    }
+
+    /// Returns true once per frame when V-Blank is entered and the frame buffer is complete
+    pub fn take_frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
     fn updated(&mut self) {
         //println!("updated");
         self.updated = true;
@@ -213,10 +319,19 @@ impl Gpu {
 
     pub fn set_lcdc_control(&mut self, value: u8) {
         let bit = |flag: u8| value & 1 << flag > 0;
+        let was_enabled = self.lcd_enable;
         self.lcd_enable = bit(7);
-        if !self.lcd_enable {
+        if was_enabled != self.lcd_enable {
+            // LY and the mode stay at 0 while the LCD is off, on enabling it restarts from the top of line 0
             self.ly = 0;
+            self.cycle_count = 0;
             self.set_lcdc_mode(H_BLANK_MODE);
+            self.window_internal_line_counter = None;
+            self.first_line_after_enable = self.lcd_enable;
+            self.skip_frame = self.lcd_enable;
+            if self.lcd_enable {
+                self.update_coincidence();
+            }
         }
         self.window_tile_map = bit(6);
         self.window_enable = bit(5);
@@ -227,6 +342,18 @@ impl Gpu {
         self.bg_window_priority = bit(0);
         self.updated() 
     }
+    /// Leaves the PPU as the boot ROM hands it over, with the LCD on and no blank first frame
+    pub(crate) fn skip_boot(&mut self) {
+        self.set_lcdc_control(0x91);
+        self.first_line_after_enable = false;
+        self.skip_frame = false;
+        self.bgp = 0xFC;
+        if self.color_mode {
+            // Every background color is white
+            self.color_bg_palettes = [0xFF; 0x40];
+        }
+    }
+
     pub fn get_lcdc_control(&self) -> u8 {
         let mut lcdc = 0;
         let mut bit = |flag: u8, cond: bool| if cond { lcdc |= 1 << flag };
@@ -362,27 +489,28 @@ impl Gpu {
     }
 
 
-    fn get_color(& self, pixel_x: u8, pixel_y: u8) -> Color {
+    /// Returns the color of the pixel, its raw palette index and the layer it was drawn from
+    fn get_color(& self, pixel_x: u8, pixel_y: u8) -> (Color, u8, Layer) {
         let reverse_x_if = |condition: bool, x: u8| if condition { 7 - x } else { x };
         let reverse_y_if = |condition: bool, y: u8| if condition { 7 - y } else { y };
 
-        let (bg_or_win_color, bg_attributes) = // If the window is enabled and wx and wy are less than x and y draw window
-            if self.window_enable && pixel_x + WINDOW_X_SHIFT >= self.wx && self.window_internal_line_counter.is_some() { // pixel_y >= self.wy {
+        let (bg_or_win_color, bg_attributes, bg_layer) = // If the window is enabled and wx and wy are less than x and y draw window
+            if self.window_enable && self.debug_layers.window && pixel_x + WINDOW_X_SHIFT >= self.wx && self.window_internal_line_counter.is_some() { // pixel_y >= self.wy {
                 let (window_x, window_y) = ((pixel_x + WINDOW_X_SHIFT) - self.wx, self.window_internal_line_counter.unwrap());
                 let (tile, attributes) = self.get_tile_at(self.window_tile_map, window_x / 8, window_y / 8);
-                (tile[reverse_y_if(attributes & 0b01000000 > 0, window_y % 8) as usize][reverse_x_if(attributes & 0b00100000 > 0, window_x % 8) as usize], attributes)
+                (tile[reverse_y_if(attributes & 0b01000000 > 0, window_y % 8) as usize][reverse_x_if(attributes & 0b00100000 > 0, window_x % 8) as usize], attributes, Layer::Window)
             } else
             // TODO window priority works differently for CGB, on DMG works as enable bg
             // If the background is enabled draw the background
-            if self.color_mode || self.bg_window_priority {
+            if (self.color_mode || self.bg_window_priority) && self.debug_layers.background {
                 let (scrolled_x, scrolled_y) = (pixel_x.wrapping_add(self.scx), pixel_y.wrapping_add(self.scy));
                 let (tile, attributes) = self.get_tile_at(self.bg_tile_map_select, scrolled_x / 8, scrolled_y / 8);
-                (tile[reverse_y_if(attributes & 0b01000000 > 0, scrolled_y % 8) as usize][reverse_x_if(attributes & 0b00100000 > 0, scrolled_x % 8) as usize], attributes)
-            } else { (0, 0) };
+                (tile[reverse_y_if(attributes & 0b01000000 > 0, scrolled_y % 8) as usize][reverse_x_if(attributes & 0b00100000 > 0, scrolled_x % 8) as usize], attributes, Layer::Background)
+            } else { (0, 0, Layer::Background) };
 
         // Compare x to all 10 sprites, if any are visible draw that scanline of the sprite
         for sprite in self.sprites.iter().filter(|x| x.is_some()).map(|x| x.as_ref().unwrap()) {
-            if !self.sprite_enable {
+            if !self.sprite_enable || !self.debug_layers.sprites {
                 break
             }
             let mut sprite_x = (pixel_x).wrapping_sub(sprite.x).wrapping_add(8);
@@ -399,7 +527,7 @@ impl Gpu {
                         _ => panic!()
                     };
                     match self.get_sprite_color(&sprite, tile[(sprite_y % 8) as usize][(sprite_x % 8) as usize]) {
-                        Some(color) => return color,
+                        Some((color, index)) => return (color, index, Layer::Sprites),
                         None => {}
                     }
                 }
@@ -410,7 +538,7 @@ impl Gpu {
                     let tile = self.get_sprite_tile(tile_vram_bank, sprite.tile_number);
                     if sprite.flags & SPRITE_OBJ_TO_BG_PRIORITY == 0 || bg_or_win_color == 0  {
                         match self.get_sprite_color(&sprite, tile[sprite_y as usize][sprite_x as usize]) {
-                            Some(color) => return color,
+                            Some((color, index)) => return (color, index, Layer::Sprites),
                             None => {}
                         }                
                     }
@@ -419,19 +547,47 @@ impl Gpu {
             }
         }
         
-        self.get_bg_color(bg_or_win_color, bg_attributes & 0b00000111)
+        let (color, index) = self.get_bg_color(bg_or_win_color, bg_attributes & 0b00000111);
+        (color, index, bg_layer)
     }
 
-    fn draw_scanline(&mut self, display: &mut dyn Display) {
+    fn draw_scanline(&mut self) {
         let pixel_y = self.ly;
-        let mut buffer: [Color; SCREEN_WIDTH as usize] = [Default::default(); SCREEN_WIDTH as usize];
         for pixel_x in 0 .. SCREEN_WIDTH {
-            let color = self.get_color(pixel_x, pixel_y);
-            buffer[pixel_x as usize] = color; 
+            let (mut color, index, layer) = self.get_color(pixel_x, pixel_y);
+            if self.debug_layers.highlight {
+                color = highlight_layer(color, layer);
+            }
+            self.frame.set_pixel(pixel_x, pixel_y, color, index);
+        }
+    }
+
+    /// Shows or hides a layer for debugging, independent of LCDC
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        match layer {
+            Layer::Background => self.debug_layers.background = enabled,
+            Layer::Window => self.debug_layers.window = enabled,
+            Layer::Sprites => self.debug_layers.sprites = enabled
+        };
+        self.updated()
+    }
+
+    pub fn layer_enabled(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Background => self.debug_layers.background,
+            Layer::Window => self.debug_layers.window,
+            Layer::Sprites => self.debug_layers.sprites
         }
-        display.update_line_from_buffer(buffer, pixel_y);
     }
 
+    /// Tints every pixel red, green or blue for the background, window or sprites respectively
+    pub fn set_highlight_layers(&mut self, highlight: bool) {
+        self.debug_layers.highlight = highlight;
+        self.updated()
+    }
+
+    pub fn highlight_layers(&self) -> bool { self.debug_layers.highlight }
+
     fn get_tile_at(&self, tilemap: bool, x: u8, y: u8) -> (Tile, u8) {
         let address = y as u16 * 32 + x as u16;
         let (tile_id, tile_attributes) = if tilemap {
@@ -485,11 +641,16 @@ impl Gpu {
     }
 
     pub fn write_to_vram(&mut self, address: u16, value: u8) {
+        if self.vram_locked() {
+            // cannot access VRAM during LCD Transfer
+            return;
+        }
+        self.dma_write_vram(address, value);
+    }
+
+    /// Writes to VRAM ignoring mode 3 locking, used by HDMA
+    pub fn dma_write_vram(&mut self, address: u16, value: u8) {
         self.updated(); 
-        // if self.lcdc_status & 0b00000011 == LCD_TRANSFER_MODE {
-        //     // cannot access VRAM during LCD Transfer
-        //     return;
-        // }
         let vram_bank = if self.vram_bank_1_selected { 1 } else { 0 };
 
         self.vram[vram_bank][address as usize] = value;
@@ -501,8 +662,8 @@ impl Gpu {
         let index: usize = (address & 0xFFFE) as usize;
         let byte1 = self.vram[vram_bank][index];
         let byte2 = self.vram[vram_bank][index + 1];
-        let tile: usize = (index / 16) as usize;
-        let row: usize = ((index % 16) / 2) as usize;
+        let tile: usize = index / 16;
+        let row: usize = (index % 16) / 2;
 
         for pixel in 0..8 {
             let mask = 1 << (7 - pixel);
@@ -511,58 +672,61 @@ impl Gpu {
         }
     }
 
-    fn get_sprite_color(&self, sprite: &Sprite, value: u8) -> Option<Color> {
+    fn get_sprite_color(&self, sprite: &Sprite, value: u8) -> Option<(Color, u8)> {
         if value == 0 {
             return None;
         }
 
         if self.color_mode {
-            let to_8_bit_color = |c: u8| (c << 3) | (c >> 2);
             let palette_num = sprite.flags & 0b00000111;
-            let b0: u8 = self.color_obj_palettes[((palette_num * 8) + value * 2) as usize];
-            let b1: u8 = self.color_obj_palettes[((palette_num * 8) + value * 2) as usize + 1];
-            let color_bytes: u16 = u16::from_le_bytes([b0, b1]);
-            Some(Color::rgb(
-                to_8_bit_color((color_bytes & 0b11111) as u8),
-                to_8_bit_color(((color_bytes & 0b1111100000) >> 5) as u8),
-                to_8_bit_color(((color_bytes & 0b111110000000000) >> 10) as u8)
-            ))
+            Some((self.get_cgb_color(&self.color_obj_palettes, palette_num, value), SPRITE_PALETTE_INDEX | palette_num << 2 | value))
         } else {
-            let palette = if sprite.flags & SPRITE_PALETTE_NUM > 0 { self.obp1 } else { self.obp0 };
-            match (palette >> (2 * value)) & 0b11 {
-                0 => Some(WHITE),
-                1 => Some(LIGHT_GRAY),
-                2 => Some(DARK_GRAY),
-                3 => Some(BLACK),
-                _ => Some(RED)
-            }
+            let (palette, shades) = if sprite.flags & SPRITE_PALETTE_NUM > 0 {
+                (self.obp1, &self.monochrome_palette.obj1)
+            } else {
+                (self.obp0, &self.monochrome_palette.obj0)
+            };
+            let shade = (palette >> (2 * value)) & 0b11;
+            Some((shades[shade as usize], shade))
         }
     }
 
-    fn get_bg_color(&self, value: u8, palette_num: u8) -> Color {
+    fn get_bg_color(&self, value: u8, palette_num: u8) -> (Color, u8) {
         if self.color_mode {
-            let to_8_bit_color = |c: u8| (c << 3) | (c >> 2);
-            let b0: u8 = self.color_bg_palettes[((palette_num * 8) + value * 2) as usize];
-            let b1: u8 = self.color_bg_palettes[((palette_num * 8) + value * 2) as usize + 1];
-            let color_bytes: u16 = u16::from_le_bytes([b0, b1]);
-            Color::rgb(
-                to_8_bit_color((color_bytes & 0b11111) as u8),
-                to_8_bit_color(((color_bytes & 0b1111100000) >> 5) as u8),
-                to_8_bit_color(((color_bytes & 0b111110000000000) >> 10) as u8)
-            )
+            (self.get_cgb_color(&self.color_bg_palettes, palette_num, value), palette_num << 2 | value)
         } else {
-            match (self.bgp >> (2 * value)) & 0b11 {
-                0 => WHITE,
-                1 => LIGHT_GRAY,
-                2 => DARK_GRAY,
-                3 => BLACK,
-                _ => RED
-            }
+            let shade = (self.bgp >> (2 * value)) & 0b11;
+            (self.monochrome_palette.bg[shade as usize], shade)
         }
     }
 
+    fn get_cgb_color(&self, palettes: &[u8; 0x40], palette_num: u8, value: u8) -> Color {
+        let b0: u8 = palettes[((palette_num * 8) + value * 2) as usize];
+        let b1: u8 = palettes[((palette_num * 8) + value * 2) as usize + 1];
+        self.color_lut[(u16::from_le_bytes([b0, b1]) & 0x7FFF) as usize]
+    }
+
+    /// Sets the shades used for the background and sprites when not in color mode
+    pub fn set_monochrome_palette(&mut self, palette: MonochromePalette) {
+        self.monochrome_palette = palette;
+        self.updated()
+    }
+
+    pub fn get_monochrome_palette(&self) -> MonochromePalette { self.monochrome_palette }
+
+    /// Sets the curve used to convert CGB palette RAM colors to RGB
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        if correction != self.color_correction {
+            self.color_correction = correction;
+            self.color_lut = correction.lookup_table();
+        }
+        self.updated()
+    }
+
+    pub fn get_color_correction(&self) -> ColorCorrection { self.color_correction }
+
     pub fn read_from_vram(&self, address: u16) -> u8 {
-        if self.get_lcdc_mode() == LCD_TRANSFER_MODE {
+        if self.vram_locked() {
             // cannot access VRAM during LCD Transfer
             return 0xFF;
         }
@@ -571,23 +735,237 @@ impl Gpu {
     }
 
     pub fn write_to_oam(&mut self, address: u16, value: u8) {
-        self.updated();
-        if false /*self.lcdc_status & 0b00000011 == OAM_SEARCH_MODE
-            ||  self.lcdc_status & 0b00000011 == LCD_TRANSFER_MODE */ {
+        if self.oam_locked() {
             // cannot access OAM during OAM search or LCD Transfer
             return;
-        } else {
-            self.oam[address as usize] = value;
         }
+        self.dma_write_oam(address, value);
+    }
+
+    /// Writes to OAM ignoring mode 2 and 3 locking, used by OAM DMA
+    pub fn dma_write_oam(&mut self, address: u16, value: u8) {
+        self.updated();
+        self.oam[address as usize] = value;
     }
 
     pub fn read_from_oam(&self, address: u16) -> u8 {
-        if false /*self.lcdc_status & 0b00000011 == OAM_SEARCH_MODE
-            ||  self.lcdc_status & 0b00000011 == LCD_TRANSFER_MODE*/ {
+        if self.oam_locked() {
             // cannot access OAM during OAM search or LCD Transfer
             0xFF
         } else {
             self.oam[address as usize]
         }
     }
-}
\ No newline at end of file
+
+    /// Enables or disables blocking the CPU from VRAM during mode 3 and OAM during modes 2 and 3.
+    /// Disabling is inaccurate but some homebrew relies on it due to its own timing bugs
+    pub fn set_access_locking(&mut self, enabled: bool) {
+        self.access_locking = enabled;
+    }
+
+    pub fn access_locking(&self) -> bool { self.access_locking }
+
+    fn vram_locked(&self) -> bool {
+        self.access_locking && self.get_lcdc_mode() == LCD_TRANSFER_MODE
+    }
+
+    fn oam_locked(&self) -> bool {
+        self.access_locking && (self.get_lcdc_mode() == OAM_SEARCH_MODE || self.get_lcdc_mode() == LCD_TRANSFER_MODE)
+    }
+}
+
+/// Blends half of the layer's tint into the color, keeping the original shading visible
+fn highlight_layer(color: Color, layer: Layer) -> Color {
+    let tint = match layer {
+        Layer::Background => Color::rgb(0xFF, 0x00, 0x00),
+        Layer::Window => Color::rgb(0x00, 0xFF, 0x00),
+        Layer::Sprites => Color::rgb(0x00, 0x00, 0xFF)
+    };
+    let blend = |c: u8, t: u8| ((c as u16 + t as u16) / 2) as u8;
+    Color::rgb(blend(color.r, tint.r), blend(color.g, tint.g), blend(color.b, tint.b))
+}
+
+const VIEWPORT_COLOR: Color = Color::rgb(0xFF, 0x00, 0x00);
+const TRANSPARENT_COLOR: Color = Color::rgb(0x80, 0x80, 0x80);
+const TILES_PER_ROW: usize = 16;
+const SPRITES_PER_ROW: usize = 8;
+
+/// Images of VRAM, OAM and palette RAM for the debug viewers
+impl Gpu {
+    /// All 384 tiles of a VRAM bank, 16 tiles per row, drawn with BG palette 0
+    pub fn tile_set_image(&self, bank: usize) -> DebugImage {
+        let mut image = DebugImage::new(TILES_PER_ROW * 8, (384 / TILES_PER_ROW) * 8);
+        for (tile_idx, tile) in self.tile_set[bank].iter().enumerate() {
+            let (tile_x, tile_y) = ((tile_idx % TILES_PER_ROW) * 8, (tile_idx / TILES_PER_ROW) * 8);
+            for (y, row) in tile.iter().enumerate() {
+                for (x, value) in row.iter().enumerate() {
+                    image.set_pixel(tile_x + x, tile_y + y, self.get_bg_color(*value, 0).0);
+                }
+            }
+        }
+        image
+    }
+
+    /// The 32x32 tile map at 9800 (false) or 9C00 (true) with the SCX/SCY viewport outlined
+    pub fn tile_map_image(&self, tilemap: bool) -> DebugImage {
+        let mut image = DebugImage::new(256, 256);
+        for y in 0 ..= 255u8 {
+            for x in 0 ..= 255u8 {
+                let (tile, attributes) = self.get_tile_at(tilemap, x / 8, y / 8);
+                let tile_y = if attributes & 0b01000000 > 0 { 7 - y % 8 } else { y % 8 };
+                let tile_x = if attributes & 0b00100000 > 0 { 7 - x % 8 } else { x % 8 };
+                let value = tile[tile_y as usize][tile_x as usize];
+                image.set_pixel(x as usize, y as usize, self.get_bg_color(value, attributes & 0b111).0);
+            }
+        }
+        // The viewport wraps around the edges of the map
+        for offset in 0 .. SCREEN_WIDTH {
+            let x = self.scx.wrapping_add(offset) as usize;
+            image.set_pixel(x, self.scy as usize, VIEWPORT_COLOR);
+            image.set_pixel(x, self.scy.wrapping_add(SCREEN_HEIGHT - 1) as usize, VIEWPORT_COLOR);
+        }
+        for offset in 0 .. SCREEN_HEIGHT {
+            let y = self.scy.wrapping_add(offset) as usize;
+            image.set_pixel(self.scx as usize, y, VIEWPORT_COLOR);
+            image.set_pixel(self.scx.wrapping_add(SCREEN_WIDTH - 1) as usize, y, VIEWPORT_COLOR);
+        }
+        image
+    }
+
+    pub fn oam_entries(&self) -> Vec<Sprite> {
+        (0 .. 40).map(|n| self.get_sprite(n)).collect()
+    }
+
+    /// All 40 sprites in OAM order, 8 per row in 8x16 cells with a 1 pixel gap,
+    /// drawn with their own palette and flips. Transparent pixels are grey
+    pub fn oam_image(&self) -> DebugImage {
+        let (cell_width, cell_height) = (9, 17);
+        let mut image = DebugImage::new(SPRITES_PER_ROW * cell_width, (40 / SPRITES_PER_ROW) * cell_height);
+        let sprite_height = if self.double_sprite_size { 16 } else { 8 };
+        for (n, sprite) in self.oam_entries().iter().enumerate() {
+            let (cell_x, cell_y) = ((n % SPRITES_PER_ROW) * cell_width, (n / SPRITES_PER_ROW) * cell_height);
+            let bank = if self.color_mode { sprite.vram_bank() } else { 0 };
+            for y in 0 .. sprite_height {
+                let sprite_y = if sprite.y_flip() { sprite_height - 1 - y } else { y };
+                let tile_number = match (self.double_sprite_size, sprite_y) {
+                    (true, 0 ..= 7) => sprite.tile_number & 0xFE,
+                    (true, _) => sprite.tile_number | 0x01,
+                    (false, _) => sprite.tile_number
+                };
+                let tile = self.get_sprite_tile(bank, tile_number);
+                for x in 0 .. 8 {
+                    let sprite_x = if sprite.x_flip() { 7 - x } else { x };
+                    let color = match self.get_sprite_color(sprite, tile[(sprite_y % 8) as usize][sprite_x as usize]) {
+                        Some((color, _)) => color,
+                        None => TRANSPARENT_COLOR
+                    };
+                    image.set_pixel(cell_x + x as usize, cell_y + y as usize, color);
+                }
+            }
+        }
+        image
+    }
+
+    /// CGB palette RAM as 8x8 swatches, BG palettes 0-7 on the left and sprite palettes 0-7 on the right.
+    /// In DMG mode BGP is shown as BG palette 0 and OBP0/OBP1 as sprite palettes 0 and 1
+    pub fn palette_image(&self) -> DebugImage {
+        let swatch = 8;
+        let mut image = DebugImage::new(swatch * 9, swatch * 8);
+        for palette_num in 0 .. 8u8 {
+            for value in 0 .. 4u8 {
+                let (bg_color, obj_color) = if self.color_mode {
+                    (self.get_cgb_color(&self.color_bg_palettes, palette_num, value),
+                     self.get_cgb_color(&self.color_obj_palettes, palette_num, value))
+                } else {
+                    let shade = |palette: u8| (palette >> (2 * value)) & 0b11;
+                    match palette_num {
+                        0 => (self.monochrome_palette.bg[shade(self.bgp) as usize], self.monochrome_palette.obj0[shade(self.obp0) as usize]),
+                        1 => (TRANSPARENT_COLOR, self.monochrome_palette.obj1[shade(self.obp1) as usize]),
+                        _ => (TRANSPARENT_COLOR, TRANSPARENT_COLOR)
+                    }
+                };
+                let y = palette_num as usize * swatch;
+                image.fill_rect(value as usize * swatch, y, swatch, swatch, bg_color);
+                image.fill_rect((value as usize + 5) * swatch, y, swatch, swatch, obj_color);
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_set_image_reflects_vram() {
+        let mut gpu = Gpu::new(false).unwrap();
+        gpu.set_bgp(0b11100100);
+        // Tile 1, row 0: lo byte 0xFF hi byte 0x00 gives color 1 across the row
+        gpu.write_to_vram(0x10, 0xFF);
+        gpu.write_to_vram(0x11, 0x00);
+        let image = gpu.tile_set_image(0);
+        assert_eq!((image.width, image.height), (128, 192));
+        let shade = gpu.get_monochrome_palette().bg[1];
+        let pixel = image.pixel(8, 0);
+        assert_eq!((pixel.r, pixel.g, pixel.b), (shade.r, shade.g, shade.b));
+        assert_eq!(gpu.oam_entries().len(), 40);
+    }
+
+    #[test]
+    fn test_vram_and_oam_locking() {
+        let mut gpu = Gpu::new(false).unwrap();
+        gpu.set_lcdc_control(0x91);
+        // Step into line 1 OAM search
+        for _ in 0 .. 115 { gpu.gpu_step(4); }
+        assert_eq!(gpu.get_lcdc_status() & 0b11, OAM_SEARCH_MODE);
+        gpu.write_to_oam(0, 0x12);
+        assert_eq!(gpu.read_from_oam(0), 0xFF);
+        // VRAM is still accessible during OAM search
+        gpu.write_to_vram(0, 0x12);
+        assert_eq!(gpu.read_from_vram(0), 0x12);
+        // DMA bypasses the lock
+        gpu.dma_write_oam(0, 0x56);
+
+        for _ in 0 .. 20 { gpu.gpu_step(4); }
+        assert_eq!(gpu.get_lcdc_status() & 0b11, LCD_TRANSFER_MODE);
+        gpu.write_to_vram(0, 0x34);
+        assert_eq!(gpu.read_from_vram(0), 0xFF);
+        gpu.set_access_locking(false);
+        assert_eq!(gpu.read_from_oam(0), 0x56);
+        gpu.write_to_vram(0, 0x34);
+        assert_eq!(gpu.read_from_vram(0), 0x34);
+    }
+
+    #[test]
+    fn test_lcd_enable_timing() {
+        let mut gpu = Gpu::new(false).unwrap();
+        gpu.set_lcdc_control(0x91);
+        // Line 0 starts in H-Blank instead of OAM search after enabling
+        gpu.gpu_step(4);
+        assert_eq!(gpu.get_lcdc_status() & 0b11, H_BLANK_MODE);
+        for _ in 0 .. 20 { gpu.gpu_step(4); }
+        assert_eq!(gpu.get_lcdc_status() & 0b11, LCD_TRANSFER_MODE);
+        // Line 1 has a normal OAM search
+        for _ in 0 .. 100 { gpu.gpu_step(4); }
+        assert_eq!(gpu.get_ly(), 1);
+        assert_eq!(gpu.get_lcdc_status() & 0b11, OAM_SEARCH_MODE);
+
+        // The first frame is blank but still reported
+        let mut cycles = 121 * 4;
+        while !gpu.take_frame_ready() {
+            gpu.gpu_step(4);
+            cycles += 4;
+        }
+        assert_eq!(cycles, 144 * 456);
+
+        // While off LY and the mode read 0 and blank frames keep coming every 70224 cycles
+        gpu.set_lcdc_control(0x11);
+        assert_eq!(gpu.get_ly(), 0);
+        assert_eq!(gpu.get_lcdc_status() & 0b11, H_BLANK_MODE);
+        for _ in 0 .. 70_224 / 4 - 1 { gpu.gpu_step(4); }
+        assert!(!gpu.take_frame_ready());
+        gpu.gpu_step(4);
+        assert!(gpu.take_frame_ready());
+    }
+}
@@ -1,10 +1,39 @@
 use super::JOYPAD_INTERRUPT;
 const SELECT_BUTTON: u8 = 0b00100000;
 const SELECT_DIRECTION: u8 = 0b00010000;
+/// Joypads the SGB can read through MLT_REQ
+pub const MAX_PLAYERS: usize = 4;
+const SOURCE_COUNT: usize = 3;
+
+/// Where a key press came from. Each source keeps its own state and a key reads as pressed
+/// while any source holds it, so releasing a keyboard key does not release the same gamepad button
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InputSource {
+    Keyboard,
+    Gamepad,
+    /// Presses from scripts, movies or tests
+    Script
+}
+
+impl InputSource {
+    fn index(self) -> usize {
+        match self {
+            InputSource::Keyboard => 0,
+            InputSource::Gamepad => 1,
+            InputSource::Script => 2
+        }
+    }
+}
 
 pub struct Input {
     joypad: u8,
-    buttons: u8,
+    /// Active low state of each source of each joypad, directions in the upper nibble
+    buttons: [[u8; SOURCE_COUNT]; MAX_PLAYERS],
+    /// Joypads being read in turn, more than one only after an SGB MLT_REQ
+    player_count: usize,
+    current_player: usize,
+    /// Hide left+right and up+down pressed together, which a real D-pad cannot do
+    filter_opposing: bool,
     pub interrupt: u8
 }
 
@@ -12,35 +41,124 @@ impl Input {
     pub fn new() -> Self {
         Input {
             joypad: 0b00110000,
-            buttons: 0xFF,
+            buttons: [[0xFF; SOURCE_COUNT]; MAX_PLAYERS],
+            player_count: 1,
+            current_player: 0,
+            filter_opposing: false,
             interrupt: 0
         }
     }
 
+    /// Presses a key on the keyboard of the first joypad
     pub fn key_pressed(&mut self, key: Keycode) {
-        self.interrupt = JOYPAD_INTERRUPT;
-        self.buttons &= !key.bit();
+        self.press(InputSource::Keyboard, 0, key);
     }
 
     pub fn key_released(&mut self, key: Keycode) {
-        self.buttons |= key.bit();
+        self.release(InputSource::Keyboard, 0, key);
+    }
+
+    /// Presses a key of joypad `player`, 0 to 3. Keys of other players are ignored
+    pub fn press(&mut self, source: InputSource, player: usize, key: Keycode) {
+        if player < MAX_PLAYERS {
+            self.update(|input| input.buttons[player][source.index()] &= !key.bit());
+        }
+    }
+
+    pub fn release(&mut self, source: InputSource, player: usize, key: Keycode) {
+        if player < MAX_PLAYERS {
+            self.update(|input| input.buttons[player][source.index()] |= key.bit());
+        }
+    }
+
+    /// Releases every key held by `source`, for example when a gamepad is disconnected
+    pub fn release_all(&mut self, source: InputSource) {
+        self.update(|input| {
+            for buttons in input.buttons.iter_mut() {
+                buttons[source.index()] = 0xFF;
+            }
+        });
+    }
+
+    /// Whether left+right and up+down pressed together read as neither pressed
+    pub fn set_filter_opposing(&mut self, filter: bool) {
+        self.update(|input| input.filter_opposing = filter);
+    }
+
+    /// Number of joypads read in turn, set by the SGB MLT_REQ command
+    pub fn set_player_count(&mut self, count: usize) {
+        self.update(|input| {
+            input.player_count = if count == 0 { 1 } else { count.min(MAX_PLAYERS) };
+            input.current_player = 0;
+        });
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.player_count
     }
 
     pub fn write_joypad(&mut self, value: u8) {
-        self.joypad = value & 0b11110000;
+        self.update(|input| {
+            let deselecting_buttons = input.joypad & SELECT_BUTTON == 0 && value & SELECT_BUTTON > 0;
+            if input.player_count > 1 && deselecting_buttons {
+                // The SGB moves on to the next joypad whenever P15 goes high
+                input.current_player = (input.current_player + 1) % input.player_count;
+            }
+            input.joypad = value & 0b00110000;
+        });
     }
 
     pub fn read_joypad(&self) -> u8 {
+        self.joypad | self.input_lines() | 0b11000000
+    }
+
+    /// Applies a change and requests the joypad interrupt if any of P10-P13 went from high to low
+    fn update<F: FnOnce(&mut Self)>(&mut self, change: F) {
+        let before = self.input_lines();
+        change(self);
+        if before & !self.input_lines() > 0 {
+            self.interrupt = JOYPAD_INTERRUPT;
+        }
+    }
+
+    /// P10-P13, the selected groups of keys pulling them low when pressed
+    fn input_lines(&self) -> u8 {
+        let buttons = self.merged_buttons(self.current_player);
+        let mut lines = 0b00001111;
         if self.joypad & SELECT_DIRECTION == 0 {
-            self.joypad | ((0b11110000 & self.buttons) >> 4) | 0b11000000
-        } else if self.joypad & SELECT_BUTTON == 0 {
-            self.joypad | (0b00001111 & self.buttons) | 0b11000000
-        } else {
-            self.joypad | 0b11000000
+            lines &= buttons >> 4;
+        }
+        if self.joypad & SELECT_BUTTON == 0 {
+            lines &= buttons & 0b00001111;
+        }
+        if self.joypad & (SELECT_DIRECTION | SELECT_BUTTON) == SELECT_DIRECTION | SELECT_BUTTON && self.player_count > 1 {
+            // With neither line selected the SGB returns the current joypad as $F, $E, $D, $C
+            lines = 0x0F - self.current_player as u8;
+        }
+        lines
+    }
+
+    fn merged_buttons(&self, player: usize) -> u8 {
+        let mut buttons = self.buttons[player].iter().fold(0xFF, |merged, source| merged & source);
+        if self.filter_opposing {
+            for &(first, second) in [(Keycode::Left, Keycode::Right), (Keycode::Up, Keycode::Down)].iter() {
+                let both = first.bit() | second.bit();
+                if buttons & both == 0 {
+                    buttons |= both;
+                }
+            }
         }
+        buttons
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::new()
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Keycode {
     Start,
     Select,
@@ -53,7 +171,7 @@ pub enum Keycode {
 }
 
 impl Keycode {
-    fn bit(&self) -> u8 {
+    fn bit(self) -> u8 {
         match self {
             Keycode::Start => 0b00001000,
             Keycode::Select => 0b00000100,
@@ -65,4 +183,78 @@ impl Keycode {
             Keycode::Right => 0b00010000
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_only_for_selected_lines() {
+        let mut input = Input::new();
+        input.write_joypad(SELECT_BUTTON);
+        input.key_pressed(Keycode::A);
+        assert_eq!(input.interrupt, 0);
+        input.key_released(Keycode::A);
+
+        input.write_joypad(SELECT_DIRECTION);
+        input.key_pressed(Keycode::A);
+        assert_eq!(input.interrupt, JOYPAD_INTERRUPT);
+        assert_eq!(input.read_joypad(), 0b11011110);
+        // Releasing is a low to high transition
+        input.interrupt = 0;
+        input.key_released(Keycode::A);
+        assert_eq!(input.interrupt, 0);
+    }
+
+    #[test]
+    fn test_selecting_a_held_key_interrupts() {
+        let mut input = Input::new();
+        input.key_pressed(Keycode::Down);
+        assert_eq!(input.interrupt, 0);
+        input.write_joypad(SELECT_BUTTON);
+        assert_eq!(input.interrupt, JOYPAD_INTERRUPT);
+        // Pressing a key sharing an already low line does not
+        input.interrupt = 0;
+        input.key_pressed(Keycode::Start);
+        assert_eq!(input.interrupt, 0);
+        // Both groups selected read as their combination
+        input.write_joypad(0);
+        assert_eq!(input.read_joypad() & 0x0F, 0b0111);
+    }
+
+    #[test]
+    fn test_sources_are_merged() {
+        let mut input = Input::new();
+        input.write_joypad(SELECT_DIRECTION);
+        input.key_pressed(Keycode::B);
+        input.press(InputSource::Gamepad, 0, Keycode::B);
+        input.key_released(Keycode::B);
+        assert_eq!(input.read_joypad() & 0x0F, 0b1101);
+        input.release_all(InputSource::Gamepad);
+        assert_eq!(input.read_joypad() & 0x0F, 0b1111);
+    }
+
+    #[test]
+    fn test_filter_opposing_directions() {
+        let mut input = Input::new();
+        input.write_joypad(SELECT_BUTTON);
+        input.press(InputSource::Keyboard, 0, Keycode::Left);
+        input.press(InputSource::Script, 0, Keycode::Right);
+        input.key_pressed(Keycode::Up);
+        assert_eq!(input.read_joypad() & 0x0F, 0b1000);
+        input.set_filter_opposing(true);
+        assert_eq!(input.read_joypad() & 0x0F, 0b1011);
+    }
+
+    #[test]
+    fn test_players_past_the_last_joypad_are_ignored() {
+        let mut input = Input::new();
+        input.write_joypad(SELECT_DIRECTION);
+        input.press(InputSource::Script, MAX_PLAYERS, Keycode::A);
+        assert_eq!(input.read_joypad() & 0x0F, 0b1111);
+        assert_eq!(input.interrupt, 0);
+        input.release(InputSource::Script, MAX_PLAYERS, Keycode::A);
+        assert!(input.buttons.iter().all(|sources| sources.iter().all(|&buttons| buttons == 0xFF)));
+    }
+}
@@ -0,0 +1,128 @@
+use super::MemoryBank;
+
+/// Writing this to 0x0000-0x1FFF maps the IR port instead of RAM
+pub const IR_MODE: u8 = 0x0E;
+/// IR port reading with the LED off and no light received, as if no other device is present
+pub const IR_NO_SIGNAL: u8 = 0xC0;
+
+/// Hudson HuC1, banked like MBC1 with an infrared port that can replace RAM
+pub struct HuC1 {
+    rom_banks: Vec<Vec<u8>>,
+    ram_banks: Vec<Vec<u8>>,
+    selected_rom: u8,
+    selected_ram: u8,
+    ir_mode: bool
+}
+
+impl HuC1 {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> Result<HuC1, String> {
+        println!("HuC1");
+        if rom_bank_count > 64 {
+            return Err(format!("HuC1 does not support {} ROM banks", rom_bank_count));
+        }
+        let mut mbc = HuC1 {
+            rom_banks: vec![vec![0; 0x4000]; rom_bank_count as usize],
+            ram_banks: vec![vec![0; ram_bank_size as usize]; ram_bank_count as usize],
+            selected_rom: 1,
+            selected_ram: 0,
+            ir_mode: false
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
+            mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
+        }
+        Ok(mbc)
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        match self.ir_mode || self.ram_banks.is_empty() {
+            true => None,
+            false => Some(self.selected_ram as usize % self.ram_banks.len())
+        }
+    }
+}
+
+impl MemoryBank for HuC1 {
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0 ..= 0x1FFF => self.ir_mode = value == IR_MODE,
+            0x2000 ..= 0x3FFF => {
+                self.selected_rom = match value & 0b00111111 {
+                    0 => 1,
+                    bank => bank
+                };
+            },
+            0x4000 ..= 0x5FFF => self.selected_ram = value & 0b11,
+            _ => {}
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        // Writes in IR mode switch the LED, which nothing receives
+        if let Some(bank) = self.ram_bank() {
+            let bank_size = self.ram_banks[bank].len();
+            self.ram_banks[bank][address as usize % bank_size] = value;
+        }
+    }
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0 ..= 0x3FFF => self.rom_banks[0][address as usize],
+            0x4000 ..= 0x7FFF => {
+                self.rom_banks[self.selected_rom as usize % self.rom_banks.len()][(address - 0x4000) as usize]
+            },
+            _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.ram_bank() {
+            Some(bank) => self.ram_banks[bank][address as usize % self.ram_banks[bank].len()],
+            None if self.ir_mode => IR_NO_SIGNAL,
+            None => 0xFF
+        }
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram_banks.concat())
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram_banks(&mut self.ram_banks, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ir_port_reports_no_device() {
+        let mut mbc = HuC1::load_rom(&[], 4, 4, 0x2000).unwrap();
+        mbc.write_ram(0, 0x12);
+        mbc.write_rom(0x0000, IR_MODE);
+        assert_eq!(mbc.read_ram(0), IR_NO_SIGNAL);
+        mbc.write_ram(0, 0x01);
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0), 0x12);
+    }
+
+    #[test]
+    fn test_save_data_round_trip() {
+        let mut mbc = HuC1::load_rom(&[], 4, 4, 0x2000).unwrap();
+        mbc.write_rom(0x4000, 3);
+        mbc.write_ram(0x10, 0x34);
+        let save = mbc.save_data().unwrap();
+        assert_eq!(save.len(), 0x8000);
+        let mut reloaded = HuC1::load_rom(&[], 4, 4, 0x2000).unwrap();
+        reloaded.load_save_data(&save);
+        reloaded.write_rom(0x4000, 3);
+        assert_eq!(reloaded.read_ram(0x10), 0x34);
+    }
+
+    #[test]
+    fn test_truncated_save_data() {
+        let mut mbc = HuC1::load_rom(&[], 4, 4, 0x2000).unwrap();
+        mbc.load_save_data(&[]);
+        // Only part of the first bank was saved
+        mbc.load_save_data(&[0x12; 0x1000]);
+        assert_eq!(mbc.read_ram(0x0FFF), 0x12);
+        assert_eq!(mbc.read_ram(0x1000), 0x00);
+        mbc.write_rom(0x4000, 1);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+    }
+}
@@ -0,0 +1,302 @@
+use super::MemoryBank;
+use super::huc1::{IR_MODE, IR_NO_SIGNAL};
+use std::convert::TryInto;
+
+const CPU_CLOCK: u32 = 4_194_304;
+const MINUTES_PER_DAY: u64 = 1440;
+/// Bytes appended to the RAM in save data, the clock in seconds and when it was saved
+const RTC_SAVE_SIZE: usize = 16;
+/// Followed by the RTC memory nibbles, where games keep alarms and settings
+const RTC_MEMORY_SIZE: usize = 256;
+
+const MODE_RAM_READ_ONLY: u8 = 0x00;
+const MODE_RAM: u8 = 0x0A;
+const MODE_RTC_COMMAND: u8 = 0x0B;
+const MODE_RTC_RESPONSE: u8 = 0x0C;
+const MODE_RTC_SEMAPHORE: u8 = 0x0D;
+
+/// Hudson HuC3 with an RTC driven by 4 bit commands and an infrared port. The mode written to
+/// 0x0000-0x1FFF selects what 0xA000-0xBFFF accesses
+pub struct HuC3 {
+    rom_banks: Vec<Vec<u8>>,
+    ram_banks: Vec<Vec<u8>>,
+    selected_rom: u8,
+    selected_ram: u8,
+    mode: u8,
+    rtc: Rtc
+}
+
+impl HuC3 {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> Result<HuC3, String> {
+        println!("HuC3");
+        if rom_bank_count > 128 {
+            return Err(format!("HuC3 does not support {} ROM banks", rom_bank_count));
+        }
+        let mut mbc = HuC3 {
+            rom_banks: vec![vec![0; 0x4000]; rom_bank_count as usize],
+            ram_banks: vec![vec![0; ram_bank_size as usize]; ram_bank_count as usize],
+            selected_rom: 1,
+            selected_ram: 0,
+            mode: MODE_RAM_READ_ONLY,
+            rtc: Rtc::new()
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
+            mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
+        }
+        Ok(mbc)
+    }
+
+    fn ram_address(&self, address: u16) -> Option<(usize, usize)> {
+        if self.ram_banks.is_empty() {
+            return None;
+        }
+        let bank = self.selected_ram as usize % self.ram_banks.len();
+        Some((bank, address as usize % self.ram_banks[bank].len()))
+    }
+}
+
+impl MemoryBank for HuC3 {
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0 ..= 0x1FFF => self.mode = value & 0x0F,
+            0x2000 ..= 0x3FFF => self.selected_rom = value & 0x7F,
+            0x4000 ..= 0x5FFF => self.selected_ram = value & 0x0F,
+            _ => {}
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        match self.mode {
+            MODE_RAM => if let Some((bank, offset)) = self.ram_address(address) {
+                self.ram_banks[bank][offset] = value;
+            },
+            MODE_RTC_COMMAND => self.rtc.command(value),
+            // The semaphore and IR LED writes have no lasting effect
+            _ => {}
+        }
+    }
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0 ..= 0x3FFF => self.rom_banks[0][address as usize],
+            0x4000 ..= 0x7FFF => {
+                self.rom_banks[self.selected_rom as usize % self.rom_banks.len()][(address - 0x4000) as usize]
+            },
+            _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.mode {
+            MODE_RAM | MODE_RAM_READ_ONLY => match self.ram_address(address) {
+                Some((bank, offset)) => self.ram_banks[bank][offset],
+                None => 0xFF
+            },
+            MODE_RTC_RESPONSE => self.rtc.response,
+            // Bit 0 set means the RTC is ready for the next command, which it always is
+            MODE_RTC_SEMAPHORE => 0xFF,
+            IR_MODE => IR_NO_SIGNAL,
+            _ => 0xFF
+        }
+    }
+    fn step(&mut self, cycles: u8) {
+        self.rtc.step(cycles);
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        let mut data = self.ram_banks.concat();
+        data.extend_from_slice(&self.rtc.seconds.to_le_bytes());
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data.extend_from_slice(&self.rtc.memory);
+        Some(data)
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram_banks(&mut self.ram_banks, data);
+        let ram_size: usize = self.ram_banks.iter().map(|bank| bank.len()).sum();
+        if data.len() >= ram_size + RTC_SAVE_SIZE {
+            let rtc = &data[ram_size .. ram_size + RTC_SAVE_SIZE];
+            let seconds = u64::from_le_bytes(rtc[.. 8].try_into().unwrap());
+            let saved_at = u64::from_le_bytes(rtc[8 ..].try_into().unwrap());
+            // Catch up with the time that passed while the emulator was closed
+            let now = unix_time();
+            let elapsed = if saved_at > 0 && now > saved_at { now - saved_at } else { 0 };
+            self.rtc.seconds = seconds + elapsed;
+        }
+        // Saves from before the memory was kept end after the clock
+        let memory_start = ram_size + RTC_SAVE_SIZE;
+        if data.len() >= memory_start + RTC_MEMORY_SIZE {
+            self.rtc.memory.copy_from_slice(&data[memory_start .. memory_start + RTC_MEMORY_SIZE]);
+        }
+    }
+}
+
+/// Seconds since the Unix epoch. wasm32 has no system clock so the RTC only runs with the emulator there
+fn unix_time() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        0
+    }
+}
+
+/// The HuC3 clock counts minutes of the day and days, which games access through 256 nibbles of
+/// memory. Commands write a 3 bit opcode in bits 4-6 and an argument in bits 0-3
+struct Rtc {
+    /// Time counted in seconds, minutes and days are derived from this
+    seconds: u64,
+    cycles: u32,
+    memory: [u8; 256],
+    address: u8,
+    response: u8
+}
+
+impl Rtc {
+    fn new() -> Self {
+        Rtc {
+            seconds: 0,
+            cycles: 0,
+            memory: [0; 256],
+            address: 0,
+            response: 0
+        }
+    }
+
+    fn step(&mut self, cycles: u8) {
+        self.cycles += cycles as u32;
+        if self.cycles >= CPU_CLOCK {
+            self.cycles -= CPU_CLOCK;
+            self.seconds += 1;
+        }
+    }
+
+    fn command(&mut self, value: u8) {
+        let argument = value & 0x0F;
+        match (value >> 4) & 0b111 {
+            // Read the nibble at the address and increment it
+            0x1 => {
+                self.response = (value & 0xF0) | self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            },
+            // Write the argument at the address and increment it
+            0x3 => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+                self.response = value & 0xF0;
+            },
+            0x4 => self.address = (self.address & 0xF0) | argument,
+            0x5 => self.address = (self.address & 0x0F) | argument << 4,
+            0x6 => {
+                self.response = value & 0xF0;
+                match argument {
+                    0x0 => self.time_to_memory(),
+                    0x1 => self.memory_to_time(),
+                    // Status, reports the clock is running
+                    0x2 => self.response |= 1,
+                    // Tone generator and other extended commands are not emulated
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Copies the minute of the day to nibbles 0-2 and the day count to nibbles 3-5
+    fn time_to_memory(&mut self) {
+        let total_minutes = self.seconds / 60;
+        let minutes = total_minutes % MINUTES_PER_DAY;
+        let days = (total_minutes / MINUTES_PER_DAY) & 0xFFF;
+        for nibble in 0 .. 3 {
+            self.memory[nibble] = ((minutes >> (nibble * 4)) & 0xF) as u8;
+            self.memory[3 + nibble] = ((days >> (nibble * 4)) & 0xF) as u8;
+        }
+    }
+
+    fn memory_to_time(&mut self) {
+        let mut minutes = 0;
+        let mut days = 0;
+        for nibble in 0 .. 3 {
+            minutes |= (self.memory[nibble] as u64) << (nibble * 4);
+            days |= (self.memory[3 + nibble] as u64) << (nibble * 4);
+        }
+        self.seconds = (days * MINUTES_PER_DAY + minutes % MINUTES_PER_DAY) * 60;
+        self.cycles = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rtc_command(mbc: &mut HuC3, value: u8) -> u8 {
+        mbc.write_rom(0x0000, MODE_RTC_COMMAND);
+        mbc.write_ram(0, value);
+        mbc.write_rom(0x0000, MODE_RTC_RESPONSE);
+        mbc.read_ram(0)
+    }
+
+    fn read_nibbles(mbc: &mut HuC3, count: usize) -> u64 {
+        rtc_command(mbc, 0x40);
+        rtc_command(mbc, 0x50);
+        (0 .. count).fold(0, |value, nibble| value | ((rtc_command(mbc, 0x10) & 0xF) as u64) << (nibble * 4))
+    }
+
+    #[test]
+    fn test_rtc_set_and_read() {
+        let mut mbc = HuC3::load_rom(&[], 4, 4, 0x2000).unwrap();
+        // Write minute 0x123 of day 0x005 to the nibbles and set the clock from them
+        rtc_command(&mut mbc, 0x40);
+        rtc_command(&mut mbc, 0x50);
+        for nibble in [0x3, 0x2, 0x1, 0x5, 0x0, 0x0].iter() {
+            rtc_command(&mut mbc, 0x30 | nibble);
+        }
+        rtc_command(&mut mbc, 0x61);
+
+        // A minute later
+        for _ in 0 .. 60 {
+            for _ in 0 .. CPU_CLOCK / 128 {
+                mbc.step(128);
+            }
+        }
+        rtc_command(&mut mbc, 0x60);
+        assert_eq!(read_nibbles(&mut mbc, 6), 0x005124);
+        assert_eq!(rtc_command(&mut mbc, 0x62), 0x61);
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut mbc = HuC3::load_rom(&[], 4, 4, 0x2000).unwrap();
+        mbc.write_rom(0x0000, MODE_RAM);
+        mbc.write_ram(0, 0x12);
+        // Mode 0 can read but not write RAM
+        mbc.write_rom(0x0000, MODE_RAM_READ_ONLY);
+        mbc.write_ram(0, 0x34);
+        assert_eq!(mbc.read_ram(0), 0x12);
+        mbc.write_rom(0x0000, MODE_RTC_SEMAPHORE);
+        assert_eq!(mbc.read_ram(0) & 1, 1);
+        mbc.write_rom(0x0000, IR_MODE);
+        assert_eq!(mbc.read_ram(0), IR_NO_SIGNAL);
+    }
+
+    #[test]
+    fn test_save_data_keeps_time() {
+        let mut mbc = HuC3::load_rom(&[], 4, 4, 0x2000).unwrap();
+        mbc.rtc.seconds = 3600;
+        mbc.rtc.memory[0x10] = 0xA;
+        let save = mbc.save_data().unwrap();
+        assert_eq!(save.len(), 0x8000 + RTC_SAVE_SIZE + RTC_MEMORY_SIZE);
+        let mut reloaded = HuC3::load_rom(&[], 4, 4, 0x2000).unwrap();
+        reloaded.load_save_data(&save);
+        assert!(reloaded.rtc.seconds >= 3600 && reloaded.rtc.seconds < 3660);
+        assert_eq!(reloaded.rtc.memory[0x10], 0xA);
+    }
+
+    #[test]
+    fn test_save_data_with_small_ram_banks() {
+        let mut mbc = HuC3::load_rom(&[], 4, 2, 0x800).unwrap();
+        mbc.ram_banks[1][0] = 0x42;
+        let save = mbc.save_data().unwrap();
+        let mut reloaded = HuC3::load_rom(&[], 4, 2, 0x800).unwrap();
+        reloaded.load_save_data(&save);
+        assert_eq!(reloaded.ram_banks[1][0], 0x42);
+    }
+}
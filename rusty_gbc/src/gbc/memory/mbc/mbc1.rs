@@ -1,96 +1,214 @@
 use super::MemoryBank;
+use crate::gbc::boot::NINTENDO_LOGO;
+
+/// Bank of the second game in an MBC1M multicart, where each game starts with its own header
+const MULTICART_GAME_BANK: usize = 0x10;
 
 pub struct MBC1 {
     rom_banks: Vec<Vec<u8>>,
     ram_banks: Vec<Vec<u8>>,
-    selected_rom: u8,
-    two_bits: u8,
+    /// BANK1 register, 5 bits and never 0
+    bank1: u8,
+    /// BANK2 register, upper ROM bank bits or the RAM bank
+    bank2: u8,
     ram_enabled: bool,
-    /// ROM banking mode if false, RAM banking mode if true
-    ram_banking_mode: bool,
+    /// In mode 1 BANK2 also applies to 0x0000-0x3FFF and RAM
+    mode: bool,
+    /// MBC1M wires BANK2 to bits 4-5 of the ROM bank instead of 5-6, BANK1 bit 4 is not connected
+    multicart: bool
 }
 
 impl MBC1 {
-    pub fn load_rom(bytes: &Vec<u8>, rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> MBC1 {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> Result<MBC1, String> {
         println!("MBC1");
-        //Special limitation of MBC1
-        let rom_bank_count = match rom_bank_count {
-            64 => 63,
-            128 => 125,
-            129 ..= 0xFFFF => panic!("MBC1 does not support {} rom banks", rom_bank_count),
-            _ => rom_bank_count
-        };
+        if rom_bank_count > 128 {
+            return Err(format!("MBC1 does not support {} ROM banks", rom_bank_count));
+        }
         let mut mbc = MBC1 {
             rom_banks: vec![vec![0; 0x4000]; rom_bank_count as usize],
             ram_banks: vec![vec![0; ram_bank_size as usize]; ram_bank_count as usize],
-            selected_rom: 1,
-            two_bits: 0,
+            bank1: 1,
+            bank2: 0,
             ram_enabled: false,
-            ram_banking_mode: false,
+            mode: false,
+            multicart: is_multicart(bytes, rom_bank_count)
         };
         println!("{} ROM banks of size 0x4000 (total {}Kbyte) {} RAM banks of size 0x{:04X} (total {}Kbyte)",
-            rom_bank_count, mbc.rom_banks.len() / 0x400, ram_bank_count, ram_bank_size, (mbc.ram_banks.len() * ram_bank_size as usize) / 0x400);
-        for (idx, byte) in bytes.iter().enumerate() {
+            rom_bank_count, mbc.rom_banks.len() * 0x4000 / 0x400, ram_bank_count, ram_bank_size, (mbc.ram_banks.len() * ram_bank_size as usize) / 0x400);
+        if mbc.multicart {
+            println!("MBC1M multicart");
+        }
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
             mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
         }
-        mbc
+        Ok(mbc)
     }
 
-    fn selected_rom(&self) -> u8 {
-        match self.ram_banking_mode {
-            true => self.selected_rom,
-            false => self.selected_rom | (self.two_bits << 5)
+    fn upper_bits(&self) -> usize {
+        let shift = if self.multicart { 4 } else { 5 };
+        (self.bank2 as usize) << shift
+    }
+
+    /// Bank mapped to 0x0000-0x3FFF, only BANK2 in mode 1 changes it
+    fn zero_bank(&self) -> usize {
+        let bank = if self.mode { self.upper_bits() } else { 0 };
+        bank % self.rom_banks.len()
+    }
+
+    /// Bank mapped to 0x4000-0x7FFF, wrapped to the ROM size like the unused address lines
+    fn high_bank(&self) -> usize {
+        let lower_mask = if self.multicart { 0b1111 } else { 0b11111 };
+        ((self.bank1 & lower_mask) as usize | self.upper_bits()) % self.rom_banks.len()
+    }
+
+    fn ram_address(&self, address: u16) -> Option<(usize, usize)> {
+        if !self.ram_enabled || self.ram_banks.is_empty() {
+            return None;
         }
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        let bank_size = self.ram_banks[0].len();
+        Some((bank % self.ram_banks.len(), address as usize % bank_size))
     }
 }
 
+/// MBC1M carts are 1MB and repeat the header of their menu in bank 0x10
+fn is_multicart(bytes: &[u8], rom_bank_count: u16) -> bool {
+    let logo_address = MULTICART_GAME_BANK * 0x4000 + 0x104;
+    rom_bank_count == 64 && bytes.len() >= logo_address + NINTENDO_LOGO.len()
+        && bytes[logo_address .. logo_address + NINTENDO_LOGO.len()] == NINTENDO_LOGO[..]
+}
+
 impl MemoryBank for MBC1 {
     fn write_rom(&mut self, address: u16, value: u8) {
         match address {
             0 ..= 0x1FFF => {
-                self.ram_enabled = value & 0x0A == 0x0A;
-                //println!("ram enabled: {}", self.ram_enabled);
+                self.ram_enabled = value & 0x0F == 0x0A;
             },
             0x2000 ..= 0x3FFF => {
-                self.selected_rom = match value & 0b00011111 {
-                    0 => (value + 1) & 0b00011111,
-                    _ => value & 0b00011111
+                // The zero check uses all 5 bits, even on multicarts where bit 4 is not connected
+                self.bank1 = match value & 0b00011111 {
+                    0 => 1,
+                    bank => bank
                 };
-                //println!("selected rom: {} with value: {}", self.selected_rom, value);
             },
             0x4000 ..= 0x5FFF => {
-                self.two_bits = value & 0b11;
-                //println!("two bits: {} value: {}", self.two_bits, value);
+                self.bank2 = value & 0b11;
             },
             0x6000 ..= 0x7FFF => {
-                self.ram_banking_mode = value & 1 == 1;
-                //println!("ram banking: {}", self.ram_banking_mode);
+                self.mode = value & 1 == 1;
             },
             _ => {}
         }
     }
     fn write_ram(&mut self, address: u16, value: u8) {
-        let ram_bank_count = self.ram_banks.len();
-        match self.ram_enabled {
-            true if self.ram_banking_mode => self.ram_banks[self.two_bits as usize % ram_bank_count][address as usize] = value,
-            true => self.ram_banks[0][address as usize] = value,
-            false => {}
+        if let Some((bank, offset)) = self.ram_address(address) {
+            self.ram_banks[bank][offset] = value;
         }
     }
     fn read_rom(&self, address: u16) -> u8 {
         match address {
-            0 ..= 0x3FFF => self.rom_banks[0][address as usize],
-            0x4000 ..= 0x7FFF => {
-                self.rom_banks[self.selected_rom() as usize % self.rom_banks.len()][(address - 0x4000) as usize]
-            },
+            0 ..= 0x3FFF => self.rom_banks[self.zero_bank()][address as usize],
+            0x4000 ..= 0x7FFF => self.rom_banks[self.high_bank()][(address - 0x4000) as usize],
             _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
         }
     }
     fn read_ram(&self, address: u16) -> u8 {
-        match self.ram_enabled {
-            true if self.ram_banking_mode => self.ram_banks[self.two_bits as usize % self.ram_banks.len()][address as usize],
-            true => self.ram_banks[0][address as usize],
-            false => 0xFF
+        match self.ram_address(address) {
+            Some((bank, offset)) => self.ram_banks[bank][offset],
+            None => 0xFF
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM where the first byte of each bank holds its bank number
+    fn numbered_rom(bank_count: usize) -> Vec<u8> {
+        let mut rom = vec![0; bank_count * 0x4000];
+        for bank in 0 .. bank_count {
+            rom[bank * 0x4000] = bank as u8;
         }
+        rom
+    }
+
+    #[test]
+    fn test_bank_0_maps_to_1() {
+        let mut mbc = MBC1::load_rom(&numbered_rom(128), 128, 0, 0).unwrap();
+        mbc.write_rom(0x2000, 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        // 0x20, 0x40 and 0x60 are reachable, only the lower 5 bits being 0 selects bank 1
+        mbc.write_rom(0x4000, 1);
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x3F);
+    }
+
+    #[test]
+    fn test_mode_1_remaps_bank_0_area() {
+        let mut mbc = MBC1::load_rom(&numbered_rom(128), 128, 0, 0).unwrap();
+        mbc.write_rom(0x4000, 2);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        mbc.write_rom(0x6000, 1);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_banks_wrap_to_rom_size() {
+        let mut mbc = MBC1::load_rom(&numbered_rom(64), 64, 0, 0).unwrap();
+        mbc.write_rom(0x2000, 0x1F);
+        mbc.write_rom(0x4000, 1);
+        assert_eq!(mbc.read_rom(0x4000), 0x3F);
+        mbc.write_rom(0x4000, 3);
+        assert_eq!(mbc.read_rom(0x4000), 0x3F);
+    }
+
+    #[test]
+    fn test_ram_enable_needs_exact_value() {
+        let mut mbc = MBC1::load_rom(&numbered_rom(4), 4, 4, 0x2000).unwrap();
+        for value in [0x0B, 0x1B, 0x0E].iter() {
+            mbc.write_rom(0x0000, *value);
+            mbc.write_ram(0, 0x12);
+            assert_eq!(mbc.read_ram(0), 0xFF);
+        }
+        mbc.write_rom(0x0000, 0x1A);
+        mbc.write_ram(0, 0x12);
+        assert_eq!(mbc.read_ram(0), 0x12);
+    }
+
+    #[test]
+    fn test_ram_banking_in_mode_1() {
+        let mut mbc = MBC1::load_rom(&numbered_rom(4), 4, 4, 0x2000).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 2);
+        mbc.write_ram(0, 0x22);
+        // Mode 0 always uses RAM bank 0
+        assert_eq!(mbc.ram_banks[0][0], 0x22);
+        mbc.write_rom(0x6000, 1);
+        mbc.write_ram(0, 0x33);
+        assert_eq!(mbc.ram_banks[2][0], 0x33);
+        assert_eq!(mbc.read_ram(0), 0x33);
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut rom = numbered_rom(64);
+        for game in [0, 0x10, 0x20, 0x30].iter() {
+            let start = game * 0x4000 + 0x104;
+            rom[start .. start + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        let mut mbc = MBC1::load_rom(&rom, 64, 0, 0).unwrap();
+        assert!(mbc.multicart);
+        // BANK2 selects the game, BANK1 bit 4 is ignored
+        mbc.write_rom(0x4000, 1);
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+        mbc.write_rom(0x6000, 1);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+
+        assert!(!MBC1::load_rom(&numbered_rom(64), 64, 0, 0).unwrap().multicart);
+    }
+}
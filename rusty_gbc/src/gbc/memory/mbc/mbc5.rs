@@ -1,33 +1,48 @@
 use super::MemoryBank;
 
+/// On rumble carts bit 3 of the RAM bank register drives the motor instead of a RAM address line
+const RUMBLE_MOTOR: u8 = 0b00001000;
+
 pub struct MBC5 {
     rom_banks: Vec<Vec<u8>>,
     ram_banks: Vec<Vec<u8>>,
+    /// 9 bit ROM bank, bank 0 can be selected
     selected_rom: u16,
     selected_ram: u8,
     ram_enabled: bool,
+    /// Cart types 0x1C-0x1E have a rumble motor
+    has_rumble: bool,
+    motor_on: bool
 }
 
 impl MBC5 {
-    pub fn load_rom(bytes: &Vec<u8>, rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> MBC5 {
-        println!("MBC5");
-        let rom_bank_count = match rom_bank_count {
-            0x1E0 ..= 0xFFFF => panic!("MBC5 does not support {} rom banks", rom_bank_count),
-            _ => rom_bank_count
-        };
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16, has_rumble: bool) -> Result<MBC5, String> {
+        println!("MBC5{}", if has_rumble { "+RUMBLE" } else { "" });
+        if rom_bank_count > 512 {
+            return Err(format!("MBC5 does not support {} ROM banks", rom_bank_count));
+        }
         let mut mbc = MBC5 {
             rom_banks: vec![vec![0; 0x4000]; rom_bank_count as usize],
             ram_banks: vec![vec![0; ram_bank_size as usize]; ram_bank_count as usize],
             selected_rom: 1,
             selected_ram: 0,
             ram_enabled: false,
+            has_rumble,
+            motor_on: false
         };
         println!("{} ROM banks of size 0x4000 (total {}Kbyte) {} RAM banks of size 0x{:04X} (total {}Kbyte)",
-            rom_bank_count, mbc.rom_banks.len() / 0x400, ram_bank_count, ram_bank_size, (mbc.ram_banks.len() * ram_bank_size as usize) / 0x400);
-        for (idx, byte) in bytes.iter().enumerate() {
+            rom_bank_count, mbc.rom_banks.len() * 0x4000 / 0x400, ram_bank_count, ram_bank_size, (mbc.ram_banks.len() * ram_bank_size as usize) / 0x400);
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
             mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
         }
-        mbc
+        Ok(mbc)
+    }
+
+    fn ram_bank(&self) -> Option<usize> {
+        match self.ram_enabled && !self.ram_banks.is_empty() {
+            true => Some(self.selected_ram as usize % self.ram_banks.len()),
+            false => None
+        }
     }
 }
 
@@ -45,6 +60,10 @@ impl MemoryBank for MBC5 {
             0x3000 ..= 0x3FFF => {
                 self.selected_rom = u16::from_be_bytes([value & 1, (self.selected_rom & 0xFF) as u8]);
             },
+            0x4000 ..= 0x5FFF if self.has_rumble => {
+                self.motor_on = value & RUMBLE_MOTOR > 0;
+                self.selected_ram = value & 0x7;
+            },
             0x4000 ..= 0x5FFF => {
                 self.selected_ram = value & 0xF;
             },
@@ -52,10 +71,8 @@ impl MemoryBank for MBC5 {
         }
     }
     fn write_ram(&mut self, address: u16, value: u8) {
-        let ram_bank_count = self.ram_banks.len();
-        match self.ram_enabled {
-            true => self.ram_banks[self.selected_ram as usize % ram_bank_count][address as usize] = value,
-            false => {}
+        if let Some(bank) = self.ram_bank() {
+            self.ram_banks[bank][address as usize] = value;
         }
     }
     fn read_rom(&self, address: u16) -> u8 {
@@ -68,9 +85,46 @@ impl MemoryBank for MBC5 {
         }
     }
     fn read_ram(&self, address: u16) -> u8 {
-        match self.ram_enabled {
-            true => self.ram_banks[self.selected_ram as usize % self.ram_banks.len()][address as usize],
-            false => 0xFF
+        match self.ram_bank() {
+            Some(bank) => self.ram_banks[bank][address as usize],
+            None => 0xFF
         }
     }
-}
\ No newline at end of file
+    fn rumble(&self) -> bool {
+        self.motor_on
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rumble_bit_is_not_a_ram_bank() {
+        let mut mbc = MBC5::load_rom(&[], 4, 4, 0x2000, true).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0, 0x11);
+        mbc.write_rom(0x4000, RUMBLE_MOTOR | 0x01);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read_ram(0), 0x11);
+        mbc.write_rom(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+
+    #[test]
+    fn test_512_banks() {
+        let mut rom = vec![0; 512 * 0x4000];
+        rom[0x1FF * 0x4000] = 0x42;
+        rom[0x100 * 0x4000] = 0x24;
+        let mut mbc = MBC5::load_rom(&rom, 512, 0, 0, false).unwrap();
+        mbc.write_rom(0x2000, 0xFF);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x42);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x24);
+        // Without rumble bit 3 selects a RAM bank
+        mbc.write_rom(0x4000, RUMBLE_MOTOR);
+        assert!(!mbc.rumble());
+    }
+}
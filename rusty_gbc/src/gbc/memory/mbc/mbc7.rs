@@ -0,0 +1,353 @@
+use super::MemoryBank;
+
+/// Accelerometer reading when level, both axes
+const ACCELEROMETER_CENTER: f32 = 0x81D0 as f32;
+/// Change in the reading for 1g of tilt
+const ACCELEROMETER_GRAVITY: f32 = 0x70 as f32;
+const EEPROM_WORDS: usize = 128;
+
+const EEPROM_CS: u8 = 0b10000000;
+const EEPROM_CLK: u8 = 0b01000000;
+const EEPROM_DI: u8 = 0b00000010;
+
+/// MBC7 with a two axis accelerometer and a 93LC56 EEPROM instead of RAM, both mapped as
+/// registers at 0xA000-0xAFFF once RAM is enabled through 0x0000-0x1FFF and 0x4000-0x5FFF
+pub struct MBC7 {
+    rom_banks: Vec<Vec<u8>>,
+    selected_rom: u8,
+    ram_enabled: bool,
+    ram_enabled_2: bool,
+    /// Current tilt in g as set by the frontend
+    tilt: (f32, f32),
+    latched: (u16, u16),
+    /// 0x55 was written and the next 0xAA write latches the accelerometer
+    latch_ready: bool,
+    eeprom: Eeprom
+}
+
+impl MBC7 {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16) -> Result<MBC7, String> {
+        println!("MBC7");
+        if rom_bank_count > 128 {
+            return Err(format!("MBC7 does not support {} ROM banks", rom_bank_count));
+        }
+        let mut mbc = MBC7 {
+            rom_banks: vec![vec![0; 0x4000]; rom_bank_count as usize],
+            selected_rom: 1,
+            ram_enabled: false,
+            ram_enabled_2: false,
+            tilt: (0.0, 0.0),
+            latched: (0x8000, 0x8000),
+            latch_ready: false,
+            eeprom: Eeprom::new()
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
+            mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
+        }
+        Ok(mbc)
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enabled && self.ram_enabled_2
+    }
+}
+
+impl MemoryBank for MBC7 {
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0 ..= 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.selected_rom = value & 0x7F,
+            0x4000 ..= 0x5FFF => self.ram_enabled_2 = value == 0x40,
+            _ => {}
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.registers_enabled() || address >= 0x1000 {
+            return;
+        }
+        match (address >> 4) & 0xF {
+            0 if value == 0x55 => {
+                self.latched = (0x8000, 0x8000);
+                self.latch_ready = true;
+            },
+            1 if value == 0xAA && self.latch_ready => {
+                let axis = |tilt: f32| (ACCELEROMETER_CENTER + tilt * ACCELEROMETER_GRAVITY) as u16;
+                self.latched = (axis(self.tilt.0), axis(self.tilt.1));
+                self.latch_ready = false;
+            },
+            8 => self.eeprom.write(value),
+            _ => {}
+        }
+    }
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0 ..= 0x3FFF => self.rom_banks[0][address as usize],
+            0x4000 ..= 0x7FFF => {
+                self.rom_banks[self.selected_rom as usize % self.rom_banks.len()][(address - 0x4000) as usize]
+            },
+            _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.registers_enabled() || address >= 0x1000 {
+            return 0xFF;
+        }
+        match (address >> 4) & 0xF {
+            2 => self.latched.0 as u8,
+            3 => (self.latched.0 >> 8) as u8,
+            4 => self.latched.1 as u8,
+            5 => (self.latched.1 >> 8) as u8,
+            6 => 0x00,
+            8 => self.eeprom.read(),
+            _ => 0xFF
+        }
+    }
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.eeprom.words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect())
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum EepromState {
+    /// Waiting for a start bit
+    Idle,
+    /// Shifting in the 2 bit opcode and 8 bit address
+    Command,
+    /// Shifting out a word, starting with a dummy 0 bit
+    Reading { address: usize },
+    /// Shifting in a word for WRITE, or WRAL when `address` is `None`
+    Writing { address: Option<usize> },
+    /// Finished, DO shows ready until CS goes low
+    Done
+}
+
+/// 93LC56 serial EEPROM organized as 128 16 bit words. Bits are shifted in from DI on the
+/// rising edge of CLK while CS is high, most significant bit first
+struct Eeprom {
+    words: [u16; EEPROM_WORDS],
+    pins: u8,
+    data_out: bool,
+    write_enabled: bool,
+    state: EepromState,
+    shift: u16,
+    bit_count: u8
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            words: [0xFFFF; EEPROM_WORDS],
+            pins: 0,
+            data_out: true,
+            write_enabled: false,
+            state: EepromState::Idle,
+            shift: 0,
+            bit_count: 0
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.pins & (EEPROM_CS | EEPROM_CLK | EEPROM_DI)) | if self.data_out { 1 } else { 0 }
+    }
+
+    fn write(&mut self, value: u8) {
+        let previous = self.pins;
+        self.pins = value;
+        if value & EEPROM_CS == 0 {
+            self.state = EepromState::Idle;
+            self.data_out = true;
+            return;
+        }
+        if previous & EEPROM_CLK == 0 && value & EEPROM_CLK > 0 {
+            self.clock(value & EEPROM_DI > 0);
+        }
+    }
+
+    fn clock(&mut self, bit: bool) {
+        match self.state {
+            EepromState::Idle if bit => {
+                self.state = EepromState::Command;
+                self.shift = 0;
+                self.bit_count = 0;
+            },
+            EepromState::Command => {
+                self.shift_in(bit);
+                if self.bit_count == 10 {
+                    self.execute_command();
+                }
+            },
+            EepromState::Reading { address } => {
+                let word = self.words[address];
+                self.data_out = word & (0x8000 >> self.bit_count) > 0;
+                self.bit_count += 1;
+                if self.bit_count == 16 {
+                    // Sequential reads continue with the next word
+                    self.state = EepromState::Reading { address: (address + 1) % EEPROM_WORDS };
+                    self.bit_count = 0;
+                }
+            },
+            EepromState::Writing { address } => {
+                self.shift_in(bit);
+                if self.bit_count == 16 {
+                    match address {
+                        Some(address) => self.words[address] = self.shift,
+                        None => self.words = [self.shift; EEPROM_WORDS]
+                    }
+                    self.finish();
+                }
+            },
+            EepromState::Idle | EepromState::Done => {}
+        }
+    }
+
+    fn shift_in(&mut self, bit: bool) {
+        self.shift = self.shift << 1 | if bit { 1 } else { 0 };
+        self.bit_count += 1;
+    }
+
+    fn execute_command(&mut self) {
+        let opcode = self.shift >> 8;
+        // The 93LC56 ignores the top address bit in 16 bit mode
+        let address = (self.shift & 0x7F) as usize;
+        // With a 00 opcode the top two address bits select EWEN, EWDS, WRAL or ERAL
+        let extended = (self.shift >> 6) & 0b11;
+        self.shift = 0;
+        self.bit_count = 0;
+        match opcode {
+            0b10 => {
+                self.data_out = false;
+                self.state = EepromState::Reading { address };
+            },
+            0b01 if self.write_enabled => self.state = EepromState::Writing { address: Some(address) },
+            0b11 if self.write_enabled => {
+                self.words[address] = 0xFFFF;
+                self.finish();
+            },
+            0b00 => match (extended, self.write_enabled) {
+                // EWDS and EWEN
+                (0b00, _) => { self.write_enabled = false; self.state = EepromState::Done; },
+                (0b11, _) => { self.write_enabled = true; self.state = EepromState::Done; },
+                // WRAL and ERAL
+                (0b01, true) => self.state = EepromState::Writing { address: None },
+                (0b10, true) => {
+                    self.words = [0xFFFF; EEPROM_WORDS];
+                    self.finish();
+                },
+                _ => self.state = EepromState::Done
+            },
+            _ => self.state = EepromState::Done
+        }
+    }
+
+    fn finish(&mut self) {
+        self.state = EepromState::Done;
+        self.data_out = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_mbc() -> MBC7 {
+        let mut mbc = MBC7::load_rom(&[], 4).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    fn send_bits(mbc: &mut MBC7, value: u32, count: u8) {
+        for bit in (0 .. count).rev() {
+            let di = if value & (1 << bit) > 0 { EEPROM_DI } else { 0 };
+            mbc.write_ram(0x80, EEPROM_CS | di);
+            mbc.write_ram(0x80, EEPROM_CS | EEPROM_CLK | di);
+        }
+    }
+
+    fn receive_word(mbc: &mut MBC7) -> u16 {
+        let mut word = 0;
+        for _ in 0 .. 16 {
+            mbc.write_ram(0x80, EEPROM_CS);
+            mbc.write_ram(0x80, EEPROM_CS | EEPROM_CLK);
+            word = word << 1 | (mbc.read_ram(0x80) & 1) as u16;
+        }
+        word
+    }
+
+    fn deselect(mbc: &mut MBC7) {
+        mbc.write_ram(0x80, 0);
+    }
+
+    #[test]
+    fn test_accelerometer_latch() {
+        let mut mbc = enabled_mbc();
+        mbc.set_accelerometer(1.0, -1.0);
+        // Latching without erasing first does nothing
+        mbc.write_ram(0x10, 0xAA);
+        assert_eq!(mbc.read_ram(0x20), 0x00);
+        assert_eq!(mbc.read_ram(0x30), 0x80);
+        mbc.write_ram(0x00, 0x55);
+        mbc.write_ram(0x10, 0xAA);
+        assert_eq!(u16::from_le_bytes([mbc.read_ram(0x20), mbc.read_ram(0x30)]), 0x8240);
+        assert_eq!(u16::from_le_bytes([mbc.read_ram(0x40), mbc.read_ram(0x50)]), 0x8160);
+    }
+
+    #[test]
+    fn test_registers_need_both_enables() {
+        let mut mbc = MBC7::load_rom(&[], 4).unwrap();
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0x60), 0xFF);
+        mbc.write_rom(0x4000, 0x40);
+        assert_eq!(mbc.read_ram(0x60), 0x00);
+    }
+
+    #[test]
+    fn test_eeprom_write_and_read() {
+        let mut mbc = enabled_mbc();
+        // Writes are ignored until EWEN
+        send_bits(&mut mbc, 0b1_01_00000101, 11);
+        send_bits(&mut mbc, 0x1234, 16);
+        deselect(&mut mbc);
+        send_bits(&mut mbc, 0b1_00_11000000, 11);
+        deselect(&mut mbc);
+        send_bits(&mut mbc, 0b1_01_00000101, 11);
+        send_bits(&mut mbc, 0xBEEF, 16);
+        assert_eq!(mbc.read_ram(0x80) & 1, 1);
+        deselect(&mut mbc);
+
+        send_bits(&mut mbc, 0b1_10_00000101, 11);
+        assert_eq!(mbc.read_ram(0x80) & 1, 0);
+        assert_eq!(receive_word(&mut mbc), 0xBEEF);
+        assert_eq!(receive_word(&mut mbc), 0xFFFF);
+        deselect(&mut mbc);
+
+        let save = mbc.save_data().unwrap();
+        assert_eq!(save.len(), 256);
+        let mut reloaded = enabled_mbc();
+        reloaded.load_save_data(&save);
+        send_bits(&mut reloaded, 0b1_10_00000101, 11);
+        assert_eq!(receive_word(&mut reloaded), 0xBEEF);
+    }
+
+    #[test]
+    fn test_eeprom_erase_all() {
+        let mut mbc = enabled_mbc();
+        send_bits(&mut mbc, 0b1_00_11000000, 11);
+        deselect(&mut mbc);
+        send_bits(&mut mbc, 0b1_00_01000000, 11);
+        send_bits(&mut mbc, 0x5A5A, 16);
+        deselect(&mut mbc);
+        assert!(mbc.eeprom.words.iter().all(|word| *word == 0x5A5A));
+        send_bits(&mut mbc, 0b1_00_10000000, 11);
+        deselect(&mut mbc);
+        assert!(mbc.eeprom.words.iter().all(|word| *word == 0xFFFF));
+    }
+}
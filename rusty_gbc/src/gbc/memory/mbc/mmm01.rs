@@ -0,0 +1,187 @@
+use super::MemoryBank;
+
+/// MMM01 compilations start in unmapped mode running a menu from the last 32KB of ROM. The menu
+/// writes the bank registers, including bits that are only writable while unmapped, then sets
+/// the map enable bit which locks them so the selected game sees an MBC1 of its own size
+pub struct MMM01 {
+    rom_banks: Vec<Vec<u8>>,
+    ram_banks: Vec<Vec<u8>>,
+    mapped: bool,
+    ram_enabled: bool,
+    /// ROM bank bits 0-4, 5-6 and 7-8
+    rom_low: u8,
+    rom_mid: u8,
+    rom_high: u8,
+    /// ROM bank bits 1-4 that keep their value from before mapping
+    rom_mask: u8,
+    /// RAM bank bits 0-1 and 2-3
+    ram_low: u8,
+    ram_high: u8,
+    /// RAM bank bits 0-1 that keep their value from before mapping
+    ram_mask: u8,
+    mode: bool,
+    mode_writable: bool
+}
+
+impl MMM01 {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> MMM01 {
+        println!("MMM01");
+        let mut mbc = MMM01 {
+            rom_banks: vec![vec![0; 0x4000]; rom_bank_count.max(2) as usize],
+            ram_banks: vec![vec![0; ram_bank_size as usize]; ram_bank_count as usize],
+            mapped: false,
+            ram_enabled: false,
+            rom_low: 0,
+            rom_mid: 0,
+            rom_high: 0,
+            rom_mask: 0,
+            ram_low: 0,
+            ram_high: 0,
+            ram_mask: 0,
+            mode: false,
+            mode_writable: false
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
+            mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
+        }
+        mbc
+    }
+
+    fn outer_bank(&self) -> usize {
+        (self.rom_high as usize) << 7 | (self.rom_mid as usize) << 5
+    }
+
+    /// Bank mapped to 0x0000-0x3FFF, the lower bits are cleared apart from the masked ones
+    fn zero_bank(&self) -> usize {
+        let bank = match self.mapped {
+            true => self.outer_bank() | (self.rom_low & self.rom_mask << 1) as usize,
+            false => self.rom_banks.len() - 2
+        };
+        bank % self.rom_banks.len()
+    }
+
+    fn high_bank(&self) -> usize {
+        let bank = match self.mapped {
+            true => self.outer_bank() | self.rom_low as usize,
+            false => self.rom_banks.len() - 1
+        };
+        bank % self.rom_banks.len()
+    }
+
+    fn ram_address(&self, address: u16) -> Option<(usize, usize)> {
+        if !self.ram_enabled || self.ram_banks.is_empty() {
+            return None;
+        }
+        let low = if self.mode { self.ram_low } else { self.ram_low & self.ram_mask };
+        let bank = (self.ram_high << 2 | low) as usize % self.ram_banks.len();
+        Some((bank, address as usize % self.ram_banks[bank].len()))
+    }
+}
+
+/// Whether the last 32KB holds an MMM01 menu, where the header describing the cart is found
+pub fn is_mmm01(bytes: &[u8]) -> bool {
+    bytes.len() > 0x8000 && (0x0B ..= 0x0D).contains(&bytes[bytes.len() - 0x8000 + 0x147])
+}
+
+impl MemoryBank for MMM01 {
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0 ..= 0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_mask = (value >> 4) & 0b11;
+                    self.mapped = value & 0b01000000 > 0;
+                }
+            },
+            0x2000 ..= 0x3FFF => {
+                let locked = if self.mapped { self.rom_mask << 1 } else { 0 };
+                let mut low = (self.rom_low & locked) | (value & 0b00011111 & !locked);
+                // Like MBC1 the writable bits never select bank 0
+                if low & !locked & 0b00011111 == 0 {
+                    low |= 1;
+                }
+                self.rom_low = low;
+                if !self.mapped {
+                    self.rom_mid = (value >> 5) & 0b11;
+                }
+            },
+            0x4000 ..= 0x5FFF => {
+                let locked = if self.mapped { self.ram_mask } else { 0 };
+                self.ram_low = (self.ram_low & locked) | (value & 0b11 & !locked);
+                if !self.mapped {
+                    self.ram_high = (value >> 2) & 0b11;
+                    self.rom_high = (value >> 4) & 0b11;
+                    self.mode_writable = value & 0b01000000 > 0;
+                }
+            },
+            0x6000 ..= 0x7FFF => {
+                if self.mode_writable {
+                    self.mode = value & 1 > 0;
+                }
+                if !self.mapped {
+                    self.rom_mask = (value >> 2) & 0b1111;
+                }
+            },
+            _ => {}
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some((bank, offset)) = self.ram_address(address) {
+            self.ram_banks[bank][offset] = value;
+        }
+    }
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0 ..= 0x3FFF => self.rom_banks[self.zero_bank()][address as usize],
+            0x4000 ..= 0x7FFF => self.rom_banks[self.high_bank()][(address - 0x4000) as usize],
+            _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.ram_address(address) {
+            Some((bank, offset)) => self.ram_banks[bank][offset],
+            None => 0xFF
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_rom(bank_count: usize) -> Vec<u8> {
+        let mut rom = vec![0; bank_count * 0x4000];
+        for bank in 0 .. bank_count {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_starts_in_menu() {
+        let mut rom = numbered_rom(64);
+        rom[62 * 0x4000 + 0x147] = 0x0B;
+        assert!(is_mmm01(&rom));
+        assert!(!is_mmm01(&numbered_rom(64)));
+        let mbc = MMM01::load_rom(&rom, 64, 0, 0);
+        assert_eq!(mbc.read_rom(0x0000), 62);
+        assert_eq!(mbc.read_rom(0x4000), 63);
+    }
+
+    #[test]
+    fn test_mapping_a_game() {
+        let mut mbc = MMM01::load_rom(&numbered_rom(64), 64, 0, 0);
+        // Game of 8 banks at bank 0x28: bits 3-4 of the low bank and the mid bits are fixed
+        mbc.write_rom(0x2000, 0b0_01_01001);
+        mbc.write_rom(0x6000, 0b0011_0000);
+        mbc.write_rom(0x0000, 0b0100_0000);
+        assert_eq!(mbc.read_rom(0x0000), 0x28);
+        assert_eq!(mbc.read_rom(0x4000), 0x29);
+        mbc.write_rom(0x2000, 0x07);
+        assert_eq!(mbc.read_rom(0x4000), 0x2F);
+        // Bits locked by the mask and the mid bits no longer change
+        mbc.write_rom(0x2000, 0b0_10_10011);
+        assert_eq!(mbc.read_rom(0x4000), 0x2B);
+        assert_eq!(mbc.read_rom(0x0000), 0x28);
+    }
+}
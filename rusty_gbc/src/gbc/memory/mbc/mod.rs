@@ -2,9 +2,22 @@ use std::str;
 
 mod mbc1;
 mod mbc5;
+mod mbc7;
+mod huc1;
+mod huc3;
+mod mmm01;
+mod unlicensed;
+mod pocket_camera;
 
 use mbc1::MBC1;
 use mbc5::MBC5;
+use mbc7::MBC7;
+use huc1::HuC1;
+use huc3::HuC3;
+use mmm01::MMM01;
+use unlicensed::{WisdomTree, SachenMmc1, BootlegMbc1};
+use pocket_camera::PocketCamera;
+use crate::gbc::camera::ImageSource;
 
 const MEMORY_BANK_TYPE_ADDRESS: u16 = 0x0147;
 const ROM_SIZE_ADDRESS: u16 = 0x0148;
@@ -16,17 +29,47 @@ pub trait MemoryBank {
     fn write_ram(&mut self, address: u16, value: u8);
     fn read_rom(&self, address: u16) -> u8;
     fn read_ram(&self, address: u16) -> u8;
+    /// CPU reads of ROM while the boot ROM is mapped, for carts that watch them. Other reads, such
+    /// as from debuggers, go through `read_rom` and have no side effects
+    fn read_rom_booting(&self, address: u16) -> u8 { self.read_rom(address) }
+    /// Called when the boot ROM is unmapped or skipped
+    fn finish_boot(&mut self) {}
+    /// Whether a rumble motor is currently turned on
+    fn rumble(&self) -> bool { false }
+    /// Tilt in g for carts with an accelerometer, positive x to the right and positive y towards the player
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {}
+    /// Contents of battery backed memory, `None` if the cart has none
+    fn save_data(&self) -> Option<Vec<u8>> { None }
+    fn load_save_data(&mut self, _data: &[u8]) {}
+    /// Advances carts with their own clock
+    fn step(&mut self, _cycles: u8) {}
+    /// Replaces what the Pocket Camera sensor sees
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
 }
 
 impl dyn MemoryBank {
-    pub fn new(rom_bytes: Vec<u8>) -> Box<dyn MemoryBank> {
-        let mbc_type = rom_bytes[MEMORY_BANK_TYPE_ADDRESS as usize];
-        let rom_size = rom_bytes[ROM_SIZE_ADDRESS as usize];
+    /// Picks the memory bank controller from the cartridge header, failing for unsupported carts
+    pub fn new(rom_bytes: Vec<u8>) -> Result<Box<dyn MemoryBank>, String> {
+        // Too short to have a header, the boot ROM rejects it anyway
+        if rom_bytes.len() < 0x150 {
+            return Ok(NoMBC::load_rom(&rom_bytes));
+        }
+        // Bootleg and unlicensed carts often declare less ROM than they have
+        let file_bank_count = (rom_bytes.len().saturating_sub(1) / 0x4000 + 1).next_power_of_two().max(2) as u16;
+        // Sachen headers are scrambled, only the logo after them is reliable
+        if unlicensed::is_sachen(&rom_bytes) {
+            return Ok(Box::new(SachenMmc1::load_rom(&rom_bytes, file_bank_count)));
+        }
+        // MMM01 carts boot into a menu in the last 32KB, which holds the header describing the cart
+        let header = if mmm01::is_mmm01(&rom_bytes) { rom_bytes.len() - 0x8000 } else { 0 };
+        let mbc_type = rom_bytes[header + MEMORY_BANK_TYPE_ADDRESS as usize];
+        let rom_size = rom_bytes[header + ROM_SIZE_ADDRESS as usize];
         let rom_bank_count: u16 = match rom_size {
             0 ..= 8 => ((0x8000 << rom_size as usize) / 0x4000) as u16,
-            _ => panic!("Unsupported ROM size {:02X}", rom_size)
+            _ => return Err(format!("Unsupported ROM size ${:02X} in the cartridge header", rom_size))
         };
-        let ram_size = rom_bytes[RAM_SIZE_ADDRESS as usize];
+        let rom_bank_count = rom_bank_count.max(file_bank_count);
+        let ram_size = rom_bytes[header + RAM_SIZE_ADDRESS as usize];
         let (ram_bank_count, ram_bank_size) = match ram_size {
             0 => (0, 0),
             1 => (1, 0x800),
@@ -34,48 +77,80 @@ impl dyn MemoryBank {
             3 => (4, 0x2000),
             4 => (16, 0x2000),
             5 => (8, 0x2000),
-            _ => panic!("Unsupported RAM size {:02X}", ram_size)
+            _ => return Err(format!("Unsupported RAM size ${:02X} in the cartridge header", ram_size))
         };
-        match mbc_type {
+        let mbc: Box<dyn MemoryBank> = match mbc_type {
+            _ if unlicensed::is_wisdom_tree(&rom_bytes) => Box::new(WisdomTree::load_rom(&rom_bytes, rom_bank_count)),
             0 => NoMBC::load_rom(&rom_bytes),
-            1 ..= 3 => Box::new(MBC1::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)),
-            0x19 ..= 0x1E => Box::new(MBC5::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)),
-            _ => panic!("not implemented {}", mbc_type)
-        }
+            // MBC1 reaches only 2MB, bigger carts use a bootleg clone with a wider bank register
+            1 ..= 3 if rom_bank_count > 128 => Box::new(BootlegMbc1::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)?),
+            1 ..= 3 => Box::new(MBC1::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)?),
+            0x0B ..= 0x0D => Box::new(MMM01::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)),
+            0x19 ..= 0x1B => Box::new(MBC5::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size, false)?),
+            0x1C ..= 0x1E => Box::new(MBC5::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size, true)?),
+            0x22 => Box::new(MBC7::load_rom(&rom_bytes, rom_bank_count)?),
+            0xFC => Box::new(PocketCamera::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)),
+            0xFE => Box::new(HuC3::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)?),
+            0xFF => Box::new(HuC1::load_rom(&rom_bytes, rom_bank_count, ram_bank_count, ram_bank_size)?),
+            _ => return Err(format!("Unsupported cartridge type ${:02X} ({})", mbc_type, cartridge_type_name(mbc_type)))
+        };
+        Ok(mbc)
     }
 
     pub fn print_metadata(&self) {
         let bytes: Vec<u8> = (1..16).map(|x| self.read_rom(TITLE_ADDRESS_MINUS_1 + x)).collect();
         println!("{}", str::from_utf8(&bytes).unwrap());
-        // match &self.bytes[0x0147] {
-        //     0x00 => println!("ROM ONLY"),
-        //     0x01 => println!("MBC 1"),
-        //     0x02 => println!("MBC1+RAM"),
-        //     0x03 => println!("MBC1+RAM+BATTERY"),
-        //     0x05 => println!("MBC2"),
-        //     0x06 => println!("MBC2+BATTERY"),
-        //     0x08 => println!("ROM+RAM"),
-        //     0x09 => println!("ROM+RAM+BATTERY"),
-        //     0x0B => println!("MMM01"),
-        //     0x0C => println!("MMM01+RAM"),
-        //     0x0D => println!("MMM01+RAM+BATTERY"),
-        //     0x0F => println!("MBC3+TIMER+BATTERY"),
-        //     0x10 => println!("MBC3+TIMER+RAM+BATTERY"),
-        //     0x11 => println!("MBC3"),
-        //     0x12 => println!("MBC3+RAM"),
-        //     0x13 => println!("MBC3+RAM+BATTERY"),
-        //     0x19 => println!("MBC5"),
-        //     0x1A => println!("MBC5+RAM"),
-        //     0x1B => println!("MBC5+RAM+BATTERY"),
-        //     0x1C => println!("MBC5+RUMBLE"),
-        //     0x1D => println!("MBC5+RUMBLE+RAM"),
-        //     0x1E => println!("MBC5+RUMBLE+RAM+BATTERY"),
-        //     0x20 => println!("MBC6"),
-        //     0x22 => println!("MBC7+SENSOR+RUMBLE+RAM+BATTERY"),
-        //     _ => println!("unrecognized cart type")
     }
 }
 
+/// Name of a cartridge type from header byte 0x147
+fn cartridge_type_name(mbc_type: u8) -> &'static str {
+    match mbc_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "unrecognized cart type"
+    }
+}
+
+/// Copies save data into RAM banks of any size, in order. Banks past the end of a short save are
+/// left as they are
+fn load_ram_banks(banks: &mut [Vec<u8>], data: &[u8]) {
+    let mut offset = 0;
+    for bank in banks.iter_mut() {
+        if offset >= data.len() {
+            break;
+        }
+        let len = bank.len().min(data.len() - offset);
+        bank[.. len].copy_from_slice(&data[offset .. offset + len]);
+        offset += bank.len();
+    }
+}
 
 struct NoMBC {
     rom: [u8; 0x8000],
@@ -88,7 +163,7 @@ impl NoMBC {
             rom: [0; 0x8000],
             ram: [0; 0x2000]
         });
-        for (idx, byte) in bytes.iter().enumerate() {
+        for (idx, byte) in bytes.iter().enumerate().take(0x8000) {
             mbc.rom[idx] = *byte;
         }
         mbc
@@ -0,0 +1,220 @@
+use super::MemoryBank;
+use crate::gbc::camera::{ImageSource, TestPattern, CAMERA_WIDTH, CAMERA_HEIGHT};
+
+/// Selecting a RAM bank with this bit maps the sensor registers instead
+const CAMERA_REGISTERS: u8 = 0x10;
+const REGISTER_COUNT: usize = 0x36;
+/// Where the camera ROM expects a finished picture in RAM bank 0, as 16x14 tiles
+const IMAGE_ADDRESS: usize = 0x100;
+/// Start of the 4x4 matrix of 3 thresholds per pixel used to dither the picture
+const DITHER_MATRIX: usize = 0x06;
+/// Edge enhancement strength selected by bits 4-6 of register 4
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// Pocket Camera mapper with a Mitsubishi M64282FP sensor. Writing bit 0 of register 0 takes a
+/// picture from the `ImageSource`, which is exposed, edge enhanced and dithered to 2bpp tiles
+/// using the registers. The analog gain and exposure are approximated linearly
+pub struct PocketCamera {
+    rom_banks: Vec<Vec<u8>>,
+    ram_banks: Vec<Vec<u8>>,
+    selected_rom: u8,
+    selected_ram: u8,
+    ram_write_enabled: bool,
+    registers: [u8; REGISTER_COUNT],
+    /// Cycles until the picture being taken is finished, 0 when idle
+    capture_cycles: u32,
+    source: Box<dyn ImageSource>
+}
+
+impl PocketCamera {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> PocketCamera {
+        println!("Pocket Camera");
+        let mut mbc = PocketCamera {
+            rom_banks: vec![vec![0; 0x4000]; rom_bank_count as usize],
+            // Pictures are stored in 128KB of RAM, even if the header says less
+            ram_banks: vec![vec![0; ram_bank_size.max(0x2000) as usize]; ram_bank_count.max(16) as usize],
+            selected_rom: 1,
+            selected_ram: 0,
+            ram_write_enabled: false,
+            registers: [0; REGISTER_COUNT],
+            capture_cycles: 0,
+            source: Box::new(TestPattern::new())
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
+            mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
+        }
+        mbc
+    }
+
+    fn exposure(&self) -> u32 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]]) as u32
+    }
+
+    fn start_capture(&mut self) {
+        let n_bit = self.registers[1] & 0x80 > 0;
+        self.capture_cycles = 4 * (32446 + if n_bit { 0 } else { 512 } + 16 * self.exposure());
+    }
+
+    fn finish_capture(&mut self) {
+        let mut sensor = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        self.source.capture(&mut sensor);
+
+        let gain = 1.0 + (self.registers[1] & 0x1F) as f32 / 8.0;
+        let exposure = self.exposure() as f32 / 0x1000 as f32;
+        let exposed: Vec<f32> = sensor.iter().map(|pixel| *pixel as f32 * exposure * gain).collect();
+        let edge_enhance = self.registers[4] & 0x80 > 0;
+        let edge_ratio = EDGE_RATIOS[((self.registers[4] >> 4) & 0b111) as usize];
+        let invert = self.registers[4] & 0x08 > 0;
+
+        let image = &mut self.ram_banks[0][IMAGE_ADDRESS ..];
+        for y in 0 .. CAMERA_HEIGHT {
+            for x in 0 .. CAMERA_WIDTH {
+                let at = |x: usize, y: usize| exposed[y * CAMERA_WIDTH + x];
+                let mut value = at(x, y);
+                if edge_enhance {
+                    let neighbours = at(x.saturating_sub(1), y) + at((x + 1).min(CAMERA_WIDTH - 1), y)
+                        + at(x, y.saturating_sub(1)) + at(x, (y + 1).min(CAMERA_HEIGHT - 1));
+                    value += edge_ratio * (4.0 * value - neighbours);
+                }
+                if invert {
+                    value = 255.0 - value;
+                }
+                let value = if value <= 0.0 { 0 } else if value >= 255.0 { 255 } else { value as u8 };
+
+                let cell = DITHER_MATRIX + ((y % 4) * 4 + x % 4) * 3;
+                let thresholds = &self.registers[cell .. cell + 3];
+                let color = if value < thresholds[0] { 3 } else if value < thresholds[1] { 2 } else if value < thresholds[2] { 1 } else { 0 };
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let offset = tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                image[offset] = if color & 1 > 0 { image[offset] | bit } else { image[offset] & !bit };
+                image[offset + 1] = if color & 2 > 0 { image[offset + 1] | bit } else { image[offset + 1] & !bit };
+            }
+        }
+        self.registers[0] &= !1;
+    }
+
+    fn ram_bank(&self) -> usize {
+        (self.selected_ram & 0x0F) as usize % self.ram_banks.len()
+    }
+}
+
+impl MemoryBank for PocketCamera {
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0 ..= 0x1FFF => self.ram_write_enabled = value & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.selected_rom = value & 0b00111111,
+            0x4000 ..= 0x5FFF => self.selected_ram = value & 0b00011111,
+            _ => {}
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.selected_ram & CAMERA_REGISTERS > 0 {
+            let register = (address & 0x7F) as usize;
+            match register {
+                0 => {
+                    self.registers[0] = value & 0b111;
+                    if value & 1 > 0 && self.capture_cycles == 0 {
+                        self.start_capture();
+                    }
+                },
+                1 ..= 0x35 => self.registers[register] = value,
+                _ => {}
+            }
+        } else if self.ram_write_enabled && self.capture_cycles == 0 {
+            let bank = self.ram_bank();
+            self.ram_banks[bank][address as usize] = value;
+        }
+    }
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0 ..= 0x3FFF => self.rom_banks[0][address as usize],
+            0x4000 ..= 0x7FFF => {
+                self.rom_banks[self.selected_rom as usize % self.rom_banks.len()][(address - 0x4000) as usize]
+            },
+            _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.selected_ram & CAMERA_REGISTERS > 0 {
+            // Only register 0 can be read, bit 0 stays set while a picture is being taken
+            return if address & 0x7F == 0 { self.registers[0] } else { 0x00 };
+        }
+        match self.capture_cycles {
+            0 => self.ram_banks[self.ram_bank()][address as usize],
+            _ => 0x00
+        }
+    }
+    fn step(&mut self, cycles: u8) {
+        if self.capture_cycles > 0 {
+            self.capture_cycles = self.capture_cycles.saturating_sub(cycles as u32);
+            if self.capture_cycles == 0 {
+                self.finish_capture();
+            }
+        }
+    }
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram_banks.concat())
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram_banks(&mut self.ram_banks, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gbc::camera::StaticImage;
+
+    fn capture(camera: &mut PocketCamera) {
+        camera.write_rom(0x4000, CAMERA_REGISTERS);
+        camera.write_ram(0x0000, 0x01);
+        assert_eq!(camera.read_ram(0x0000) & 1, 1);
+        while camera.read_ram(0x0000) & 1 > 0 {
+            camera.step(4);
+        }
+        camera.write_rom(0x4000, 0x00);
+    }
+
+    #[test]
+    fn test_capture_dithers_into_tiles() {
+        let mut camera = PocketCamera::load_rom(&[], 4, 16, 0x2000);
+        // Left half black, right half white
+        let luminance: Vec<u8> = (0 .. 2).map(|x| x * 255).collect();
+        camera.set_image_source(Box::new(StaticImage::new(2, 1, &luminance).unwrap()));
+        camera.write_rom(0x4000, CAMERA_REGISTERS);
+        // Exposure of 1.0 and the same thresholds for every matrix cell
+        camera.write_ram(0x0002, 0x10);
+        camera.write_ram(0x0003, 0x00);
+        for cell in 0 .. 16 {
+            camera.write_ram(0x0006 + cell * 3, 0x40);
+            camera.write_ram(0x0007 + cell * 3, 0x80);
+            camera.write_ram(0x0008 + cell * 3, 0xC0);
+        }
+        capture(&mut camera);
+
+        // Tile 0 is black and tile 15 at the right edge is white
+        assert_eq!(camera.read_ram(0x100), 0xFF);
+        assert_eq!(camera.read_ram(0x101), 0xFF);
+        assert_eq!(camera.read_ram(0x100 + 15 * 16), 0x00);
+        assert_eq!(camera.read_ram(0x101 + 15 * 16), 0x00);
+    }
+
+    #[test]
+    fn test_ram_is_unavailable_while_capturing() {
+        let mut camera = PocketCamera::load_rom(&[], 4, 16, 0x2000);
+        camera.write_rom(0x0000, 0x0A);
+        camera.write_ram(0x1000, 0x12);
+        camera.write_rom(0x4000, CAMERA_REGISTERS);
+        camera.write_ram(0x0000, 0x01);
+        camera.write_rom(0x4000, 0x00);
+        assert_eq!(camera.read_ram(0x1000), 0x00);
+        camera.step(4);
+        capture(&mut camera);
+        assert_eq!(camera.read_ram(0x1000), 0x12);
+    }
+}
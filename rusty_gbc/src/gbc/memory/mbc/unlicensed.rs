@@ -0,0 +1,314 @@
+use super::MemoryBank;
+use crate::gbc::boot::NINTENDO_LOGO;
+use std::cell::Cell;
+
+/// Where Sachen carts keep the real logo, the boot ROM is redirected here while the cart is locked
+const SACHEN_LOGO_ADDRESS: usize = 0x184;
+/// Reads from 0x0100-0x01FF that are redirected before a Sachen cart unlocks
+const SACHEN_LOCKED_READS: u8 = 0x30;
+
+const WISDOM_TREE: &[u8] = b"WISDOM TREE";
+const WISDOM_TREE_NUL: &[u8] = b"WISDOM\0TREE";
+
+/// Wisdom Tree carts switch all 32KB at once, the bank is taken from the low byte of the address
+/// written to in 0x0000-0x3FFF and the value written is ignored
+pub struct WisdomTree {
+    rom_banks: Vec<Vec<u8>>,
+    selected_rom: u8
+}
+
+impl WisdomTree {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16) -> WisdomTree {
+        println!("Wisdom Tree");
+        let mut mbc = WisdomTree {
+            rom_banks: vec![vec![0; 0x8000]; (rom_bank_count as usize / 2).max(1)],
+            selected_rom: 0
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x8000) {
+            mbc.rom_banks[idx / 0x8000][idx % 0x8000] = *byte;
+        }
+        mbc
+    }
+}
+
+/// Wisdom Tree carts claim to have no MBC and 32KB of ROM but are larger, and name the publisher
+/// in their first 32KB. The name keeps padded or over-dumped ROM only carts from matching
+pub fn is_wisdom_tree(bytes: &[u8]) -> bool {
+    let no_mbc = bytes[0x147] == 0x00 || bytes[0x147] == 0xC0;
+    let declared_32kb = bytes[0x148] == 0x00;
+    let names_publisher = bytes[.. bytes.len().min(0x8000)].windows(WISDOM_TREE.len())
+        .any(|window| window == WISDOM_TREE || window == WISDOM_TREE_NUL);
+    no_mbc && declared_32kb && bytes.len() >= 0x10000 && names_publisher
+}
+
+impl MemoryBank for WisdomTree {
+    fn write_rom(&mut self, address: u16, _value: u8) {
+        if address < 0x4000 {
+            self.selected_rom = address as u8;
+        }
+    }
+    fn write_ram(&mut self, _address: u16, _value: u8) {}
+    fn read_rom(&self, address: u16) -> u8 {
+        self.rom_banks[self.selected_rom as usize % self.rom_banks.len()][address as usize]
+    }
+    fn read_ram(&self, _address: u16) -> u8 {
+        0xFF
+    }
+}
+
+/// Bootleg MBC1 clone on carts larger than the 2MB MBC1 can address. The whole ROM bank number is
+/// written to 0x2000-0x3FFF, bank 0 still selecting bank 1, and 0x4000-0x5FFF only selects the RAM bank
+pub struct BootlegMbc1 {
+    rom_banks: Vec<Vec<u8>>,
+    ram_banks: Vec<Vec<u8>>,
+    selected_rom: u8,
+    selected_ram: u8,
+    ram_enabled: bool
+}
+
+impl BootlegMbc1 {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16, ram_bank_count: u8, ram_bank_size: u16) -> Result<BootlegMbc1, String> {
+        println!("Bootleg MBC1");
+        if rom_bank_count > 256 {
+            return Err(format!("Bootleg MBC1 does not support {} ROM banks", rom_bank_count));
+        }
+        let mut mbc = BootlegMbc1 {
+            rom_banks: vec![vec![0; 0x4000]; rom_bank_count as usize],
+            ram_banks: vec![vec![0; ram_bank_size as usize]; ram_bank_count as usize],
+            selected_rom: 1,
+            selected_ram: 0,
+            ram_enabled: false
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
+            mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
+        }
+        Ok(mbc)
+    }
+
+    fn ram_address(&self, address: u16) -> Option<(usize, usize)> {
+        if !self.ram_enabled || self.ram_banks.is_empty() {
+            return None;
+        }
+        let bank_size = self.ram_banks[0].len();
+        Some((self.selected_ram as usize % self.ram_banks.len(), address as usize % bank_size))
+    }
+}
+
+impl MemoryBank for BootlegMbc1 {
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0 ..= 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.selected_rom = if value == 0 { 1 } else { value },
+            0x4000 ..= 0x5FFF => self.selected_ram = value & 0b11,
+            _ => {}
+        }
+    }
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some((bank, offset)) = self.ram_address(address) {
+            self.ram_banks[bank][offset] = value;
+        }
+    }
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0 ..= 0x3FFF => self.rom_banks[0][address as usize],
+            0x4000 ..= 0x7FFF => {
+                self.rom_banks[self.selected_rom as usize % self.rom_banks.len()][(address - 0x4000) as usize]
+            },
+            _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
+        }
+    }
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.ram_address(address) {
+            Some((bank, offset)) => self.ram_banks[bank][offset],
+            None => 0xFF
+        }
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram_banks.concat())
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        super::load_ram_banks(&mut self.ram_banks, data);
+    }
+}
+
+/// Sachen MMC1. Multicart menus pick a game with a base bank and a mask of the bank bits the base
+/// decides, both only writable while the high bits of the current bank are set. The cart starts
+/// locked and serves the logo from 0x0184 so the boot ROM accepts it without the header containing it
+pub struct SachenMmc1 {
+    rom_banks: Vec<Vec<u8>>,
+    selected_rom: u8,
+    base_rom: u8,
+    mask: u8,
+    locked_reads: Cell<u8>
+}
+
+impl SachenMmc1 {
+    pub fn load_rom(bytes: &[u8], rom_bank_count: u16) -> SachenMmc1 {
+        println!("Sachen MMC1");
+        let mut mbc = SachenMmc1 {
+            rom_banks: vec![vec![0; 0x4000]; rom_bank_count.max(2) as usize],
+            selected_rom: 1,
+            base_rom: 0,
+            mask: 0,
+            locked_reads: Cell::new(0)
+        };
+        for (idx, byte) in bytes.iter().enumerate().take(mbc.rom_banks.len() * 0x4000) {
+            mbc.rom_banks[idx / 0x4000][idx % 0x4000] = *byte;
+        }
+        mbc
+    }
+
+    fn bank(&self, bank: u8) -> usize {
+        ((self.base_rom & self.mask) | (bank & !self.mask)) as usize % self.rom_banks.len()
+    }
+
+    fn registers_writable(&self) -> bool {
+        self.selected_rom & 0x30 == 0x30
+    }
+}
+
+/// Sachen carts keep the Nintendo logo after the header instead of in it
+pub fn is_sachen(bytes: &[u8]) -> bool {
+    bytes.len() >= SACHEN_LOGO_ADDRESS + NINTENDO_LOGO.len()
+        && bytes[0x104 .. 0x104 + NINTENDO_LOGO.len()] != NINTENDO_LOGO[..]
+        && bytes[SACHEN_LOGO_ADDRESS .. SACHEN_LOGO_ADDRESS + NINTENDO_LOGO.len()] == NINTENDO_LOGO[..]
+}
+
+impl MemoryBank for SachenMmc1 {
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0 ..= 0x1FFF if self.registers_writable() => self.base_rom = value,
+            0x2000 ..= 0x3FFF => self.selected_rom = if value == 0 { 1 } else { value },
+            0x4000 ..= 0x5FFF if self.registers_writable() => self.mask = value,
+            _ => {}
+        }
+    }
+    fn write_ram(&mut self, _address: u16, _value: u8) {}
+    fn read_rom(&self, address: u16) -> u8 {
+        let mut address = address as usize;
+        if self.locked_reads.get() < SACHEN_LOCKED_READS && address & 0xFF00 == 0x0100 {
+            // A7 is held high while locked, moving reads of the header logo to 0x0184
+            address |= 0x80;
+        }
+        match address {
+            0 ..= 0x3FFF => self.rom_banks[self.bank(0)][address],
+            0x4000 ..= 0x7FFF => self.rom_banks[self.bank(self.selected_rom)][address - 0x4000],
+            _ => panic!("ROM goes only to 0x7FFF, tried to read outside bounds")
+        }
+    }
+    fn read_ram(&self, _address: u16) -> u8 {
+        0xFF
+    }
+    fn read_rom_booting(&self, address: u16) -> u8 {
+        let value = self.read_rom(address);
+        let locked_reads = self.locked_reads.get();
+        if locked_reads < SACHEN_LOCKED_READS && address & 0xFF00 == 0x0100 {
+            self.locked_reads.set(locked_reads + 1);
+        }
+        value
+    }
+    fn finish_boot(&mut self) {
+        self.locked_reads.set(SACHEN_LOCKED_READS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_rom(bank_count: usize) -> Vec<u8> {
+        let mut rom = vec![0; bank_count * 0x4000];
+        for bank in 0 .. bank_count {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_wisdom_tree_switches_32kb() {
+        let mut rom = numbered_rom(8);
+        rom[0x200 .. 0x20B].copy_from_slice(WISDOM_TREE);
+        assert!(is_wisdom_tree(&rom));
+        rom[0x147] = 0x01;
+        assert!(!is_wisdom_tree(&rom));
+        let mut mbc = WisdomTree::load_rom(&numbered_rom(8), 8);
+        mbc.write_rom(0x0002, 0xFF);
+        assert_eq!(mbc.read_rom(0x0000), 4);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+    }
+
+    #[test]
+    fn test_rom_only_carts_are_not_wisdom_tree() {
+        // A 64KB ROM only cart declaring its size correctly
+        let mut rom = numbered_rom(4);
+        rom[0x148] = 0x01;
+        assert!(!is_wisdom_tree(&rom));
+        // Padded to 64KB but without the publisher name
+        rom[0x148] = 0x00;
+        assert!(!is_wisdom_tree(&rom));
+        rom[0x200 .. 0x20B].copy_from_slice(WISDOM_TREE_NUL);
+        assert!(is_wisdom_tree(&rom));
+    }
+
+    #[test]
+    fn test_bootleg_mbc1_takes_whole_bank_number() {
+        let mut rom = numbered_rom(256);
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x03;
+        // Detected from a cart declaring MBC1 with more than 2MB of ROM
+        let mut mbc = <dyn MemoryBank>::new(rom).unwrap();
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        // Banks MBC1 would remap or could only reach through BANK2
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x20);
+        mbc.write_rom(0x2000, 0xC5);
+        assert_eq!(mbc.read_rom(0x4000), 0xC5);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 2);
+        mbc.write_ram(0x0010, 0x34);
+        mbc.write_rom(0x4000, 0);
+        assert_eq!(mbc.read_ram(0x0010), 0x00);
+        mbc.write_rom(0x4000, 2);
+        assert_eq!(mbc.read_ram(0x0010), 0x34);
+    }
+
+    #[test]
+    fn test_sachen_unlocks_only_from_boot_reads() {
+        let mut rom = numbered_rom(2);
+        rom[SACHEN_LOGO_ADDRESS .. SACHEN_LOGO_ADDRESS + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        let mut mbc = SachenMmc1::load_rom(&rom, 2);
+        for _ in 0 .. SACHEN_LOCKED_READS {
+            assert_eq!(mbc.read_rom(0x0104), NINTENDO_LOGO[0]);
+        }
+        assert_eq!(mbc.read_rom_booting(0x0104), NINTENDO_LOGO[0]);
+        mbc.finish_boot();
+        assert_eq!(mbc.read_rom(0x0104), 0);
+    }
+
+    #[test]
+    fn test_sachen_logo_and_banking() {
+        let mut rom = numbered_rom(16);
+        rom[SACHEN_LOGO_ADDRESS .. SACHEN_LOGO_ADDRESS + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        assert!(is_sachen(&rom));
+        let mut mbc = SachenMmc1::load_rom(&rom, 16);
+        // The boot ROM sees the logo at 0x0104 until the cart unlocks
+        assert_eq!(mbc.read_rom_booting(0x0104), NINTENDO_LOGO[0]);
+        for _ in 1 .. SACHEN_LOCKED_READS {
+            mbc.read_rom_booting(0x0104);
+        }
+        assert_eq!(mbc.read_rom_booting(0x0104), 0);
+
+        // Base and mask are ignored until the bank has bits 4-5 set
+        mbc.write_rom(0x0000, 0x08);
+        assert_eq!(mbc.base_rom, 0);
+        mbc.write_rom(0x2000, 0x30);
+        mbc.write_rom(0x0000, 0x08);
+        mbc.write_rom(0x4000, 0x38);
+        mbc.write_rom(0x2000, 0x03);
+        assert_eq!(mbc.read_rom(0x0000), 0x08);
+        assert_eq!(mbc.read_rom(0x4000), 0x0B);
+    }
+}
@@ -1,12 +1,17 @@
 extern crate rand;
 
 use super::Registers;
+use super::V_BLANK_INTERRUPT;
 use super::memory::ram::Ram;
 use super::memory::mbc::MemoryBank;
-use std::str;
 use super::gpu::Gpu;
 use super::input::Input;
 use super::timer::Timer;
+use super::model::Model;
+use super::sgb::{self, Sgb};
+use super::camera::ImageSource;
+use super::serial::{Serial, SerialDevice, StdoutSerial};
+use super::boot::{DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE, NINTENDO_LOGO, REGISTERED_TILE};
 
 const ROM_START: u16 = 0;
 const ROM_END: u16 = 0x7FFF;
@@ -33,74 +38,233 @@ pub const INTERUPT_REQUEST: u16 = 0xFF0F;
 pub struct Mmu {
     boot_rom: Vec<u8>,
     pub gpu: Box<Gpu>,
+    /// OAM DMA currently transferring, the CPU bus is blocked while this is active
     dma: Option<Dma>,
+    /// OAM DMA waiting out its start delay, a running transfer continues until it starts
+    dma_pending: Option<Dma>,
+    dma_register: u8,
+    dma_written: bool,
     hdma: Hdma,
     mbc: Box<dyn MemoryBank>,
     wram: Vec<Ram>,
     pub input: Input,
+    /// Only present when an SGB enhanced cartridge runs on the SGB model
+    sgb: Option<Sgb>,
     timer: Timer,
+    serial: Serial,
     io: Ram,
     hram: Ram,
     interupt_switch: u8,
     wram_select: u8,
     pub booting: bool,
-    pub prepare_doublespeed: bool
+    pub prepare_doublespeed: bool,
+    model: Model,
+    /// Cycles the cartridge rumble motor was on and cycles run since the last `take_rumble`
+    rumble_cycles: u64,
+    rumble_total_cycles: u64
 }
 
 impl Mmu {
-    pub fn new(rom_bytes: Vec<u8>, gpu: Box<Gpu>) -> Mmu {
+    pub fn new(rom_bytes: Vec<u8>, gpu: Box<Gpu>) -> Result<Mmu, String> {
         if gpu.color_mode {
             println!("Color");
         }
         
-        let mbc = MemoryBank::new(rom_bytes);
+        let model = gpu.model();
+        let mut serial = Serial::new(gpu.color_mode);
+        serial.connect(Box::new(StdoutSerial));
+        let sgb = if model == Model::Sgb && sgb::supports_sgb(&rom_bytes) { Some(Sgb::new()) } else { None };
+        let mbc = MemoryBank::new(rom_bytes)?;
         let mut wram = Vec::new();
-        for _ in 0 .. if gpu.color_mode { 8 } else { 2 } {   
+        for _ in 0 .. model.wram_banks() {
             wram.push(Ram::new(0x2000));
         }
 
-        let boot_rom = if gpu.color_mode { super::boot::load_cgb_rom() } else { super::boot::load_rom() };
+        let boot_rom = if model.is_color() { super::boot::load_cgb_rom() } else { super::boot::load_rom() };
 
-        Mmu {
+        Ok(Mmu {
             boot_rom,
             mbc,
             gpu,
             dma: None,
+            dma_pending: None,
+            dma_register: 0,
+            dma_written: false,
             hdma: Hdma::new(),
             wram: wram,
             input: Input::new(),
+            sgb,
             timer: Timer::new(),
+            serial,
             io: Ram::new(0x80),
             hram: Ram::new(0x7F),
             interupt_switch: 0,
             wram_select: 0,
             booting: true,
-            prepare_doublespeed: false
+            prepare_doublespeed: false,
+            model,
+            rumble_cycles: 0,
+            rumble_total_cycles: 0
+        })
+    }
+
+    /// Replaces the built-in boot ROM with a dump of the one matching the model
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), String> {
+        // Color models run their CGB boot ROM even for DMG cartridges
+        let expected = if self.model.is_color() { CGB_BOOT_ROM_SIZE } else { DMG_BOOT_ROM_SIZE };
+        if boot_rom.len() != expected {
+            return Err(format!("Boot ROM is {} bytes, expected {} bytes for the {} model",
+                boot_rom.len(), expected, format!("{:?}", self.model).to_uppercase()));
         }
+        self.boot_rom = boot_rom;
+        Ok(())
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn sgb(&self) -> Option<&Sgb> {
+        self.sgb.as_ref()
+    }
+
+    /// Unmaps the boot ROM and sets IO, timer and PPU state to the values the boot ROM leaves
+    pub(crate) fn skip_boot(&mut self) {
+        let model = self.model;
+        self.booting = false;
+        // Audio registers as the boot ROM leaves them
+        let audio = [
+            (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0xBF),
+            (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+            (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+            (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF),
+            (0xFF24, 0x77), (0xFF25, 0xF3), (0xFF26, 0xF1)
+        ];
+        for &(address, value) in audio.iter() {
+            self.io.write(address - IO_START, value);
+        }
+        self.serial.write_control(0x7E);
+        self.io.write(INTERUPT_REQUEST - IO_START, V_BLANK_INTERRUPT);
+        self.dma_register = if model.is_color() { 0x00 } else { 0xFF };
+        // Approximate internal counter values when the boot ROM hands over, DMG/MGB read DIV as $AB
+        self.timer.set_system_counter(if model.is_color() { 0x1EA0 } else { 0xABCC });
+        self.gpu.skip_boot();
+        if !model.is_color() {
+            self.draw_boot_logo();
+        }
+        self.mbc.finish_boot();
+        self.mbc.print_metadata();
+    }
+
+    /// Leaves the header logo and (R) tiles and their tile map in VRAM like the DMG boot ROM.
+    /// Each logo nibble becomes two rows of a tile with every bit doubled in width
+    fn draw_boot_logo(&mut self) {
+        let double_bits = |nibble: u8| (0 .. 4).fold(0u8, |byte, bit| {
+            if nibble & (0b1000 >> bit) > 0 { byte | (0b11000000 >> (bit * 2)) } else { byte }
+        });
+        let logo: Vec<u8> = (0 .. NINTENDO_LOGO.len() as u16).map(|i| self.mbc.read_rom(0x104 + i)).collect();
+        let mut address = 0x0010;
+        for byte in logo {
+            for &nibble in [byte >> 4, byte & 0x0F].iter() {
+                let row = double_bits(nibble);
+                for _ in 0 .. 2 {
+                    self.gpu.dma_write_vram(address, row);
+                    address += 2;
+                }
+            }
+        }
+        for row in REGISTERED_TILE.iter() {
+            self.gpu.dma_write_vram(address, *row);
+            address += 2;
+        }
+        // Top row of the logo uses tiles 1-12 and the bottom row 13-24 followed by the (R)
+        for tile in 1 ..= 12 {
+            self.gpu.dma_write_vram(0x1903 + tile, tile as u8);
+            self.gpu.dma_write_vram(0x1923 + tile, tile as u8 + 12);
+        }
+        self.gpu.dma_write_vram(0x1910, 25);
     }
 
     pub fn mmu_step(&mut self, cycles: u8) {
-        let int = self.read(INTERUPT_REQUEST) | self.gpu.interrupts | self.input.interrupt | self.timer.interrupt;
+        let int = self.read(INTERUPT_REQUEST) | self.gpu.interrupts | self.input.interrupt | self.timer.interrupt | self.serial.interrupt;
         self.write(INTERUPT_REQUEST, int);
         self.gpu.interrupts = 0;
         self.input.interrupt = 0;
         self.timer.interrupt = 0;
+        self.serial.interrupt = 0;
         self.timer.timer_step(cycles);
-        let dma = self.dma;
-        match dma {
-            Some(ref dma) => self.dma_step(*dma, cycles),
-            None => {}
-        };
+        self.serial.step(cycles);
+        // A DMA started by this instruction's write only begins counting down from the next instruction
+        let age_pending = !self.dma_written;
+        self.dma_written = false;
+        for _ in 0 .. cycles / 4 {
+            self.dma_step(age_pending);
+        }
         self.hdma_step();
+        self.mbc.step(cycles);
+        self.rumble_total_cycles += cycles as u64;
+        if self.mbc.rumble() {
+            self.rumble_cycles += cycles as u64;
+        }
+    }
+
+    /// How much of the time since the last call the rumble motor was on, from 0.0 to 1.0.
+    /// Games pulse the motor to vary its strength, so frontends should poll this once a frame
+    pub fn take_rumble(&mut self) -> f32 {
+        let strength = match self.rumble_total_cycles {
+            0 => 0.0,
+            total => self.rumble_cycles as f32 / total as f32
+        };
+        self.rumble_cycles = 0;
+        self.rumble_total_cycles = 0;
+        strength
+    }
+
+    /// Tilts carts with an accelerometer, in g with positive x to the right and positive y towards the player
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mbc.set_accelerometer(x, y);
+    }
+
+    /// Battery backed cartridge memory to persist between sessions, `None` if the cart has none
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.mbc.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.mbc.load_save_data(data);
+    }
+
+    /// Plugs a device such as a `Printer` into the link port, replacing the `StdoutSerial` connected by default
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.connect(device);
+    }
+
+    /// Sets what a Pocket Camera sees, by default it shows a test pattern
+    pub fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mbc.set_image_source(source);
     }
 
-    #[allow(overlapping_patterns)]
     pub fn read(&self, address: u16) -> u8 {
+        match self.dma {
+            // During OAM DMA the CPU can only reach IO, HRAM and IE, other reads see the byte being transferred
+            Some(ref dma) if address < IO_START => if address >= OAM_START { 0xFF } else { dma.last_byte },
+            _ if self.booting && address <= ROM_END && !self.boot_rom_mapped(address) => self.mbc.read_rom_booting(address),
+            _ => self.peek(address)
+        }
+    }
+
+    /// Whether `address` reads from the boot ROM while it is mapped, color models boot from $8FF
+    /// bytes even for DMG carts and leave $100-$14F unmapped so the boot ROM can read the header
+    fn boot_rom_mapped(&self, address: u16) -> bool {
+        address <= 0xFF || (self.model.is_color() && (0x150 ..= 0x8FF).contains(&address))
+    }
+
+    /// Reads without OAM DMA bus conflicts, used by DMA itself and debuggers
+    #[allow(overlapping_patterns)]
+    pub fn peek(&self, address: u16) -> u8 {
 
         let output = match address {
-            // In color mode bios is $8FF bytes, leave $100-$14F unmapped so bios can read cartridge header
-            0 ..= 0xFF | 0x150 ..= 0x8FF if self.booting && self.gpu.color_mode => self.boot_rom[address as usize],
-            0 ..= 0xFF if self.booting => self.boot_rom[address as usize],
+            _ if self.booting && self.boot_rom_mapped(address) => self.boot_rom[address as usize],
             ROM_START ..= ROM_END => self.mbc.read_rom(address),
             VRAM_START ..= VRAM_END => self.gpu.read_from_vram(address - VRAM_START),
             ERAM_START ..= ERAM_END => self.mbc.read_ram(address - ERAM_START),
@@ -111,9 +275,11 @@ impl Mmu {
             OAM_START ..= OAM_END => self.gpu.read_from_oam(address - OAM_START),
             0xFEA0 ..= 0xFEFF => 0xFF, // Unusable returns this
             IO_START => self.input.read_joypad(),
+            0xFF01 => self.serial.read_data(),
+            0xFF02 => self.serial.read_control(),
             0xFF04 => self.timer.get_div(),
-            0xFF05 => self.timer.tima,
-            0xFF06 => self.timer.tma,
+            0xFF05 => self.timer.get_tima(),
+            0xFF06 => self.timer.get_tma(),
             0xFF07 => self.timer.get_timer_control(),
             0xFF40 => self.gpu.get_lcdc_control(),
             0xFF41 => self.gpu.get_lcdc_status(),
@@ -121,10 +287,7 @@ impl Mmu {
             0xFF43 => self.gpu.get_scx(),
             0xFF44 => self.gpu.get_ly(),
             0xFF45 => self.gpu.get_lyc(),
-            0xFF46 => match &self.dma {
-                Some(dma) => dma.value,
-                None => 0
-            },
+            0xFF46 => self.dma_register,
             0xFF47 => self.gpu.get_bgp(),
             0xFF48 => self.gpu.get_obp0(),
             0xFF49 => self.gpu.get_obp1(),
@@ -142,6 +305,7 @@ impl Mmu {
             0xFF6A if self.gpu.color_mode => self.gpu.get_color_sprite_palette_idx(), //cgb spi
             0xFF6B if self.gpu.color_mode => self.gpu.get_color_sprite_palette(), //cgb spd
             0xFF70 if self.gpu.color_mode => self.wram_select | 0b11111000, // TODO verify
+            INTERUPT_REQUEST => self.io.read(INTERUPT_REQUEST - IO_START) | 0b11100000, // Upper 3 bits unused and read as 1
             IO_START ..= IO_END => self.io.read(address - IO_START),
             HRAM_START ..= HRAM_END => self.hram.read(address - HRAM_START),
             INTERUPTS_ENABLE => self.interupt_switch
@@ -154,16 +318,19 @@ impl Mmu {
         u16::from_le_bytes([self.read(address), self.read(address + 1)])
     }
 
-    #[allow(overlapping_patterns)]
     pub fn write(&mut self, address: u16, value: u8) {
-        if address == 0xFF02 && value == 0x81 {
-            match str::from_utf8(&[self.read(0xFF01)]) {
-                Ok(s) => print!("{}", s),
-                _ => { }
-            }
+        if self.dma.is_some() && address < IO_START {
+            // Only IO, HRAM and IE are reachable during OAM DMA
+            return;
         }
+        self.write_bus(address, value);
+    }
+
+    #[allow(overlapping_patterns)]
+    fn write_bus(&mut self, address: u16, value: u8) {
         if self.booting && address == 0xFF50 {
             self.booting = false;
+            self.mbc.finish_boot();
             self.mbc.print_metadata();
             println!("boot complete");
         }
@@ -178,12 +345,17 @@ impl Mmu {
             ECHO_START ..= ECHO_END => self.wram[((address - ECHO_START) / 0x2000) as usize].write(address - ECHO_START, value),
             OAM_START ..= OAM_END => self.gpu.write_to_oam(address - OAM_START, value),
             0xFEA0 ..= 0xFEFF => { /* Unusable */} ,
-            0xFF00 => self.input.write_joypad(value),
-            // 0xFF01 SB serial transfer data
-            // 0xFF02 SC serial transfer control
+            0xFF00 => {
+                self.input.write_joypad(value);
+                if let Some(sgb) = self.sgb.as_mut() {
+                    sgb.write_joypad(value, &self.gpu, &mut self.input);
+                }
+            },
+            0xFF01 => self.serial.write_data(value), // SB serial transfer data
+            0xFF02 => self.serial.write_control(value), // SC serial transfer control
             0xFF04 => self.timer.reset_div(), // writing any value to DIV resets it to 0
-            0xFF05 => self.timer.tima = value,
-            0xFF06 => self.timer.tma = value,
+            0xFF05 => self.timer.set_tima(value),
+            0xFF06 => self.timer.set_tma(value),
             0xFF07 => self.timer.set_timer_control(value),
             0xFF40 => self.gpu.set_lcdc_control(value),
             0xFF41 => self.gpu.set_lcdc_status(value),
@@ -191,7 +363,12 @@ impl Mmu {
             0xFF43 => self.gpu.set_scx(value),
             0xFF44 => { /* No Writes to VRAM*/},
             0xFF45 => self.gpu.set_lyc(value),
-            0xFF46 => { self.dma = Some(Dma::new(value)); },
+            0xFF46 => {
+                // Restarting replaces any transfer waiting to start, a running one continues until the new one begins
+                self.dma_register = value;
+                self.dma_pending = Some(Dma::new(value));
+                self.dma_written = true;
+            },
             0xFF47 => self.gpu.set_bgp(value),
             0xFF48 => self.gpu.set_obp0(value),
             0xFF49 => self.gpu.set_obp1(value),
@@ -211,6 +388,7 @@ impl Mmu {
             0xFF70 if self.gpu.color_mode => {
                 self.wram_select = if value == 0 { 1 } else { value & 0b00000111 };
             },
+            INTERUPT_REQUEST => self.io.write(INTERUPT_REQUEST - IO_START, value & 0b00011111),
             IO_START ..= IO_END => self.io.write(address - IO_START, value),
             HRAM_START ..= HRAM_END => self.hram.write(address - HRAM_START, value),
             INTERUPTS_ENABLE => self.interupt_switch = value,
@@ -234,30 +412,36 @@ impl Mmu {
         res
     }
 
-    fn dma_step(&mut self, mut dma: Dma, cycles: u8) {
-        for _ in 0 ..= (cycles / 4) {
-            if dma.started && dma.address < 0xA0 {
-                //println!("{:04X} to {:04X}", dma.source + dma.address as u16, OAM_START + dma.address as u16);
-                let val = self.read(dma.source + dma.address as u16);
-                // TODO writes to OAM need to overide write
-                self.write(OAM_START + dma.address as u16, val);
-                dma.address += 1;
-            } else if dma.started {
-                self.dma = None;
-                return
+    /// Advances OAM DMA by one M-cycle, transferring one byte
+    fn dma_step(&mut self, age_pending: bool) {
+        if let Some(mut dma) = self.dma.take() {
+            let val = self.peek(dma.source + dma.address as u16);
+            self.gpu.dma_write_oam(dma.address as u16, val);
+            dma.last_byte = val;
+            dma.address += 1;
+            if dma.address < 0xA0 {
+                self.dma = Some(dma);
+            }
+        }
+
+        if let Some(mut pending) = self.dma_pending.take() {
+            if age_pending {
+                pending.delay -= 1;
+            }
+            if pending.delay == 0 {
+                self.dma = Some(pending);
             } else {
-                dma.started = true;
+                self.dma_pending = Some(pending);
             }
         }
-        self.dma = Some(dma);
     }
 
     fn hdma_step(&mut self) {
         if self.gpu.color_mode && self.hdma.active() {
             //println!("transferred");
             for i in 0 .. self.hdma.remaining_len() {
-                let val = self.read(self.hdma.source + i);
-                self.write(self.hdma.destination + i, val);
+                let val = self.peek(self.hdma.source + i);
+                self.gpu.dma_write_vram((self.hdma.destination + i) & 0x1FFF, val);
             }
             self.hdma.value = 0xFF;
         }
@@ -266,19 +450,22 @@ impl Mmu {
 
 #[derive(Copy, Clone)]
 struct Dma {
-    value: u8,
     source: u16,
     address: u8,
-    started: bool
+    /// M-cycles before the first byte is transferred
+    delay: u8,
+    last_byte: u8
 }
 
 impl Dma {
     fn new(value: u8) -> Self {
+        // Sources above DFFF read from echo RAM, which maps back onto WRAM
+        let page = if value >= 0xE0 { value - 0x20 } else { value };
         Dma {
-            value: value,
-            source: (value as u16) << 8,
+            source: (page as u16) << 8,
             address: 0,
-            started: false
+            delay: 1,
+            last_byte: 0xFF
         }
     }
 }
@@ -317,4 +504,67 @@ impl Hdma {
             ((self.value & 0b011111111) as u16 + 1) * 0x10
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_mmu() -> Mmu {
+        Mmu::new(vec![0; 0x8000], Gpu::new(false).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_mapped_for_dmg_carts() {
+        let mut mmu = Mmu::new(vec![0; 0x8000], Gpu::with_model(Model::Cgb, false).unwrap()).unwrap();
+        assert!(!mmu.gpu.color_mode);
+        let mut boot_rom = vec![0; CGB_BOOT_ROM_SIZE];
+        boot_rom[0x200] = 0xAB;
+        mmu.set_boot_rom(boot_rom).unwrap();
+        assert_eq!(mmu.read(0x0200), 0xAB);
+        mmu.booting = false;
+        assert_eq!(mmu.read(0x0200), 0x00);
+    }
+
+    #[test]
+    fn test_oam_dma_timing_and_bus_conflicts() {
+        let mut mmu = test_mmu();
+        for i in 0 .. 0xA0 {
+            mmu.write(0xC000 + i, i as u8 + 1);
+        }
+        mmu.write(HRAM_START, 0x42);
+        mmu.write(0xFF46, 0xC0);
+        // The writing instruction finishes before the start delay begins
+        mmu.mmu_step(12);
+        assert_eq!(mmu.read(0xC001), 2);
+        mmu.mmu_step(4);
+        // Transfer running, only HRAM and IO are reachable
+        assert_eq!(mmu.read(0xC001), 0xFF);
+        assert_eq!(mmu.read(HRAM_START), 0x42);
+        assert_eq!(mmu.read(0xFF46), 0xC0);
+        mmu.mmu_step(4);
+        assert_eq!(mmu.read(0x0150), 1);
+        assert_eq!(mmu.read(OAM_START), 0xFF);
+        mmu.write(0xC000, 0x99);
+        assert_eq!(mmu.peek(0xC000), 1);
+
+        for _ in 0 .. 159 {
+            mmu.mmu_step(4);
+        }
+        assert_eq!(mmu.read(0xC001), 2);
+        for i in 0 .. 0xA0 {
+            assert_eq!(mmu.read(OAM_START + i), i as u8 + 1);
+        }
+    }
+
+    #[test]
+    fn test_oam_dma_echo_source() {
+        let mut mmu = test_mmu();
+        mmu.write(0xDE00, 0x77);
+        mmu.write(0xFF46, 0xFE);
+        for _ in 0 .. 170 {
+            mmu.mmu_step(4);
+        }
+        assert_eq!(mmu.read(OAM_START), 0x77);
+    }
+}
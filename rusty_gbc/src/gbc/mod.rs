@@ -1,16 +1,27 @@
 pub mod gpu;
 pub mod input;
+pub mod palette;
+pub mod opcodes;
+pub mod sgb;
+pub mod camera;
+pub mod serial;
+pub mod printer;
 mod timer;
 mod boot;
+mod model;
 mod mmu;
 mod memory;
 mod registers;
 
 pub use mmu::Mmu;
+pub use boot::{BootMode, header_check};
+pub use model::Model;
 pub use registers::Registers;
 use super::debugger::Debugger;
 use crate::gbc::gpu::Gpu;
-use crate::Display;
+use crate::gbc::opcodes::{OPCODES, CB_OPCODES};
+use crate::{Color, Display, FrameBuffer};
+use std::io::Write;
 
 const V_BLANK_INTERRUPT: u8 = 1;
 const STAT_INTERRUPT: u8 = 2;
@@ -18,36 +29,125 @@ const TIMER_INTERRUPT: u8 = 4;
 const SERIAL_INTERRUPT: u8 = 8;
 pub const JOYPAD_INTERRUPT: u8 = 16;
 
+/// What the CPU is currently doing, for frontends to report
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuStatus {
+    Running,
+    /// Waiting in HALT for an interrupt
+    Halted,
+    /// Hung by executing an illegal opcode at `address`, nothing but a reset recovers from this
+    Locked { opcode: u8, address: u16 },
+    /// Hung like the boot ROM does when the cartridge header logo or checksum is wrong
+    BootFailed
+}
+
 pub struct Cpu {
     pub mem: Mmu,
     pub regs: Registers,
     ime: bool, // disables interrupts when false overriding IE register
+    /// EI was executed, IME is set after the following instruction
     ei: bool,
+    /// IME was set by an EI immediately before the current instruction
+    ei_applied: bool,
     halted: bool,
+    /// HALT was executed with IME clear and an interrupt pending, the next byte is read twice
+    halt_bug: bool,
+    /// Why the CPU has hung, once it has
+    lockup: Option<CpuStatus>,
+    model: Model,
+    /// The boot ROM is still running, registers are adjusted for the model once it finishes
+    booting: bool,
     pub log: bool,
+    /// Where the trace goes while `log` is set, stdout if `None`
+    log_output: Option<Box<dyn Write>>,
     debugger: Option<Debugger>
 }
 
 impl Cpu {
-    pub fn new(rom_bytes: Vec<u8>, gpu: Box<Gpu>) -> Cpu {
-        Cpu {
-            mem: Mmu::new(rom_bytes, gpu),
+    /// Creates a `Cpu` for the model the `Gpu` was created with, starting in the built-in boot ROM.
+    /// Fails if the cartridge type or size is not supported
+    pub fn new(rom_bytes: Vec<u8>, gpu: Box<Gpu>) -> Result<Cpu, String> {
+        let model = gpu.model();
+        Ok(Cpu {
+            mem: Mmu::new(rom_bytes, gpu)?,
             regs: Registers::new(),
-            ime: true,
+            ime: false,
             ei: false,
+            ei_applied: false,
             halted: false,
+            halt_bug: false,
+            lockup: None,
+            model,
+            booting: true,
             log: false,
+            log_output: None,
             debugger: None
-        }
+        })
+    }
+
+    /// Creates a `Cpu` that either runs a boot ROM or starts directly at the cartridge entry point
+    pub fn with_boot_mode(rom_bytes: Vec<u8>, gpu: Box<Gpu>, boot_mode: BootMode) -> Result<Cpu, String> {
+        let cpu = match boot_mode {
+            BootMode::Skip => {
+                let model = gpu.model();
+                let regs = boot::post_boot_registers(model, gpu.color_mode, &rom_bytes);
+                let header_ok = boot::header_check(&rom_bytes, model);
+                let mut cpu = Cpu::new(rom_bytes, gpu)?;
+                cpu.regs = regs;
+                cpu.booting = false;
+                cpu.mem.skip_boot();
+                if !header_ok {
+                    cpu.lockup = Some(CpuStatus::BootFailed);
+                }
+                cpu
+            },
+            BootMode::External(boot_rom) => {
+                let mut cpu = Cpu::new(rom_bytes, gpu)?;
+                cpu.mem.set_boot_rom(boot_rom)?;
+                cpu
+            },
+            BootMode::BuiltIn => Cpu::new(rom_bytes, gpu)?
+        };
+        Ok(cpu)
     }
 
     pub fn run_one_frame(&mut self, display: &mut dyn Display) {
-        let mut cycle_count: u32  = 0;
-        while cycle_count < 70_224 {
+        self.run_until_frame();
+        display.render_frame(self.mem.gpu.frame());
+    }
+
+    /// Runs until the GPU completes a frame, blank frames are still
+    /// completed at the normal rate while the LCD is off
+    pub fn run_until_frame(&mut self) {
+        loop {
             let cycles = self.step_cycles();
-            cycle_count += cycles as u32;
-            self.mem.gpu.gpu_step(display, cycles);
+            self.mem.gpu.gpu_step(cycles);
             self.mem.mmu_step(cycles);
+            if self.mem.gpu.take_frame_ready() {
+                return;
+            }
+        }
+    }
+
+    pub fn frame(&self) -> &FrameBuffer {
+        self.mem.gpu.frame()
+    }
+
+    /// The last frame colorized and framed by the Super Game Boy, `None` unless running
+    /// an SGB enhanced cartridge on the SGB model
+    pub fn render_sgb(&self) -> Option<Vec<Color>> {
+        self.mem.sgb().map(|sgb| sgb.render(self.mem.gpu.frame()))
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn status(&self) -> CpuStatus {
+        match self.lockup {
+            Some(status) => status,
+            None if self.halted => CpuStatus::Halted,
+            None => CpuStatus::Running
         }
     }
 
@@ -55,44 +155,72 @@ impl Cpu {
         self.debugger = Some(debug);
     }
 
-    fn handle_interrupts(&mut self) -> u8 {
-        //interrupts take 20 cycles to handle (+ 4 if in halt mode)
-        let int_enable = self.mem.read(mmu::INTERUPTS_ENABLE);
-        let int_request = self.mem.read(mmu::INTERUPT_REQUEST);
-        for flag in vec![V_BLANK_INTERRUPT, STAT_INTERRUPT, TIMER_INTERRUPT, SERIAL_INTERRUPT, JOYPAD_INTERRUPT] {
-            let interrupt_cycles = self.handle_interrupt(flag, int_enable, int_request);
-            if interrupt_cycles > 0 {
-                return interrupt_cycles
-            }
-        }
-        0
+    /// Writes the trace enabled by `log` to `output` instead of stdout
+    pub fn set_log_output(&mut self, output: Box<dyn Write>) {
+        self.log_output = Some(output);
     }
 
-    fn handle_interrupt(&mut self, flag: u8, enabled: u8, requested: u8) -> u8 {
-        let was_halted = self.halted;
-        if enabled & requested & flag == flag {
-            self.halted = false;
-            if self.ime {
-                self.ime = false;
-                self.mem.write(mmu::INTERUPT_REQUEST, requested ^ flag);
-                let pc = self.regs.pc;
-                self.mem.push_u16(&mut self.regs, pc);
-                self.regs.pc = match flag {
-                    V_BLANK_INTERRUPT => 0x40, // v-blank
-                    STAT_INTERRUPT => 0x48, // LCD Stat
-                    TIMER_INTERRUPT => 0x50, // Timer
-                    SERIAL_INTERRUPT => 0x58, // Serial
-                    JOYPAD_INTERRUPT => 0x60, // Joypad
-                    _ => panic!("unknown interrupt")
-                };
-                if self.log {
-                    println!("handled INT {:02X}", self.regs.pc);
+    fn write_log(&mut self, line: String) {
+        match self.log_output.as_mut() {
+            Some(output) => {
+                if let Err(e) = writeln!(output, "{}", line) {
+                    println!("Could not write log, logging stopped: {}", e);
+                    self.log = false;
                 }
-                return if was_halted { 24 } else { 20 }
+            },
+            None => println!("{}", line)
+        }
+    }
+
+    /// Interrupts that are both requested and enabled
+    fn pending_interrupts(&self) -> u8 {
+        self.mem.read(mmu::INTERUPTS_ENABLE) & self.mem.read(mmu::INTERUPT_REQUEST) & 0b00011111
+    }
+
+    /// Pushes PC and jumps to the highest priority pending interrupt, taking 20 cycles
+    fn dispatch_interrupt(&mut self) -> u8 {
+        self.ime = false;
+        let [pc_high, pc_low] = self.regs.pc.to_be_bytes();
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.mem.write(self.regs.sp, pc_high);
+        // The vector is chosen after the upper byte is pushed, if that push overwrote IE
+        // so nothing is pending anymore the dispatch is cancelled and jumps to 0x0000
+        let pending = self.pending_interrupts();
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.mem.write(self.regs.sp, pc_low);
+
+        self.regs.pc = if pending == 0 {
+            0x0000
+        } else {
+            let flag = pending & pending.wrapping_neg();
+            let requested = self.mem.read(mmu::INTERUPT_REQUEST);
+            self.mem.write(mmu::INTERUPT_REQUEST, requested & !flag);
+            match flag {
+                V_BLANK_INTERRUPT => 0x40, // v-blank
+                STAT_INTERRUPT => 0x48, // LCD Stat
+                TIMER_INTERRUPT => 0x50, // Timer
+                SERIAL_INTERRUPT => 0x58, // Serial
+                JOYPAD_INTERRUPT => 0x60, // Joypad
+                _ => panic!("unknown interrupt")
             }
-            return if was_halted { 4 } else { 0 }
+        };
+        if self.log {
+            self.write_log(format!("handled INT {:02X}", self.regs.pc));
+        }
+        20
+    }
+
+    fn halt(&mut self) {
+        let pending = self.pending_interrupts() != 0;
+        if pending && self.ei_applied {
+            // EI directly before HALT services the interrupt and returns to the HALT
+            self.regs.pc -= 1;
+        } else if pending && !self.ime {
+            // HALT exits immediately and the following byte is read twice
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
         }
-        0
     }
 
     fn check_breakpoint(&mut self) {
@@ -106,7 +234,11 @@ impl Cpu {
 
     fn next_byte(&mut self) -> u8 {
         let byte = self.mem.read(self.regs.pc);
-        self.regs.pc += 1;
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.regs.pc += 1;
+        }
         byte
     }
 
@@ -117,14 +249,48 @@ impl Cpu {
     }
 
     fn step_cycles(&mut self) -> u8 {
-        let interrupt_cycles = self.handle_interrupts();
-        if interrupt_cycles > 0 {
-            return interrupt_cycles;
+        if self.lockup.is_some() {
+            // Hardware keeps running while the CPU no longer fetches or handles interrupts
+            return 4;
         }
+        let pending = self.pending_interrupts() != 0;
+        let mut wake_cycles = 0;
         if self.halted {
-            return 4
+            if !pending {
+                return 4;
+            }
+            // Any pending interrupt ends HALT even with IME clear, taking an extra 4 cycles
+            self.halted = false;
+            wake_cycles = 4;
+        }
+        if self.ime && pending {
+            return wake_cycles + self.dispatch_interrupt();
+        }
+        if wake_cycles > 0 {
+            return wake_cycles;
+        }
+
+        self.ei_applied = self.ei;
+        if self.ei {
+            self.ime = true;
+            self.ei = false;
+        }
+        let cycles = self.next_intruction();
+        if self.booting && !self.mem.booting {
+            self.booting = false;
+            self.finish_boot();
+        }
+        cycles
+    }
+
+    /// Only the DMG and CGB boot ROMs are built in, this sets the registers
+    /// that the boot ROMs of other models leave with different values
+    fn finish_boot(&mut self) {
+        match self.model {
+            Model::Mgb => self.regs.a = 0xFF,
+            Model::Agb => self.regs.b |= 1,
+            _ => {}
         }
-        self.next_intruction()
     }
 
     /// returns number of cycles completed
@@ -134,575 +300,267 @@ impl Cpu {
         }
 
         let opcode = self.next_byte();
-        if self.ei {
-            self.ime = true;
-            self.ei = false;
-        }
 
         if self.log {
-            println!("executing ${:02X} at address ${:04X} AF {:04X} BC {:04X} DE {:04X} HL {:04X} SP: {:04X}", opcode, self.regs.pc-1,
-                self.regs.get_af(), self.regs.get_bc(), self.regs.get_de(), self.regs.get_hl(), self.regs.sp);
+            self.write_log(format!("executing ${:02X} at address ${:04X} AF {:04X} BC {:04X} DE {:04X} HL {:04X} SP: {:04X}", opcode, self.regs.pc-1,
+                self.regs.get_af(), self.regs.get_bc(), self.regs.get_de(), self.regs.get_hl(), self.regs.sp));
         }
 
-        match opcode {
-            // HALT
-            0x76 | 0x10 => {
-                if self.log {
-                    println!("halting");
-                }
-                if opcode == 0x10 {
-                    return 4;
-                }
-                self.halted = true; 4 
-            }
-            // LD B,n
-            0x06 => { self.regs.b = self.next_byte(); 8 },
-            // LD C,n
-            0x0E => { self.regs.c = self.next_byte(); 8 },
-            // LD D,n
-            0x16 => { self.regs.d = self.next_byte(); 8 },
-            // LD E,n
-            0x1E => { self.regs.e = self.next_byte(); 8 },
-            // LD H,n
-            0x26 => { self.regs.h = self.next_byte(); 8 },
-            // LD L,n
-            0x2E => { self.regs.l = self.next_byte(); 8 },
-            // LD A,B
-            0x78 => { self.regs.a = self.regs.b; 4 },
-            // LD A,C
-            0x79 => { self.regs.a = self.regs.c; 4 },
-            // LD A,D
-            0x7A => { self.regs.a = self.regs.d; 4 },
-            // LD A,E
-            0x7B => { self.regs.a = self.regs.e; 4 },
-            // LD A,H
-            0x7C => { self.regs.a = self.regs.h; 4 },
-            // LD A,L
-            0x7D => { self.regs.a = self.regs.l; 4 },
-            // LD A, (HL)
-            0x7E => { self.regs.a = self.byte_at_hl(); 8 },
-            // LD A,A
-            0x7F => { self.regs.a = self.regs.a; 4 },
-            // LD B,B
-            0x40 => { self.regs.b = self.regs.b; 4 },
-            // LD B,C
-            0x41 => { self.regs.b = self.regs.c; 4 },
-            // LD B,D
-            0x42 => { self.regs.b = self.regs.d; 4 },
-            // LD B,E
-            0x43 => { self.regs.b = self.regs.e; 4 },
-            // LD B,H
-            0x44 => { self.regs.b = self.regs.h; 4 },
-            // LD B,L
-            0x45 => { self.regs.b = self.regs.l; 4 },
-            // LD B, (HL)
-            0x46 => { self.regs.b = self.byte_at_hl(); 8 },
-            // LD B,A
-            0x47 => { self.regs.b = self.regs.a; 4 },
-            // LD C,B
-            0x48 => { self.regs.c = self.regs.b; 4 },
-            // LD C,C
-            0x49 => { self.regs.c = self.regs.c; 4 },
-            // LD C,D
-            0x4A => { self.regs.c = self.regs.d; 4 },
-            // LD C,E
-            0x4B => { self.regs.c = self.regs.e; 4},
-            // LD C,H
-            0x4C => { self.regs.c = self.regs.h; 4 },
-            // LD C,L
-            0x4D => { self.regs.c = self.regs.l; 4 },
-            // LD C,(HL)
-            0x4E => { self.regs.c = self.byte_at_hl(); 8 },
-            // LD C,A
-            0x4f => { self.regs.c = self.regs.a; 4 },
-            // LD D,B
-            0x50 => { self.regs.d = self.regs.b; 4 },
-            // LD D,C
-            0x51 => { self.regs.d = self.regs.c; 4 },
-            // LD D,D
-            0x52 => { self.regs.d = self.regs.d; 4 },
-            // LD D,E
-            0x53 => { self.regs.d = self.regs.e; 4 },
-            // LD D,H
-            0x54 => { self.regs.d = self.regs.h; 4 },
-            // LD D,L
-            0x55 => { self.regs.d = self.regs.l; 4 },
-            // LD D, (HL)
-            0x56 => { self.regs.d = self.byte_at_hl(); 8 },
-            // LD D,A
-            0x57 => { self.regs.d = self.regs.a; 4 },
-            // LD E,B
-            0x58 => { self.regs.e = self.regs.b; 4 },
-            // LD E,C
-            0x59 => { self.regs.e = self.regs.c; 4 },
-            // LD E,D
-            0x5A => { self.regs.e = self.regs.d; 4 },
-            // LD E,E
-            0x5B => { self.regs.e = self.regs.e; 4 },
-            // LD E,H
-            0x5C => { self.regs.e = self.regs.h; 4 },
-            // LD E,L
-            0x5D => { self.regs.e = self.regs.l; 4 },
-            // LD E, (HL)
-            0x5E => { self.regs.e = self.byte_at_hl(); 8 },
-            // LD E,A
-            0x5F => { self.regs.e = self.regs.a; 4 },
-            // LD H,B
-            0x60 => { self.regs.h = self.regs.b; 4 },
-            // LD H,C
-            0x61 => { self.regs.h = self.regs.c; 4 },
-            // LD H,D
-            0x62 => { self.regs.h = self.regs.d; 4 },
-            // LD H,E
-            0x63 => { self.regs.h = self.regs.e; 4 },
-            // LD H,H
-            0x64 => { self.regs.h = self.regs.h; 4 },
-            // LD H,L
-            0x65 => { self.regs.h = self.regs.l; 4 },
-            // LD H, (HL)
-            0x66 => { self.regs.h = self.byte_at_hl(); 8 },
-            // LD H, A
-            0x67 => { self.regs.h = self.regs.a; 4 },
-            // LD L,B
-            0x68 => { self.regs.l = self.regs.b; 4 },
-            // LD L,C
-            0x69 => { self.regs.l = self.regs.c; 4 },
-            // LD L,D
-            0x6A => { self.regs.l = self.regs.d; 4 },
-            // LD L,E
-            0x6B => { self.regs.l = self.regs.e; 4 },
-            // LD L,H
-            0x6C => { self.regs.l = self.regs.h; 4 },
-            // LD L,L
-            0x6D => { self.regs.l = self.regs.l; 4 },
-            // LD L, (HL)
-            0x6E => { self.regs.l = self.byte_at_hl(); 8 },
-            // LD L,A
-            0x6F => { self.regs.l = self.regs.a; 4 }
-            // LD (HL), B
-            0x70 => { self.set_byte_at_hl(self.regs.b); 8 },
-            // LD (HL), C
-            0x71 => { self.set_byte_at_hl(self.regs.c); 8 },
-            // LD (HL), D
-            0x72 => { self.set_byte_at_hl(self.regs.d); 8 },
-            // LD (HL), E
-            0x73 => { self.set_byte_at_hl(self.regs.e); 8 },
-            // LD (HL), H
-            0x74 => { self.set_byte_at_hl(self.regs.h); 8 },
-            // LD (HL), L
-            0x75 => { self.set_byte_at_hl(self.regs.l); 8 },
-            // LD (HL), n
-            0x36 => { let n = self.next_byte(); self.set_byte_at_hl(n); 12 },
-            // LD A, (BC)
-            0x0A => { self.regs.a = self.mem.read(self.regs.get_bc()); 8 },
-            // LD A, (DE)
-            0x1A => { self.regs.a = self.mem.read(self.regs.get_de()); 8 },
-            // LD A, (nn)
-            0xFA => { let nn = self.next_u16(); self.regs.a = self.mem.read(nn); 16 },
-            // LD A, n
-            0x3E  => { self.regs.a = self.next_byte(); 8},
-            // LD (BC),A
-            0x02 => { self.mem.write(self.regs.get_bc(), self.regs.a); 8 },
-            // LD (DE),A
-            0x12 => { self.mem.write(self.regs.get_de(), self.regs.a); 8 },
-            // LD (HL),A
-            0x77 => { self.mem.write(self.regs.get_hl(), self.regs.a); 8 },
-            // LD (nn),A
-            0xEA => { let nn = self.next_u16(); self.mem.write(nn, self.regs.a); 8 },
-            // LD A, ($FF00 + C)
-            0xF2 => { self.regs.a = self.mem.read(0xFF00 + (self.regs.c as u16)); 8 },
-            // LD ($FF00+C),A
-            0xE2 => { self.mem.write(0xFF00 + self.regs.c as u16, self.regs.a); 8 },
-            // LD A, (HL Dec/-)
-            0x3A => { self.regs.a = self.byte_at_hl(); self.regs.set_hl(self.regs.get_hl() - 1); 8 },
-            // LD (HL Dec/-), A
-            0x32 => { self.set_byte_at_hl(self.regs.a); self.regs.set_hl(self.regs.get_hl() - 1); 8 },
-            // LD A, (HL Inc/+)
-            0x2A => { self.regs.a = self.byte_at_hl(); self.regs.set_hl(self.regs.get_hl() + 1); 8 },
-            // LD (HL Inc/+), A
-            0x22 => { self.set_byte_at_hl(self.regs.a); self.regs.set_hl(self.regs.get_hl() + 1); 8 },
-            // LD ($FF00+n),A
-            0xE0 => { let n = self.next_byte(); self.mem.write(0xFF00 + n as u16, self.regs.a); 12 },
-            // LD A, ($FF00+n)
-            0xF0 => { let n = self.next_byte(); self.regs.a = self.mem.read(0xFF00 + n as u16); 12 },
-            // LD BC, nn
-            0x01 => { let nn = self.next_u16(); self.regs.set_bc(nn); 12 },
-            // LD DE, nn
-            0x11 => { let nn = self.next_u16(); self.regs.set_de(nn); 12 },
-            // LD HL, nn
-            0x21 => { let nn = self.next_u16(); self.regs.set_hl(nn); 12 },
-            // LD SP, nn
-            0x31 => { self.regs.sp = self.next_u16(); 12 },
-            // LD SP, HL
-            0xF9 => { self.regs.sp = self.regs.get_hl(); 8 },
-            // LDHL SP, n
-            0xF8 => { 
-                let n = self.add_signed_u8_to_sp();
-                self.regs.set_hl(n);
-                12
-            },
-            // LD (nn), SP
-            0x08 => { let nn = self.next_u16(); self.mem.write_u16(nn, self.regs.sp); 20 },
-            // PUSH AF
-            0xF5 => { let af = self.regs.get_af(); self.mem.push_u16(&mut self.regs, af); 16 },
-            // PUSH BC
-            0xC5 => { let bc = self.regs.get_bc(); self.mem.push_u16(&mut self.regs, bc); 16 },
-            // PUSH DE
-            0xD5 => { let de = self.regs.get_de(); self.mem.push_u16(&mut self.regs, de); 16 },
-            // PUSH HL
-            0xE5 => { let hl = self.regs.get_hl(); self.mem.push_u16(&mut self.regs, hl); 16 },
-            // POP AF
-            0xF1 => { let nn = self.mem.pop_u16(&mut self.regs); self.regs.set_af(nn); 12 },
-            // POP BC
-            0xC1 => { let nn = self.mem.pop_u16(&mut self.regs); self.regs.set_bc(nn); 12 },
-            // POP DE
-            0xD1 => { let nn = self.mem.pop_u16(&mut self.regs); self.regs.set_de(nn); 12 },
-            // POP HL
-            0xE1 => { let nn = self.mem.pop_u16(&mut self.regs); self.regs.set_hl(nn); 12 },
-            // ADD A,A
-            0x87 => { self.add(self.regs.a, false); 4 },
-            // ADD A,B
-            0x80 => { self.add(self.regs.b, false); 4 },
-            // ADD A,C
-            0x81 => { self.add(self.regs.c, false); 4 },
-            // ADD A,D
-            0x82 => { self.add(self.regs.d, false); 4 },
-            // ADD A,E
-            0x83 => { self.add(self.regs.e, false); 4 },
-            // ADD A,H
-            0x84 => { self.add(self.regs.h, false); 4 },
-            // ADd A,L
-            0x85 => { self.add(self.regs.l, false); 4 },
-            // ADD A, (HL)
-            0x86 => { self.add(self.byte_at_hl(), false); 8 },
-            // ADD A,n
-            0xC6 => { let n = self.next_byte(); self.add(n, false); 8 },
-            // ADC A,A
-            0x8F => { self.add(self.regs.a, true); 4 },
-            // ADC A,B
-            0x88 => { self.add(self.regs.b, true); 4 },
-            // ADC A,C
-            0x89 => { self.add(self.regs.c, true); 4 },
-            // ADC A,D
-            0x8A => { self.add(self.regs.d, true); 4 },
-            // ADC A,E
-            0x8B => { self.add(self.regs.e, true); 4 },
-            // ADC A,H
-            0x8C => { self.add(self.regs.h, true); 4 },
-            // ADC A,L
-            0x8D => { self.add(self.regs.l, true); 4 },
-            // ADC A, (HL)
-            0x8E => { self.add(self.byte_at_hl(), true); 8 },
-            // ADC A,n
-            0xCE => { let n = self.next_byte(); self.add(n, true); 8 },
-            // SUB A
-            0x97 => { self.subtract(self.regs.a, false); 4 },
-            // SUB B
-            0x90 => { self.subtract(self.regs.b, false); 4 },
-            // SUB C
-            0x91 => { self.subtract(self.regs.c, false); 4 },
-            // SUB D
-            0x92 => { self.subtract(self.regs.d, false); 4 },
-            // SUB E
-            0x93 => { self.subtract(self.regs.e, false); 4 },
-            // SUB H
-            0x94 => { self.subtract(self.regs.h, false); 4 },
-            // SUB L
-            0x95 => { self.subtract(self.regs.l, false); 4 },
-            // SUB (HL)
-            0x96 => { self.subtract(self.byte_at_hl(), false); 8 },
-            // SUB n
-            0xD6 => { 
-                let n = self.next_byte(); 
-                self.subtract(n, false);
-                8
-            },
-            // SBC
-            // SBC A
-            0x9F => { self.subtract(self.regs.a, true); 4 },
-            // SBC B
-            0x98 => { self.subtract(self.regs.b, true); 4 },
-            // SBC C
-            0x99 => { self.subtract(self.regs.c, true); 4 },
-            // SBC D
-            0x9A => { self.subtract(self.regs.d, true); 4 },
-            // SBC E
-            0x9B => { self.subtract(self.regs.e, true); 4 },
-            // SBC H
-            0x9C => { self.subtract(self.regs.h, true); 4 },
-            // SBC L
-            0x9D => { self.subtract(self.regs.l, true); 4 },
-            // SBC (HL)
-            0x9E => { self.subtract(self.byte_at_hl(), true); 8 },
-            // SBC A,n
-            0xDE => { let n = self.next_byte(); self.subtract(n, true); 8 },
-            // AND
-            // AND A
-            0xA7 => { self.logical_and(self.regs.a); 4 }
-            // AND B
-            0xA0 => { self.logical_and(self.regs.b); 4 }
-            // AND C
-            0xA1 => { self.logical_and(self.regs.c); 4 }
-            // AND D
-            0xA2 => { self.logical_and(self.regs.d); 4 }
-            // AND E
-            0xA3 => { self.logical_and(self.regs.e); 4 }
-            // AND H
-            0xA4 => { self.logical_and(self.regs.h); 4 }
-            // AND L
-            0xA5 => { self.logical_and(self.regs.l); 4 }
-            // AND (HL)
-            0xA6 => { self.logical_and(self.byte_at_hl()); 8 }
-            // AND n
-            0xE6 => { let n = self.next_byte(); self.logical_and(n); 8 }
-            // OR
-            // OR A
-            0xB7 => { self.logical_or(self.regs.a); 4 },
-            // OR B
-            0xB0 => { self.logical_or(self.regs.b); 4 },
-            // OR C
-            0xB1 => { self.logical_or(self.regs.c); 4 },
-            // OR D
-            0xB2 => { self.logical_or(self.regs.d); 4 },
-            // OR E
-            0xB3 => { self.logical_or(self.regs.e); 4 },
-            // OR H
-            0xB4 => { self.logical_or(self.regs.h); 4 },
-            // OR L
-            0xB5 => { self.logical_or(self.regs.l); 4 },
-            // OR (HL)
-            0xB6 => { self.logical_or(self.byte_at_hl()); 8 },
-            // OR n
-            0xF6 => { let n = self.next_byte(); self.logical_or(n); 8 },
-            // XOR A
-            0xAF => { self.logical_xor(self.regs.a); 4 },
-            // XOR B
-            0xA8 => { self.logical_xor(self.regs.b); 4 },
-            // XOR C
-            0xA9 => { self.logical_xor(self.regs.c); 4 },
-            // XOR D
-            0xAA => { self.logical_xor(self.regs.d); 4 },
-            // XOR E
-            0xAB => { self.logical_xor(self.regs.e); 4 },
-            // XOR H
-            0xAC => { self.logical_xor(self.regs.h); 4 },
-            // XOR L
-            0xAD => { self.logical_xor(self.regs.l); 4 },
-            // XOR (HL)
-            0xAE => { self.logical_xor(self.byte_at_hl()); 8},
-            // XOR n
-            0xEE => { let n = self.next_byte(); self.logical_xor(n); 8 },
-            // CP
-            // CP A
-            0xBF => { self.compare(self.regs.a); 4 },
-            // CP B
-            0xB8 => { self.compare(self.regs.b); 4 },
-            // CP C
-            0xB9 => { self.compare(self.regs.c); 4 },
-            // CP D
-            0xBA => {self.compare(self.regs.d); 4 },
-            // CP E
-            0xBB => { self.compare(self.regs.e); 4 },
-            // CP H
-            0xBC => { self.compare(self.regs.h); 4 },
-            // CP L
-            0xBD => { self.compare(self.regs.l); 4 },
-            // CP (HL)
-            0xBE => { self.compare(self.byte_at_hl()); 8},
-            // CP n
-            0xFE => { let n = self.next_byte(); self.compare(n); 8 },
-            // INC
-            // INC A
-            0x3C => { self.regs.a = self.inc(self.regs.a); 4 },
-            // INC B
-            0x04 => { self.regs.b = self.inc(self.regs.b); 4 },
-            // INC C
-            0x0C => { self.regs.c = self.inc(self.regs.c); 4 },
-            // INC D
-            0x14 => { self.regs.d = self.inc(self.regs.d); 4 },
-            // INC E
-            0x1C => { self.regs.e = self.inc(self.regs.e); 4 },
-            // INC H
-            0x24 => { self.regs.h = self.inc(self.regs.h); 4 },
-            // INC L
-            0x2C => { self.regs.l = self.inc(self.regs.l); 4 },
-            // INC (HL)
-            0x34 => { 
-                let new_hl = self.inc(self.byte_at_hl()); 
-                self.set_byte_at_hl(new_hl);
-                12
-            },
-            // DEC
-            // DEC A
-            0x3D => { self.regs.a = self.dec(self.regs.a); 4 },
-            // DEC B
-            0x05 => { self.regs.b = self.dec(self.regs.b); 4 },
-            // DEC C
-            0x0D => { self.regs.c = self.dec(self.regs.c); 4 },
-            // DEC D
-            0x15 => { self.regs.d = self.dec(self.regs.d); 4 },
-            // DEC E
-            0x1D => { self.regs.e = self.dec(self.regs.e); 4 },
-            // DEC H
-            0x25 => { self.regs.h = self.dec(self.regs.h); 4 },
-            // DEC L
-            0x2D => { self.regs.l = self.dec(self.regs.l); 4 },
-            // DEC (HL)
-            0x35 => { 
-                let new_hl = self.dec(self.byte_at_hl()); 
-                self.set_byte_at_hl(new_hl); 
-                12
+        if opcode == 0xCB {
+            return self.cb_opcode_step();
+        }
+
+        // Opcodes are split into the fields xxyyyzzz with y further split into ppq.
+        // Register operands in y and z index B, C, D, E, H, L, (HL), A
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let p = y >> 1;
+        let q = y & 1;
+
+        match (x, z) {
+            (0, 0) => match y {
+                // NOP
+                0 => {},
+                // LD (nn), SP
+                1 => { let nn = self.next_u16(); self.mem.write_u16(nn, self.regs.sp); },
+                // STOP
+                2 => {
+                    if self.log {
+                        self.write_log("stopping".to_string());
+                    }
+                    self.next_byte();
+                },
+                // JR n
+                3 => return self.jump_by_n_if(true),
+                // JR cc, n
+                _ => return self.jump_by_n_if(self.condition(y - 4))
             },
-            // ADD (16 bit)
-            // ADD HL,BC
-            0x09 => { 
-                let hl = self.add_u16(self.regs.get_hl(), self.regs.get_bc());
+            // LD rr, nn
+            (0, 1) if q == 0 => { let nn = self.next_u16(); self.set_r16(p, nn); },
+            // ADD HL, rr
+            (0, 1) => {
+                let hl = self.add_u16(self.regs.get_hl(), self.get_r16(p));
                 self.regs.set_hl(hl);
-                8
             },
-            // ADD HL,DE
-            0x19 => { 
-                let hl = self.add_u16(self.regs.get_hl(), self.regs.get_de());
-                self.regs.set_hl(hl);
-                8
+            // LD (BC), A / LD (DE), A / LD (HL+), A / LD (HL-), A and the reverse loads into A
+            (0, 2) => {
+                let address = match p {
+                    0 => self.regs.get_bc(),
+                    1 => self.regs.get_de(),
+                    _ => self.regs.get_hl()
+                };
+                match p {
+                    2 => self.regs.set_hl(address.wrapping_add(1)),
+                    3 => self.regs.set_hl(address.wrapping_sub(1)),
+                    _ => {}
+                }
+                if q == 0 {
+                    self.mem.write(address, self.regs.a);
+                } else {
+                    self.regs.a = self.mem.read(address);
+                }
             },
-            // ADD HL,HL
-            0x29 => { 
-                let hl = self.add_u16(self.regs.get_hl(), self.regs.get_hl());
-                self.regs.set_hl(hl);
-                8
+            // INC rr
+            (0, 3) if q == 0 => self.set_r16(p, self.get_r16(p).wrapping_add(1)),
+            // DEC rr
+            (0, 3) => self.set_r16(p, self.get_r16(p).wrapping_sub(1)),
+            // INC r
+            (0, 4) => { let value = self.inc(self.get_r8(y)); self.set_r8(y, value); },
+            // DEC r
+            (0, 5) => { let value = self.dec(self.get_r8(y)); self.set_r8(y, value); },
+            // LD r, n
+            (0, 6) => { let n = self.next_byte(); self.set_r8(y, n); },
+            (0, _) => match y {
+                // RLCA
+                0 => { self.regs.a = self.rotate_left_carry(self.regs.a); self.regs.set_zero_flag(false); },
+                // RRCA
+                1 => { self.regs.a = self.rotate_right_carry(self.regs.a); self.regs.set_zero_flag(false); },
+                // RLA
+                2 => { self.regs.a = self.rotate_left(self.regs.a); self.regs.set_zero_flag(false); },
+                // RRA
+                3 => { self.regs.a = self.rotate_right(self.regs.a); self.regs.set_zero_flag(false); },
+                // DAA
+                4 => self.decimal_adjust(),
+                // CPL
+                5 => {
+                    self.regs.a = !self.regs.a;
+                    self.regs.set_subtract_flag(true);
+                    self.regs.set_half_carry_flag(true);
+                },
+                // SCF
+                6 => {
+                    self.regs.set_subtract_flag(false);
+                    self.regs.set_half_carry_flag(false);
+                    self.regs.set_carry_flag(true);
+                },
+                // CCF
+                _ => {
+                    self.regs.set_carry_flag(!self.regs.carry_flag());
+                    self.regs.set_subtract_flag(false);
+                    self.regs.set_half_carry_flag(false);
+                }
             },
-            // ADD HL,SP
-            0x39 => { 
-                let hl = self.add_u16(self.regs.get_hl(), self.regs.sp);
-                self.regs.set_hl(hl); 
-                8
+            // HALT, encoded where LD (HL), (HL) would be
+            (1, 6) if y == 6 => {
+                if self.log {
+                    self.write_log("halting".to_string());
+                }
+                self.halt();
             },
-            // ADD SP,n
-            0xE8 => { self.regs.sp = self.add_signed_u8_to_sp(); 16 }
-            // INC (16 bit)
-            // INC BC
-            0x03 => { self.regs.set_bc(self.regs.get_bc().wrapping_add(1)); 8 },
-            // INC DE
-            0x13 => { self.regs.set_de(self.regs.get_de().wrapping_add(1)); 8 },
-            // INC HL
-            0x23 => { self.regs.set_hl(self.regs.get_hl().wrapping_add(1)); 8 },
-            // INC SP
-            0x33 => { self.regs.sp = self.regs.sp.wrapping_add(1); 8 },
-            // DEC (16 bit)
-            // DEC BC
-            0x0B => { self.regs.set_bc(self.regs.get_bc().wrapping_sub(1)); 8 },
-            // DEC DE
-            0x1B => { self.regs.set_de(self.regs.get_de().wrapping_sub(1)); 8 },
-            // DEC HL
-            0x2B => { self.regs.set_hl(self.regs.get_hl().wrapping_sub(1)); 8 },
-            // DEC SP
-            0x3B => { self.regs.sp = self.regs.sp.wrapping_sub(1); 8 },
-            // DAA
-            0x27 => { self.decimal_adjust(); 4 },
-            // CPL
-            0x2F => {
-                self.regs.a = !self.regs.a; 
-                self.regs.set_subtract_flag(true); 
-                self.regs.set_half_carry_flag(true);
-                4
+            // LD r, r
+            (1, _) => { let value = self.get_r8(z); self.set_r8(y, value); },
+            // ADD, ADC, SUB, SBC, AND, XOR, OR, CP with a register
+            (2, _) => { let value = self.get_r8(z); self.alu(y, value); },
+            (3, 0) => match y {
+                // RET cc
+                0 ..= 3 => return self.return_if(self.condition(y)),
+                // LD ($FF00+n), A
+                4 => { let n = self.next_byte(); self.mem.write(0xFF00 + n as u16, self.regs.a); },
+                // ADD SP, n
+                5 => self.regs.sp = self.add_signed_u8_to_sp(),
+                // LD A, ($FF00+n)
+                6 => { let n = self.next_byte(); self.regs.a = self.mem.read(0xFF00 + n as u16); },
+                // LDHL SP, n
+                _ => { let n = self.add_signed_u8_to_sp(); self.regs.set_hl(n); }
             },
-            // CCF
-            0x3f => {
-                self.regs.set_carry_flag(!self.regs.carry_flag());
-                self.regs.set_subtract_flag(false);
-                self.regs.set_half_carry_flag(false);
-                4
-            }
-            // SCF
-            0x37 => { 
-                self.regs.set_subtract_flag(false);
-                self.regs.set_half_carry_flag(false);
-                self.regs.set_carry_flag(true);
-                4
+            // POP rr
+            (3, 1) if q == 0 => { let nn = self.mem.pop_u16(&mut self.regs); self.set_r16_stack(p, nn); },
+            (3, 1) => match p {
+                // RET
+                0 => self.return_from_call(),
+                // RETI
+                1 => { self.return_from_call(); self.ime = true; },
+                // JP HL
+                2 => self.regs.pc = self.regs.get_hl(),
+                // LD SP, HL
+                _ => self.regs.sp = self.regs.get_hl()
             },
-            // NOP
-            0x00 => { 4 },
-            // HALT
-            // STOP
+            (3, 2) => match y {
+                // JP cc, nn
+                0 ..= 3 => return self.jump_to_nn_if(self.condition(y)),
+                // LD ($FF00+C), A
+                4 => self.mem.write(0xFF00 + self.regs.c as u16, self.regs.a),
+                // LD (nn), A
+                5 => { let nn = self.next_u16(); self.mem.write(nn, self.regs.a); },
+                // LD A, ($FF00+C)
+                6 => self.regs.a = self.mem.read(0xFF00 + self.regs.c as u16),
+                // LD A, (nn)
+                _ => { let nn = self.next_u16(); self.regs.a = self.mem.read(nn); }
+            },
+            // JP nn
+            (3, 3) if y == 0 => return self.jump_to_nn_if(true),
             // DI
-            0xF3 => { self.ime = false; 4 },
+            (3, 3) if y == 6 => { self.ime = false; self.ei = false; },
             // EI
-            0xFB => { self.ei = true; 4 }
-            // RLCA
-            0x07 => { self.regs.a = self.rotate_left_carry(self.regs.a); self.regs.set_zero_flag(false); 4 },
-            // RLA
-            0x17 => { self.regs.a = self.rotate_left(self.regs.a); self.regs.set_zero_flag(false); 4 },
-            // RRCA
-            0x0F => { self.regs.a = self.rotate_right_carry(self.regs.a); self.regs.set_zero_flag(false); 4 },
-            // RRA
-            0x1F => { self.regs.a = self.rotate_right(self.regs.a); self.regs.set_zero_flag(false); 4 },
-            // JP
-            // JP nn
-            0xC3 => { self.jump_to_nn_if(true); 12 },
-            // JP NZ
-            0xC2 => { self.jump_to_nn_if(!self.regs.zero_flag()); 12 },
-            // JP Z
-            0xCA => { self.jump_to_nn_if(self.regs.zero_flag()); 12 },
-            // JP NC
-            0xD2 => { self.jump_to_nn_if(!self.regs.carry_flag()); 12 },
-            // JP C
-            0xDA => { self.jump_to_nn_if(self.regs.carry_flag()); 12 },
-            // JP HL
-            0xE9 => { self.regs.pc = self.regs.get_hl(); 4 },
-            // JR n
-            0x18 => { self.jump_by_n_if(true); 8 },
-            // JR NZ, *
-            0x20 => { self.jump_by_n_if(!self.regs.zero_flag()); 8 },
-            // JR Z, *
-            0x28 => { self.jump_by_n_if(self.regs.zero_flag()); 8 },
-            // JR NC, *
-            0x30 => { self.jump_by_n_if(!self.regs.carry_flag()); 8 },
-            // JR C, *
-            0x38 => { self.jump_by_n_if(self.regs.carry_flag()); 8 },
+            (3, 3) if y == 7 => self.ei = true,
+            // CALL cc, nn
+            (3, 4) if y < 4 => return self.call_if(self.condition(y)),
+            // PUSH rr
+            (3, 5) if q == 0 => { let nn = self.get_r16_stack(p); self.mem.push_u16(&mut self.regs, nn); },
             // CALL nn
-            0xCD => { self.call_if(true); 12 },
-            // CALL NZ,nn
-            0xC4 => { self.call_if(!self.regs.zero_flag()); 12 },
-            // CALL Z,nn
-            0xCC => { self.call_if(self.regs.zero_flag()); 12 },
-            // CALL NC,nn
-            0xD4 => { self.call_if(!self.regs.carry_flag()); 12 },
-            // CALL C,nn
-            0xDC => { self.call_if(self.regs.carry_flag()); 12 },
-            // RST 0x00
-            0xC7 => { self.restart(0x00); 32 },
-            // RST 0x08
-            0xCF => { self.restart(0x08); 32 },
-            // RST 0x10
-            0xD7 => { self.restart(0x10); 32 },
-            // RST 0x18
-            0xDF => { self.restart(0x18); 32 },
-            // RST 0x20
-            0xE7 => { self.restart(0x20); 32 },
-            // RST 0x28
-            0xEF => { self.restart(0x28); 32 },
-            // RST 0x30
-            0xF7 => { self.restart(0x30); 32 },
-            // RST 0x38
-            0xFF => { self.restart(0x38); 32 },
-            // RET
-            0xC9 => { self.return_if(true); 8 }
-            // RET NZ
-            0xC0 => { self.return_if(!self.regs.zero_flag()); 8 },
-            // RET Z
-            0xC8 => { self.return_if(self.regs.zero_flag()); 8 },
-            // RET NC
-            0xD0 => { self.return_if(!self.regs.carry_flag()); 8 },
-            // RET C
-            0xD8 => { self.return_if(self.regs.carry_flag()); 8 },
-            // RETI
-            0xD9 => { self.return_if(true); self.ime = true; 8 },
-            // CB ops
-            0xCB => { self.cb_opcode_step() },
-            _ => panic!("Unknown Opcode: ${:02X} at address ${:04X}", opcode, self.regs.pc-1)
+            (3, 5) if p == 0 => return self.call_if(true),
+            // ADD, ADC, SUB, SBC, AND, XOR, OR, CP with n
+            (3, 6) => { let n = self.next_byte(); self.alu(y, n); },
+            // RST
+            (3, 7) => self.restart(y * 8),
+            // Illegal opcodes lock up the CPU
+            _ => {
+                if self.log {
+                    self.write_log(format!("locked up by illegal opcode ${:02X} at address ${:04X}", opcode, self.regs.pc-1));
+                }
+                self.lockup = Some(CpuStatus::Locked { opcode, address: self.regs.pc-1 });
+            }
+        }
+        OPCODES[opcode as usize].cycles
+    }
+
+    /// Reads the register selected by a 3 bit operand field
+    fn get_r8(&self, index: u8) -> u8 {
+        match index {
+            0 => self.regs.b,
+            1 => self.regs.c,
+            2 => self.regs.d,
+            3 => self.regs.e,
+            4 => self.regs.h,
+            5 => self.regs.l,
+            6 => self.byte_at_hl(),
+            _ => self.regs.a
+        }
+    }
+
+    /// Writes the register selected by a 3 bit operand field
+    fn set_r8(&mut self, index: u8, value: u8) {
+        match index {
+            0 => self.regs.b = value,
+            1 => self.regs.c = value,
+            2 => self.regs.d = value,
+            3 => self.regs.e = value,
+            4 => self.regs.h = value,
+            5 => self.regs.l = value,
+            6 => self.set_byte_at_hl(value),
+            _ => self.regs.a = value
+        }
+    }
+
+    /// Reads BC, DE, HL or SP selected by the p field
+    fn get_r16(&self, index: u8) -> u16 {
+        match index {
+            0 => self.regs.get_bc(),
+            1 => self.regs.get_de(),
+            2 => self.regs.get_hl(),
+            _ => self.regs.sp
+        }
+    }
+
+    fn set_r16(&mut self, index: u8, value: u16) {
+        match index {
+            0 => self.regs.set_bc(value),
+            1 => self.regs.set_de(value),
+            2 => self.regs.set_hl(value),
+            _ => self.regs.sp = value
+        }
+    }
+
+    /// Reads BC, DE, HL or AF selected by the p field of PUSH
+    fn get_r16_stack(&self, index: u8) -> u16 {
+        match index {
+            3 => self.regs.get_af(),
+            _ => self.get_r16(index)
         }
     }
+
+    fn set_r16_stack(&mut self, index: u8, value: u16) {
+        match index {
+            3 => self.regs.set_af(value),
+            _ => self.set_r16(index, value)
+        }
+    }
+
+    /// Evaluates the NZ, Z, NC or C condition selected by a 2 bit field
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.regs.zero_flag(),
+            1 => self.regs.zero_flag(),
+            2 => !self.regs.carry_flag(),
+            _ => self.regs.carry_flag()
+        }
+    }
+
+    /// Runs the ADD, ADC, SUB, SBC, AND, XOR, OR or CP selected by the y field on A and `value`
+    fn alu(&mut self, operation: u8, value: u8) {
+        match operation {
+            0 => self.add(value, false),
+            1 => self.add(value, true),
+            2 => self.subtract(value, false),
+            3 => self.subtract(value, true),
+            4 => self.logical_and(value),
+            5 => self.logical_xor(value),
+            6 => self.logical_or(value),
+            _ => self.compare(value)
+        }
+    }
+
     /// param: `reg_val` - The value from a register from 
     /// which to logically or with register a
     fn logical_or(&mut self, reg_val: u8) {
@@ -734,19 +592,27 @@ impl Cpu {
     }
 
     /// If cond is true, jump to the current addres + n 
-    /// where n is the immediately following signed byte
-    fn jump_by_n_if(&mut self, cond: bool) {
+    /// where n is the immediately following signed byte.
+    /// Returns 12 cycles if the jump is taken, otherwise 8
+    fn jump_by_n_if(&mut self, cond: bool) -> u8 {
         let n = self.next_byte();
         let next_addr = add_signed_u8_to_u16(self.regs.pc, n);
         if cond {
             self.regs.pc = next_addr;
+            12
+        } else {
+            8
         }
     }
 
-    fn jump_to_nn_if(&mut self, cond: bool) {
+    /// Returns 16 cycles if the jump is taken, otherwise 12
+    fn jump_to_nn_if(&mut self, cond: bool) -> u8 {
         let nn = self.next_u16();
         if cond {
             self.regs.pc = nn;
+            16
+        } else {
+            12
         }
     }
 
@@ -847,21 +713,34 @@ impl Cpu {
         self.regs.sp.wrapping_add(n)
     }
 
-    fn call_if(&mut self, cond: bool) {
+    /// Returns 24 cycles if the call is made, otherwise 12
+    fn call_if(&mut self, cond: bool) -> u8 {
         let next_addr = self.next_u16(); 
         if cond {
             let next_instr = self.regs.pc;
             self.mem.push_u16(&mut self.regs, next_instr);
             self.regs.pc = next_addr;
+            24
+        } else {
+            12
         }
     }
 
-    fn return_if(&mut self, cond: bool) {
+    /// Returns 20 cycles if returning, otherwise 8. Checking the
+    /// condition costs 4 cycles more than an unconditional RET
+    fn return_if(&mut self, cond: bool) -> u8 {
         if cond {
-            self.regs.pc = self.mem.pop_u16(&mut self.regs);
+            self.return_from_call();
+            20
+        } else {
+            8
         }
     }
 
+    fn return_from_call(&mut self) {
+        self.regs.pc = self.mem.pop_u16(&mut self.regs);
+    }
+
     /// Gets the value of the byte in memory at address stored in HL register
     fn byte_at_hl(&self) -> u8 {
         self.mem.read(self.regs.get_hl())
@@ -874,616 +753,40 @@ impl Cpu {
 
     fn cb_opcode_step(&mut self) -> u8 {
         let cb_opcode = self.next_byte();
-        match cb_opcode {
-            // SWAP A
-            0x37 => { self.regs.a = self.swap_nibles(self.regs.a); 8 },
-            // SWAP B
-            0x30 => { self.regs.b = self.swap_nibles(self.regs.b); 8 },
-            // SWAP C
-            0x31 => { self.regs.c = self.swap_nibles(self.regs.c); 8 },
-            // SWAP D
-            0x32 => { self.regs.d = self.swap_nibles(self.regs.d); 8 },
-            // SWAP E
-            0x33 => { self.regs.e = self.swap_nibles(self.regs.e); 8 },
-            // SWAP H
-            0x34 => { self.regs.h = self.swap_nibles(self.regs.h); 8 },
-            // SWAP L
-            0x35 => { self.regs.l = self.swap_nibles(self.regs.l); 8 },
-            // SWAP (HL)
-            0x36 => { 
-                let value = self.swap_nibles(self.byte_at_hl());
-                self.set_byte_at_hl(value); 
-                16 
-            },
-            // RLC A
-            0x07 => { self.regs.a = self.rotate_left_carry(self.regs.a); 8 },
-            // RLC B
-            0x00 => { self.regs.b = self.rotate_left_carry(self.regs.b); 8 },
-            // RLC C
-            0x01 => { self.regs.c = self.rotate_left_carry(self.regs.c); 8 },
-            // RLC D
-            0x02 => { self.regs.d = self.rotate_left_carry(self.regs.d); 8 },
-            // RLC E
-            0x03 => { self.regs.e = self.rotate_left_carry(self.regs.e); 8 },
-            // RLC H
-            0x04 => { self.regs.h = self.rotate_left_carry(self.regs.h); 8 },
-            // RLC L
-            0x05 => { self.regs.l = self.rotate_left_carry(self.regs.l); 8 },
-            // RLC (HL)
-            0x06 => { 
-                let value = self.rotate_left_carry(self.byte_at_hl());
-                self.set_byte_at_hl(value);
-                16 
-            },
-            // RL A
-            0x17 => { self.regs.a = self.rotate_left(self.regs.a); 8 },
-            // RL B
-            0x10 => { self.regs.b = self.rotate_left(self.regs.b); 8 },
-            // RL C
-            0x11 => { self.regs.c = self.rotate_left(self.regs.c); 8 },
-            // RL D
-            0x12 => { self.regs.d = self.rotate_left(self.regs.d); 8 },
-            // RL E
-            0x13 => { self.regs.e = self.rotate_left(self.regs.e); 8 },
-            // RL H
-            0x14 => { self.regs.h = self.rotate_left(self.regs.h); 8 },
-            // RL L
-            0x15 => { self.regs.l = self.rotate_left(self.regs.l); 8 },
-            // RL (HL)
-            0x16 => { 
-                let value = self.rotate_left(self.byte_at_hl());
-                self.set_byte_at_hl(value);
-                16 
-            },
-            // RRC A
-            0x0F => { self.regs.a = self.rotate_right_carry(self.regs.a); 8 },
-            // RRC B
-            0x08 => { self.regs.b = self.rotate_right_carry(self.regs.b); 8 },
-            // RRC C
-            0x09 => { self.regs.c = self.rotate_right_carry(self.regs.c); 8 },
-            // RRC D
-            0x0A => { self.regs.d = self.rotate_right_carry(self.regs.d); 8 },
-            // RRC E
-            0x0B => { self.regs.e = self.rotate_right_carry(self.regs.e); 8 },
-            // RRC H
-            0x0C => { self.regs.h = self.rotate_right_carry(self.regs.h); 8 },
-            // RRC L
-            0x0D => { self.regs.l = self.rotate_right_carry(self.regs.l); 8 },
-            // RRC (HL)
-            0x0E => { 
-                let value = self.rotate_right_carry(self.byte_at_hl());
-                self.set_byte_at_hl(value);
-                16 
-            },
-            // RR A
-            0x1F => { self.regs.a = self.rotate_right(self.regs.a); 8 },
-            // RR B
-            0x18 => { self.regs.b = self.rotate_right(self.regs.b); 8 },
-            // RR C
-            0x19 => { self.regs.c = self.rotate_right(self.regs.c); 8 },
-            // RR D
-            0x1A => { self.regs.d = self.rotate_right(self.regs.d); 8 },
-            // RR E
-            0x1B => { self.regs.e = self.rotate_right(self.regs.e); 8 },
-            // RR H
-            0x1C => { self.regs.h = self.rotate_right(self.regs.h); 8 },
-            // RR L
-            0x1D => { self.regs.l = self.rotate_right(self.regs.l); 8 },
-            // RR (HL)
-            0x1E => { 
-                let value = self.rotate_right(self.byte_at_hl());
-                self.set_byte_at_hl(value);
-                16 
-            },
-            // SLA A
-            0x27 => { self.regs.a = self.shift_left(self.regs.a); 8 },
-            // SLA B
-            0x20 => { self.regs.b = self.shift_left(self.regs.b); 8 },
-            // SLA C
-            0x21 => { self.regs.c = self.shift_left(self.regs.c); 8 },
-            // SLA D
-            0x22 => { self.regs.d = self.shift_left(self.regs.d); 8 },
-            // SLA E
-            0x23 => { self.regs.e = self.shift_left(self.regs.e); 8 },
-            // SLA H
-            0x24 => { self.regs.h = self.shift_left(self.regs.h); 8 },
-            // SLA L
-            0x25 => { self.regs.l = self.shift_left(self.regs.l); 8 },
-            // SLA (HL)
-            0x26 => {
-                let value = self.shift_left(self.byte_at_hl());
-                self.set_byte_at_hl(value);
-                16 
-            },
-            // SRA A
-            0x2F => { self.regs.a = self.shift_right(self.regs.a); 8 },
-            // SRA B
-            0x28 => { self.regs.b = self.shift_right(self.regs.b); 8 },
-            // SRA C
-            0x29 => { self.regs.c = self.shift_right(self.regs.c); 8 },
-            // SRA D
-            0x2A => { self.regs.d = self.shift_right(self.regs.d); 8 },
-            // SRA E
-            0x2B => { self.regs.e = self.shift_right(self.regs.e); 8 },
-            // SRA H
-            0x2C => { self.regs.h = self.shift_right(self.regs.h); 8 },
-            // SRA L
-            0x2D => { self.regs.l = self.shift_right(self.regs.l); 8 },
-            // SRA (HL)
-            0x2E => {
-                let value = self.shift_right(self.byte_at_hl());
-                self.set_byte_at_hl(value);
-                16 
-            },
-            // SRL A
-            0x3F => { self.regs.a = self.shift_right_zero(self.regs.a); 8 },
-            // SRL B
-            0x38 => { self.regs.b = self.shift_right_zero(self.regs.b); 8 },
-            // SRL C
-            0x39 => { self.regs.c = self.shift_right_zero(self.regs.c); 8 },
-            // SRL D
-            0x3A => { self.regs.d = self.shift_right_zero(self.regs.d); 8 },
-            // SRL E
-            0x3B => { self.regs.e = self.shift_right_zero(self.regs.e); 8 },
-            // SRL H
-            0x3C => { self.regs.h = self.shift_right_zero(self.regs.h); 8 },
-            // SRL L
-            0x3D => { self.regs.l = self.shift_right_zero(self.regs.l); 8 },
-            // SRL (HL)
-            0x3E => {
-                let value = self.shift_right_zero(self.byte_at_hl());
-                self.set_byte_at_hl(value);
-                16 
-            },
-            // BIT 0,A
-            0x47 => { self.test_bit(self.regs.a, 0); 8 },
-            // BIT 0,B
-            0x40 => { self.test_bit(self.regs.b, 0); 8 },
-            // BIT 0,C
-            0x41 => { self.test_bit(self.regs.c, 0); 8 },
-            // BIT 0,D
-            0x42 => { self.test_bit(self.regs.d, 0); 8 },
-            // BIT 0,E
-            0x43 => { self.test_bit(self.regs.e, 0); 8 },
-            // BIT 0,H
-            0x44 => { self.test_bit(self.regs.h, 0); 8 },
-            // BIT 0,L
-            0x45 => { self.test_bit(self.regs.l, 0); 8 },
-            // BIT 0, (HL)
-            0x46 => { self.test_bit(self.byte_at_hl(), 0); 16 },
-            // BIT 1,A
-            0x4F => { self.test_bit(self.regs.a, 1); 8 },
-            // BIT 1,B
-            0x48 => { self.test_bit(self.regs.b, 1); 8 },
-            // BIT 1,C
-            0x49 => { self.test_bit(self.regs.c, 1); 8 },
-            // BIT 1,D
-            0x4A => { self.test_bit(self.regs.d, 1); 8 },
-            // BIT 1,E
-            0x4B => { self.test_bit(self.regs.e, 1); 8 },
-            // BIT 1,H
-            0x4C => { self.test_bit(self.regs.h, 1); 8 },
-            // BIT 1,L
-            0x4D => { self.test_bit(self.regs.l, 1); 8 },
-            // BIT 1, (HL)
-            0x4E => { self.test_bit(self.byte_at_hl(), 1); 16 },
-            // BIT 2,A
-            0x57 => { self.test_bit(self.regs.a, 2); 8 },
-            // BIT 2,B
-            0x50 => { self.test_bit(self.regs.b, 2); 8 },
-            // BIT 2,C
-            0x51 => { self.test_bit(self.regs.c, 2); 8 },
-            // BIT 2,D
-            0x52 => { self.test_bit(self.regs.d, 2); 8 },
-            // BIT 2,E
-            0x53 => { self.test_bit(self.regs.e, 2); 8 },
-            // BIT 2,H
-            0x54 => { self.test_bit(self.regs.h, 2); 8 },
-            // BIT 2,L
-            0x55 => { self.test_bit(self.regs.l, 2); 8 },
-            // BIT 2, (HL)
-            0x56 => { self.test_bit(self.byte_at_hl(), 2); 16 },
-            // BIT 3,A
-            0x5F => { self.test_bit(self.regs.a, 3); 8 },
-            // BIT 3,B
-            0x58 => { self.test_bit(self.regs.b, 3); 8 },
-            // BIT 3,C
-            0x59 => { self.test_bit(self.regs.c, 3); 8 },
-            // BIT 3,D
-            0x5A => { self.test_bit(self.regs.d, 3); 8 },
-            // BIT 3,E
-            0x5B => { self.test_bit(self.regs.e, 3); 8 },
-            // BIT 3,H
-            0x5C => { self.test_bit(self.regs.h, 3); 8 },
-            // BIT 3,L
-            0x5D => { self.test_bit(self.regs.l, 3); 8 },
-            // BIT 3, (HL)
-            0x5E => { self.test_bit(self.byte_at_hl(), 3); 16 },
-            // BIT 4,A
-            0x67 => { self.test_bit(self.regs.a, 4); 8 },
-            // BIT 4,B
-            0x60 => { self.test_bit(self.regs.b, 4); 8 },
-            // BIT 4,C
-            0x61 => { self.test_bit(self.regs.c, 4); 8 },
-            // BIT 4,D
-            0x62 => { self.test_bit(self.regs.d, 4); 8 },
-            // BIT 4,E
-            0x63 => { self.test_bit(self.regs.e, 4); 8 },
-            // BIT 4,H
-            0x64 => { self.test_bit(self.regs.h, 4); 8 },
-            // BIT 4,L
-            0x65 => { self.test_bit(self.regs.l, 4); 8 },
-            // BIT 4, (HL)
-            0x66 => { self.test_bit(self.byte_at_hl(), 4); 16 },
-            // BIT 5,A
-            0x6F => { self.test_bit(self.regs.a, 5); 8 },
-            // BIT 5,B
-            0x68 => { self.test_bit(self.regs.b, 5); 8 },
-            // BIT 5,C
-            0x69 => { self.test_bit(self.regs.c, 5); 8 },
-            // BIT 5,D
-            0x6A => { self.test_bit(self.regs.d, 5); 8 },
-            // BIT 5,E
-            0x6B => { self.test_bit(self.regs.e, 5); 8 },
-            // BIT 5,H
-            0x6C => { self.test_bit(self.regs.h, 5); 8 },
-            // BIT 5,L
-            0x6D => { self.test_bit(self.regs.l, 5); 8 },
-            // BIT 5, (HL)
-            0x6E => { self.test_bit(self.byte_at_hl(), 5); 16 },
-            // BIT 6,A
-            0x77 => { self.test_bit(self.regs.a, 6); 8 },
-            // BIT 6,B
-            0x70 => { self.test_bit(self.regs.b, 6); 8 },
-            // BIT 6,C
-            0x71 => { self.test_bit(self.regs.c, 6); 8 },
-            // BIT 6,D
-            0x72 => { self.test_bit(self.regs.d, 6); 8 },
-            // BIT 6,E
-            0x73 => { self.test_bit(self.regs.e, 6); 8 },
-            // BIT 6,H
-            0x74 => { self.test_bit(self.regs.h, 6); 8 },
-            // BIT 6,L
-            0x75 => { self.test_bit(self.regs.l, 6); 8 },
-            // BIT 6, (HL)
-            0x76 => { self.test_bit(self.byte_at_hl(), 6); 16 },
-            // BIT 7,A
-            0x7F => { self.test_bit(self.regs.a, 7); 8 },
-            // BIT 7,B
-            0x78 => { self.test_bit(self.regs.b, 7); 8 },
-            // BIT 7,C
-            0x79 => { self.test_bit(self.regs.c, 7); 8 },
-            // BIT 7,D
-            0x7A => { self.test_bit(self.regs.d, 7); 8 },
-            // BIT 7,E
-            0x7B => { self.test_bit(self.regs.e, 7); 8 },
-            // BIT 7,H
-            0x7C => { self.test_bit(self.regs.h, 7); 8 },
-            // BIT 7,L
-            0x7D => { self.test_bit(self.regs.l, 7); 8 },
-            // BIT 7, (HL)
-            0x7E => { self.test_bit(self.byte_at_hl(), 7); 16 },
-            // RES 0,A
-            0x87 => { self.regs.a = self.reset_bit(self.regs.a, 0); 8 },
-            // RES 0,B
-            0x80 => { self.regs.b = self.reset_bit(self.regs.b, 0); 8 },
-            // RES 0,C
-            0x81 => { self.regs.c = self.reset_bit(self.regs.c, 0); 8 },
-            // RES 0,D
-            0x82 => { self.regs.d = self.reset_bit(self.regs.d, 0); 8 },
-            // RES 0,E
-            0x83 => { self.regs.e = self.reset_bit(self.regs.e, 0); 8 },
-            // RES 0,H
-            0x84 => { self.regs.h = self.reset_bit(self.regs.h, 0); 8 },
-            // RES 0,L
-            0x85 => { self.regs.l = self.reset_bit(self.regs.l, 0); 8 },
-            // RES 0, (HL)
-            0x86 => {
-                let hl = self.reset_bit(self.byte_at_hl(), 0);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // RES 1,A
-            0x8F => { self.regs.a = self.reset_bit(self.regs.a, 1); 8 },
-            // RES 1,B
-            0x88 => { self.regs.b = self.reset_bit(self.regs.b, 1); 8 },
-            // RES 1,C
-            0x89 => { self.regs.c = self.reset_bit(self.regs.c, 1); 8 },
-            // RES 1,D
-            0x8A => { self.regs.d = self.reset_bit(self.regs.d, 1); 8 },
-            // RES 1,E
-            0x8B => { self.regs.e = self.reset_bit(self.regs.e, 1); 8 },
-            // RES 1,H
-            0x8C => { self.regs.h = self.reset_bit(self.regs.h, 1); 8 },
-            // RES 1,L
-            0x8D => { self.regs.l = self.reset_bit(self.regs.l, 1); 8 },
-            // RES 1, (HL)
-            0x8E => {
-                let hl = self.reset_bit(self.byte_at_hl(), 1);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // RES 2,A
-            0x97 => { self.regs.a = self.reset_bit(self.regs.a, 2); 8 },
-            // RES 2,B
-            0x90 => { self.regs.b = self.reset_bit(self.regs.b, 2); 8 },
-            // RES 2,C
-            0x91 => { self.regs.c = self.reset_bit(self.regs.c, 2); 8 },
-            // RES 2,D
-            0x92 => { self.regs.d = self.reset_bit(self.regs.d, 2); 8 },
-            // RES 2,E
-            0x93 => { self.regs.e = self.reset_bit(self.regs.e, 2); 8 },
-            // RES 2,H
-            0x94 => { self.regs.h = self.reset_bit(self.regs.h, 2); 8 },
-            // RES 2,L
-            0x95 => { self.regs.l = self.reset_bit(self.regs.l, 2); 8 },
-            // RES 2, (HL)
-            0x96 => {
-                let hl = self.reset_bit(self.byte_at_hl(), 2);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // RES 3,A
-            0x9F => { self.regs.a = self.reset_bit(self.regs.a, 3); 8 },
-            // RES 3,B
-            0x98 => { self.regs.b = self.reset_bit(self.regs.b, 3); 8 },
-            // RES 3,C
-            0x99 => { self.regs.c = self.reset_bit(self.regs.c, 3); 8 },
-            // RES 3,D
-            0x9A => { self.regs.d = self.reset_bit(self.regs.d, 3); 8 },
-            // RES 3,E
-            0x9B => { self.regs.e = self.reset_bit(self.regs.e, 3); 8 },
-            // RES 3,H
-            0x9C => { self.regs.h = self.reset_bit(self.regs.h, 3); 8 },
-            // RES 3,L
-            0x9D => { self.regs.l = self.reset_bit(self.regs.l, 3); 8 },
-            // RES 3, (HL)
-            0x9E => {
-                let hl = self.reset_bit(self.byte_at_hl(), 3);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // RES 4,A
-            0xA7 => { self.regs.a = self.reset_bit(self.regs.a, 4); 8 },
-            // RES 4,B
-            0xA0 => { self.regs.b = self.reset_bit(self.regs.b, 4); 8 },
-            // RES 4,C
-            0xA1 => { self.regs.c = self.reset_bit(self.regs.c, 4); 8 },
-            // RES 4,D
-            0xA2 => { self.regs.d = self.reset_bit(self.regs.d, 4); 8 },
-            // RES 4,E
-            0xA3 => { self.regs.e = self.reset_bit(self.regs.e, 4); 8 },
-            // RES 4,H
-            0xA4 => { self.regs.h = self.reset_bit(self.regs.h, 4); 8 },
-            // RES 4,L
-            0xA5 => { self.regs.l = self.reset_bit(self.regs.l, 4); 8 },
-            // RES 4, (HL)
-            0xA6 => {
-                let hl = self.reset_bit(self.byte_at_hl(), 4);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // RES 5,A
-            0xAF => { self.regs.a = self.reset_bit(self.regs.a, 5); 8 },
-            // RES 5,B
-            0xA8 => { self.regs.b = self.reset_bit(self.regs.b, 5); 8 },
-            // RES 5,C
-            0xA9 => { self.regs.c = self.reset_bit(self.regs.c, 5); 8 },
-            // RES 5,D
-            0xAA => { self.regs.d = self.reset_bit(self.regs.d, 5); 8 },
-            // RES 5,E
-            0xAB => { self.regs.e = self.reset_bit(self.regs.e, 5); 8 },
-            // RES 5,H
-            0xAC => { self.regs.h = self.reset_bit(self.regs.h, 5); 8 },
-            // RES 5,L
-            0xAD => { self.regs.l = self.reset_bit(self.regs.l, 5); 8 },
-            // RES 5, (HL)
-            0xAE => { 
-                let hl = self.reset_bit(self.byte_at_hl(), 5);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // RES 6,A
-            0xB7 => { self.regs.a = self.reset_bit(self.regs.a, 6); 8 },
-            // RES 6,B
-            0xB0 => { self.regs.b = self.reset_bit(self.regs.b, 6); 8 },
-            // RES 6,C
-            0xB1 => { self.regs.c = self.reset_bit(self.regs.c, 6); 8 },
-            // RES 6,D
-            0xB2 => { self.regs.d = self.reset_bit(self.regs.d, 6); 8 },
-            // RES 6,E
-            0xB3 => { self.regs.e = self.reset_bit(self.regs.e, 6); 8 },
-            // RES 6,H
-            0xB4 => { self.regs.h = self.reset_bit(self.regs.h, 6); 8 },
-            // RES 6,L
-            0xB5 => { self.regs.l = self.reset_bit(self.regs.l, 6); 8 },
-            // RES 6, (HL)
-            0xB6 => {
-                let hl = self.reset_bit(self.byte_at_hl(), 6);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // RES 7,A
-            0xBF => { self.regs.a = self.reset_bit(self.regs.a, 7); 8 },
-            // RES 7,B
-            0xB8 => { self.regs.b = self.reset_bit(self.regs.b, 7); 8 },
-            // RES 7,C
-            0xB9 => { self.regs.c = self.reset_bit(self.regs.c, 7); 8 },
-            // RES 7,D
-            0xBA => { self.regs.d = self.reset_bit(self.regs.d, 7); 8 },
-            // RES 7,E
-            0xBB => { self.regs.e = self.reset_bit(self.regs.e, 7); 8 },
-            // RES 7,H
-            0xBC => { self.regs.h = self.reset_bit(self.regs.h, 7); 8 },
-            // RES 7,L
-            0xBD => { self.regs.l = self.reset_bit(self.regs.l, 7); 8 },
-            // RES 7, (HL)
-            0xBE => { 
-                let hl = self.reset_bit(self.byte_at_hl(), 7);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // SET 0,A
-            0xC7 => { self.regs.a = self.set_bit(self.regs.a, 0); 8 },
-            // SET 0,B
-            0xC0 => { self.regs.b = self.set_bit(self.regs.b, 0); 8 },
-            // SET 0,C
-            0xC1 => { self.regs.c = self.set_bit(self.regs.c, 0); 8 },
-            // SET 0,D
-            0xC2 => { self.regs.d = self.set_bit(self.regs.d, 0); 8 },
-            // SET 0,E
-            0xC3 => { self.regs.e = self.set_bit(self.regs.e, 0); 8 },
-            // SET 0,H
-            0xC4 => { self.regs.h = self.set_bit(self.regs.h, 0); 8 },
-            // SET 0,L
-            0xC5 => { self.regs.l = self.set_bit(self.regs.l, 0); 8 },
-            // SET 0, (HL)
-            0xC6 => {
-                let hl = self.set_bit(self.byte_at_hl(), 0);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // SET 1,A
-            0xCF => { self.regs.a = self.set_bit(self.regs.a, 1); 8 },
-            // SET 1,B
-            0xC8 => { self.regs.b = self.set_bit(self.regs.b, 1); 8 },
-            // SET 1,C
-            0xC9 => { self.regs.c = self.set_bit(self.regs.c, 1); 8 },
-            // SET 1,D
-            0xCA => { self.regs.d = self.set_bit(self.regs.d, 1); 8 },
-            // SET 1,E
-            0xCB => { self.regs.e = self.set_bit(self.regs.e, 1); 8 },
-            // SET 1,H
-            0xCC => { self.regs.h = self.set_bit(self.regs.h, 1); 8 },
-            // SET 1,L
-            0xCD => { self.regs.l = self.set_bit(self.regs.l, 1); 8 },
-            // SET 1, (HL)
-            0xCE => {
-                let hl = self.set_bit(self.byte_at_hl(), 1);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // SET 2,A
-            0xD7 => { self.regs.a = self.set_bit(self.regs.a, 2); 8 },
-            // SET 2,B
-            0xD0 => { self.regs.b = self.set_bit(self.regs.b, 2); 8 },
-            // SET 2,C
-            0xD1 => { self.regs.c = self.set_bit(self.regs.c, 2); 8 },
-            // SET 2,D
-            0xD2 => { self.regs.d = self.set_bit(self.regs.d, 2); 8 },
-            // SET 2,E
-            0xD3 => { self.regs.e = self.set_bit(self.regs.e, 2); 8 },
-            // SET 2,H
-            0xD4 => { self.regs.h = self.set_bit(self.regs.h, 2); 8 },
-            // SET 2,L
-            0xD5 => { self.regs.l = self.set_bit(self.regs.l, 2); 8 },
-            // SET 2, (HL)
-            0xD6 => {
-                let hl = self.set_bit(self.byte_at_hl(), 2);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // SET 3,A
-            0xDF => { self.regs.a = self.set_bit(self.regs.a, 3); 8 },
-            // SET 3,B
-            0xD8 => { self.regs.b = self.set_bit(self.regs.b, 3); 8 },
-            // SET 3,C
-            0xD9 => { self.regs.c = self.set_bit(self.regs.c, 3); 8 },
-            // SET 3,D
-            0xDA => { self.regs.d = self.set_bit(self.regs.d, 3); 8 },
-            // SET 3,E
-            0xDB => { self.regs.e = self.set_bit(self.regs.e, 3); 8 },
-            // SET 3,H
-            0xDC => { self.regs.h = self.set_bit(self.regs.h, 3); 8 },
-            // SET 3,L
-            0xDD => { self.regs.l = self.set_bit(self.regs.l, 3); 8 },
-            // SET 3, (HL)
-            0xDE => {
-                let hl = self.set_bit(self.byte_at_hl(), 3);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // SET 4,A
-            0xE7 => { self.regs.a = self.set_bit(self.regs.a, 4); 8 },
-            // SET 4,B
-            0xE0 => { self.regs.b = self.set_bit(self.regs.b, 4); 8 },
-            // SET 4,C
-            0xE1 => { self.regs.c = self.set_bit(self.regs.c, 4); 8 },
-            // SET 4,D
-            0xE2 => { self.regs.d = self.set_bit(self.regs.d, 4); 8 },
-            // SET 4,E
-            0xE3 => { self.regs.e = self.set_bit(self.regs.e, 4); 8 },
-            // SET 4,H
-            0xE4 => { self.regs.h = self.set_bit(self.regs.h, 4); 8 },
-            // SET 4,L
-            0xE5 => { self.regs.l = self.set_bit(self.regs.l, 4); 8 },
-            // SET 4, (HL)
-            0xE6 => {
-                let hl = self.set_bit(self.byte_at_hl(), 4);
-                self.set_byte_at_hl(hl);
-                16 
-            },
-            // SET 5,A
-            0xEF => { self.regs.a = self.set_bit(self.regs.a, 5); 8 },
-            // SET 5,B
-            0xE8 => { self.regs.b = self.set_bit(self.regs.b, 5); 8 },
-            // SET 5,C
-            0xE9 => { self.regs.c = self.set_bit(self.regs.c, 5); 8 },
-            // SET 5,D
-            0xEA => { self.regs.d = self.set_bit(self.regs.d, 5); 8 },
-            // SET 5,E
-            0xEB => { self.regs.e = self.set_bit(self.regs.e, 5); 8 },
-            // SET 5,H
-            0xEC => { self.regs.h = self.set_bit(self.regs.h, 5); 8 },
-            // SET 5,L
-            0xED => { self.regs.l = self.set_bit(self.regs.l, 5); 8 },
-            // SET 5, (HL)
-            0xEE => { 
-                let hl = self.set_bit(self.byte_at_hl(), 5);
-                self.set_byte_at_hl(hl);
-                16 
+        let x = cb_opcode >> 6;
+        let y = (cb_opcode >> 3) & 0b111;
+        let z = cb_opcode & 0b111;
+        let value = self.get_r8(z);
+        match x {
+            0 => {
+                let result = match y {
+                    // RLC
+                    0 => self.rotate_left_carry(value),
+                    // RRC
+                    1 => self.rotate_right_carry(value),
+                    // RL
+                    2 => self.rotate_left(value),
+                    // RR
+                    3 => self.rotate_right(value),
+                    // SLA
+                    4 => self.shift_left(value),
+                    // SRA
+                    5 => self.shift_right(value),
+                    // SWAP
+                    6 => self.swap_nibles(value),
+                    // SRL
+                    _ => self.shift_right_zero(value)
+                };
+                self.set_r8(z, result);
             },
-            // SET 6,A
-            0xF7 => { self.regs.a = self.set_bit(self.regs.a, 6); 8 },
-            // SET 6,B
-            0xF0 => { self.regs.b = self.set_bit(self.regs.b, 6); 8 },
-            // SET 6,C
-            0xF1 => { self.regs.c = self.set_bit(self.regs.c, 6); 8 },
-            // SET 6,D
-            0xF2 => { self.regs.d = self.set_bit(self.regs.d, 6); 8 },
-            // SET 6,E
-            0xF3 => { self.regs.e = self.set_bit(self.regs.e, 6); 8 },
-            // SET 6,H
-            0xF4 => { self.regs.h = self.set_bit(self.regs.h, 6); 8 },
-            // SET 6,L
-            0xF5 => { self.regs.l = self.set_bit(self.regs.l, 6); 8 },
-            // SET 6, (HL)
-            0xF6 => {
-                let hl = self.set_bit(self.byte_at_hl(), 6);
-                self.set_byte_at_hl(hl);
-                16 
-		    },
-            // SET 7,A
-            0xFF => { self.regs.a = self.set_bit(self.regs.a, 7); 8 },
-            // SET 7,B
-            0xF8 => { self.regs.b = self.set_bit(self.regs.b, 7); 8 },
-            // SET 7,C
-            0xF9 => { self.regs.c = self.set_bit(self.regs.c, 7); 8 },
-            // SET 7,D
-            0xFA => { self.regs.d = self.set_bit(self.regs.d, 7); 8 },
-            // SET 7,E
-            0xFB => { self.regs.e = self.set_bit(self.regs.e, 7); 8 },
-            // SET 7,H
-            0xFC => { self.regs.h = self.set_bit(self.regs.h, 7); 8 },
-            // SET 7,L
-            0xFD => { self.regs.l = self.set_bit(self.regs.l, 7); 8 },
-            // SET 7, (HL)
-            0xFE => { 
-                let hl = self.set_bit(self.byte_at_hl(), 7);
-                self.set_byte_at_hl(hl);
-                16 
-			}
+            // BIT
+            1 => self.test_bit(value, y),
+            // RES
+            2 => { let result = self.reset_bit(value, y); self.set_r8(z, result); },
+            // SET
+            _ => { let result = self.set_bit(value, y); self.set_r8(z, result); }
         }
+        CB_OPCODES[cb_opcode as usize].cycles
     }
 
     fn rotate_left_carry(&mut self, value: u8) -> u8 {
@@ -1594,4 +897,356 @@ fn half_carry_addition_u16(first: u16, second: u16) -> bool {
 
 // fn half_carry_subtraction_16(first: u8, second: u8) -> bool {
 //     ((first & 0x00FF) as i32 - (second & 0x00FF) as i32) < 0
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cpu(program: &[u8]) -> Cpu {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100 .. 0x100 + program.len()].copy_from_slice(program);
+        let mut cpu = Cpu::new(rom, Gpu::new(false).unwrap()).unwrap();
+        cpu.mem.booting = false;
+        cpu.regs.pc = 0x100;
+        cpu.regs.sp = 0xFFFE;
+        cpu
+    }
+
+    fn request_vblank(cpu: &mut Cpu) {
+        cpu.mem.write(mmu::INTERUPTS_ENABLE, V_BLANK_INTERRUPT);
+        cpu.mem.write(mmu::INTERUPT_REQUEST, V_BLANK_INTERRUPT);
+    }
+
+    #[test]
+    fn test_ei_is_delayed_one_instruction() {
+        // EI; NOP; NOP
+        let mut cpu = test_cpu(&[0xFB, 0x00, 0x00]);
+        request_vblank(&mut cpu);
+        cpu.step_cycles();
+        cpu.step_cycles();
+        // The NOP after EI still runs before the interrupt
+        assert_eq!(cpu.regs.pc, 0x102);
+        assert_eq!(cpu.step_cycles(), 20);
+        assert_eq!(cpu.regs.pc, 0x40);
+        assert_eq!(cpu.mem.read(0xFFFC), 0x02);
+        assert_eq!(cpu.mem.read(mmu::INTERUPT_REQUEST) & V_BLANK_INTERRUPT, 0);
+    }
+
+    #[test]
+    fn test_di_after_ei_blocks_interrupt() {
+        // EI; DI; NOP
+        let mut cpu = test_cpu(&[0xFB, 0xF3, 0x00]);
+        request_vblank(&mut cpu);
+        for _ in 0 .. 3 {
+            cpu.step_cycles();
+        }
+        assert_eq!(cpu.regs.pc, 0x103);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn test_halt_bug_reads_next_byte_twice() {
+        // HALT; INC A
+        let mut cpu = test_cpu(&[0x76, 0x3C]);
+        request_vblank(&mut cpu);
+        for _ in 0 .. 3 {
+            cpu.step_cycles();
+        }
+        assert!(!cpu.halted);
+        assert_eq!(cpu.regs.a, 2);
+        assert_eq!(cpu.regs.pc, 0x102);
+    }
+
+    #[test]
+    fn test_halt_exits_without_ime() {
+        // HALT; NOP
+        let mut cpu = test_cpu(&[0x76, 0x00]);
+        cpu.mem.write(mmu::INTERUPTS_ENABLE, V_BLANK_INTERRUPT);
+        cpu.step_cycles();
+        assert!(cpu.halted);
+        assert_eq!(cpu.step_cycles(), 4);
+        cpu.mem.write(mmu::INTERUPT_REQUEST, V_BLANK_INTERRUPT);
+        cpu.step_cycles();
+        assert!(!cpu.halted);
+        cpu.step_cycles();
+        assert_eq!(cpu.regs.pc, 0x102);
+    }
+
+    #[test]
+    fn test_reti_enables_interrupts_immediately() {
+        let mut cpu = test_cpu(&[0xD9]);
+        cpu.mem.write(0xFFFC, 0x00);
+        cpu.mem.write(0xFFFD, 0x02);
+        cpu.regs.sp = 0xFFFC;
+        request_vblank(&mut cpu);
+        cpu.step_cycles();
+        assert_eq!(cpu.regs.pc, 0x200);
+        assert_eq!(cpu.step_cycles(), 20);
+        assert_eq!(cpu.regs.pc, 0x40);
+    }
+
+    #[test]
+    fn test_push_to_ie_cancels_dispatch() {
+        let mut cpu = test_cpu(&[]);
+        cpu.regs.pc = 0x0200;
+        cpu.regs.sp = 0x0000;
+        cpu.ime = true;
+        request_vblank(&mut cpu);
+        // Pushing the upper PC byte 0x02 to 0xFFFF disables the v-blank interrupt
+        cpu.step_cycles();
+        assert_eq!(cpu.regs.pc, 0x0000);
+        assert_eq!(cpu.mem.read(mmu::INTERUPT_REQUEST) & V_BLANK_INTERRUPT, V_BLANK_INTERRUPT);
+    }
+
+    #[test]
+    fn test_pc_advances_by_opcode_length() {
+        for opcode in 0 ..= 0xFF {
+            let info = &OPCODES[opcode as usize];
+            let branches = ["JR", "JP", "CALL", "RET", "RST"].iter().any(|name| info.mnemonic.starts_with(name));
+            if info.is_illegal() || branches {
+                continue;
+            }
+            let mut cpu = test_cpu(&[opcode, 0x00, 0x00]);
+            cpu.regs.set_hl(0xC000);
+            cpu.step_cycles();
+            assert_eq!(cpu.regs.pc, 0x100 + info.length as u16, "{}", info.mnemonic);
+        }
+    }
+
+    /// M-cycles of every opcode, conditional branches not taken. Illegal opcodes and the CB prefix are 0
+    const OPCODE_M_CYCLES: [u8; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4
+    ];
+
+    /// M-cycles of a conditional branch when taken
+    fn taken_m_cycles(opcode: u8) -> Option<u8> {
+        match opcode {
+            0x20 | 0x28 | 0x30 | 0x38 => Some(3),
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => Some(5),
+            0xC2 | 0xCA | 0xD2 | 0xDA => Some(4),
+            0xC4 | 0xCC | 0xD4 | 0xDC => Some(6),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn test_instruction_timing() {
+        let mut taken = [false; 256];
+        let mut not_taken = [false; 256];
+        for &flags in [0x00, 0xF0].iter() {
+            for opcode in 0 ..= 0xFF {
+                if OPCODE_M_CYCLES[opcode as usize] == 0 {
+                    continue;
+                }
+                // Branch operands and the return address all point away from the next instruction
+                let mut cpu = test_cpu(&[opcode, 0x10, 0x02]);
+                cpu.regs.f = flags;
+                cpu.regs.set_hl(0xC000);
+                cpu.regs.sp = 0xFFFC;
+                cpu.mem.write_u16(0xFFFC, 0x0300);
+                let cycles = cpu.step_cycles();
+
+                let info = opcodes::opcode_info(&[opcode, 0x10]);
+                let branched = cpu.regs.pc != 0x100 + info.length as u16;
+                let m_cycles = match taken_m_cycles(opcode) {
+                    Some(m_cycles) if branched => m_cycles,
+                    _ => OPCODE_M_CYCLES[opcode as usize]
+                };
+                assert_eq!(cycles, m_cycles * 4, "{} with flags {:02X}", info.mnemonic, flags);
+                taken[opcode as usize] |= branched;
+                not_taken[opcode as usize] |= !branched;
+            }
+        }
+        for (opcode, info) in OPCODES.iter().enumerate() {
+            if info.is_conditional() {
+                assert!(taken[opcode] && not_taken[opcode], "{}", info.mnemonic);
+            }
+        }
+        for cb_opcode in 0 ..= 0xFF {
+            let mut cpu = test_cpu(&[0xCB, cb_opcode]);
+            cpu.regs.set_hl(0xC000);
+            // Register operands take 8 cycles, (HL) 16 except for BIT which only reads it
+            let expected = match cb_opcode {
+                0x40 ..= 0x7F if cb_opcode & 7 == 6 => 12,
+                _ if cb_opcode & 7 == 6 => 16,
+                _ => 8
+            };
+            assert_eq!(cpu.step_cycles(), expected, "{}", CB_OPCODES[cb_opcode as usize].mnemonic);
+        }
+    }
+
+    fn rom_with_header() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104 .. 0x134].copy_from_slice(&boot::NINTENDO_LOGO);
+        rom[0x134 .. 0x139].copy_from_slice(b"TITLE");
+        rom[0x14D] = boot::header_checksum(&rom);
+        rom
+    }
+
+    #[test]
+    fn test_skip_boot() {
+        let cpu = Cpu::with_boot_mode(rom_with_header(), Gpu::new(false).unwrap(), BootMode::Skip).unwrap();
+        assert_eq!(cpu.status(), CpuStatus::Running);
+        assert_eq!(cpu.regs.get_af(), 0x01B0);
+        assert_eq!(cpu.regs.get_bc(), 0x0013);
+        assert_eq!(cpu.regs.get_de(), 0x00D8);
+        assert_eq!(cpu.regs.get_hl(), 0x014D);
+        assert_eq!((cpu.regs.sp, cpu.regs.pc), (0xFFFE, 0x0100));
+        assert!(!cpu.mem.booting);
+        assert_eq!(cpu.mem.read(0xFF04), 0xAB);
+        assert_eq!(cpu.mem.read(0xFF40), 0x91);
+        assert_eq!(cpu.mem.read(0xFF47), 0xFC);
+        assert_eq!(cpu.mem.read(0xFF0F), 0xE1);
+        // Logo tiles and map are left in VRAM, the first logo byte $CE becomes rows $F0 $F0 $FC $FC
+        assert_eq!(cpu.mem.read(0x8010), 0xF0);
+        assert_eq!(cpu.mem.read(0x8014), 0xFC);
+        assert_eq!(cpu.mem.read(0x9904), 0x01);
+        assert_eq!(cpu.mem.read(0x9910), 0x19);
+
+        let mut rom = rom_with_header();
+        rom[0x143] = 0x80;
+        rom[0x14D] = boot::header_checksum(&rom);
+        let cpu = Cpu::with_boot_mode(rom, Gpu::with_model(Model::Agb, true).unwrap(), BootMode::Skip).unwrap();
+        assert_eq!(cpu.regs.get_af(), 0x1100);
+        assert_eq!(cpu.regs.get_bc(), 0x0100);
+        assert_eq!(cpu.regs.get_de(), 0xFF56);
+    }
+
+    #[test]
+    fn test_model_selection() {
+        let mut rom = rom_with_header();
+        assert_eq!(Model::detect(&rom), Model::Dmg);
+        rom[0x143] = 0x80;
+        assert_eq!(Model::detect(&rom), Model::Cgb);
+        assert!(Model::Agb.color_mode_for(&rom, false));
+        assert!(!Model::Agb.color_mode_for(&rom, true));
+        assert!(!Model::Sgb.color_mode_for(&rom, false));
+        assert!(Gpu::with_model(Model::Mgb, true).is_err());
+
+        let cpu = Cpu::new(rom, Gpu::with_model(Model::Cgb, false).unwrap()).unwrap();
+        assert_eq!(cpu.model(), Model::Cgb);
+        assert_eq!(cpu.mem.model().wram_banks(), 8);
+    }
+
+    #[test]
+    fn test_finish_boot_sets_model_registers() {
+        // LD A,$11; LDH ($50),A like the end of the CGB boot ROM
+        let mut boot_rom = vec![0; 0x900];
+        boot_rom[.. 4].copy_from_slice(&[0x3E, 0x11, 0xE0, 0x50]);
+        let gpu = Gpu::with_model(Model::Agb, false).unwrap();
+        let mut cpu = Cpu::with_boot_mode(rom_with_header(), gpu, BootMode::External(boot_rom)).unwrap();
+        cpu.step_cycles();
+        assert_eq!(cpu.regs.b, 0x00);
+        cpu.step_cycles();
+        assert!(!cpu.mem.booting);
+        assert_eq!(cpu.regs.a, 0x11);
+        assert_eq!(cpu.regs.b, 0x01);
+    }
+
+    #[test]
+    fn test_skip_boot_header_check() {
+        let mut rom = rom_with_header();
+        // Only DMG and MGB check the second half of the logo
+        rom[0x130] = 0;
+        assert!(!header_check(&rom, Model::Dmg));
+        assert!(header_check(&rom, Model::Cgb));
+        let cpu = Cpu::with_boot_mode(rom.clone(), Gpu::with_model(Model::Mgb, false).unwrap(), BootMode::Skip).unwrap();
+        assert_eq!(cpu.status(), CpuStatus::BootFailed);
+
+        rom[0x14D] ^= 1;
+        assert!(!header_check(&rom, Model::Cgb));
+
+        // Without a header there is nothing to check
+        let cpu = Cpu::with_boot_mode(vec![0; 0x100], Gpu::new(false).unwrap(), BootMode::Skip).unwrap();
+        assert_eq!(cpu.status(), CpuStatus::BootFailed);
+        assert_eq!(cpu.regs.pc, 0x100);
+    }
+
+    #[test]
+    fn test_unsupported_cartridges() {
+        let mut rom = rom_with_header();
+        rom[0x147] = 0x13;
+        let error = Cpu::new(rom.clone(), Gpu::new(false).unwrap()).err().unwrap();
+        assert_eq!(error, "Unsupported cartridge type $13 (MBC3+RAM+BATTERY)");
+        rom[0x147] = 0x00;
+        rom[0x148] = 0x52;
+        let error = Cpu::with_boot_mode(rom, Gpu::new(false).unwrap(), BootMode::Skip).err().unwrap();
+        assert_eq!(error, "Unsupported ROM size $52 in the cartridge header");
+    }
+
+    #[test]
+    fn test_external_boot_rom_size() {
+        let boot_mode = BootMode::External(vec![0; 0x100]);
+        assert!(Cpu::with_boot_mode(rom_with_header(), Gpu::new(false).unwrap(), boot_mode).is_ok());
+        let boot_mode = BootMode::External(vec![0; 0x100]);
+        assert!(Cpu::with_boot_mode(rom_with_header(), Gpu::new(true).unwrap(), boot_mode).is_err());
+        // A CGB running a DMG cartridge still needs the CGB boot ROM
+        let boot_mode = BootMode::External(vec![0; 0x900]);
+        assert!(Cpu::with_boot_mode(rom_with_header(), Gpu::with_model(Model::Cgb, false).unwrap(), boot_mode).is_ok());
+        let boot_mode = BootMode::External(vec![0; 0x100]);
+        let error = Cpu::with_boot_mode(rom_with_header(), Gpu::with_model(Model::Agb, false).unwrap(), boot_mode).err().unwrap();
+        assert_eq!(error, "Boot ROM is 256 bytes, expected 2304 bytes for the AGB model");
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_cpu() {
+        // NOP; illegal 0xD3; INC A
+        let mut cpu = test_cpu(&[0x00, 0xD3, 0x3C]);
+        cpu.step_cycles();
+        assert_eq!(cpu.status(), CpuStatus::Running);
+        cpu.step_cycles();
+        assert_eq!(cpu.status(), CpuStatus::Locked { opcode: 0xD3, address: 0x101 });
+        cpu.ime = true;
+        request_vblank(&mut cpu);
+        for _ in 0 .. 10 {
+            assert_eq!(cpu.step_cycles(), 4);
+        }
+        assert_eq!(cpu.regs.pc, 0x102);
+        assert_eq!(cpu.regs.a, 0);
+    }
+
+    #[test]
+    fn test_decoded_operands() {
+        // LD D,(HL); ADD A,$05; SET 3,(HL); BIT 3,(HL); PUSH DE; POP AF
+        let mut cpu = test_cpu(&[0x56, 0xC6, 0x05, 0xCB, 0xDE, 0xCB, 0x5E, 0xD5, 0xF1]);
+        cpu.regs.set_hl(0xC000);
+        cpu.mem.write(0xC000, 0x21);
+        cpu.regs.e = 0x0F;
+        cpu.step_cycles();
+        assert_eq!(cpu.regs.d, 0x21);
+        cpu.step_cycles();
+        assert_eq!(cpu.regs.a, 0x05);
+        assert_eq!(cpu.step_cycles(), 16);
+        assert_eq!(cpu.mem.read(0xC000), 0x29);
+        assert_eq!(cpu.step_cycles(), 12);
+        assert!(!cpu.regs.zero_flag());
+        cpu.step_cycles();
+        cpu.step_cycles();
+        assert_eq!(cpu.regs.get_af(), 0x2100);
+    }
+
+    #[test]
+    fn test_interrupt_request_upper_bits_read_set() {
+        let mut cpu = test_cpu(&[]);
+        cpu.mem.write(mmu::INTERUPT_REQUEST, 0x01);
+        assert_eq!(cpu.mem.read(mmu::INTERUPT_REQUEST), 0xE1);
+        cpu.mem.write(mmu::INTERUPT_REQUEST, 0xFF);
+        assert_eq!(cpu.mem.read(mmu::INTERUPT_REQUEST), 0xFF);
+    }
+}
@@ -0,0 +1,45 @@
+/// The Game Boy hardware revision being emulated
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Model {
+    /// Original Game Boy
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    /// Game Boy Color
+    Cgb,
+    /// Game Boy Advance running Game Boy software
+    Agb
+}
+
+impl Model {
+    /// Picks CGB for cartridges with color support and DMG otherwise
+    pub fn detect(rom: &[u8]) -> Model {
+        if cartridge_supports_color(rom) { Model::Cgb } else { Model::Dmg }
+    }
+
+    /// Whether the model has the color hardware of the CGB
+    pub fn is_color(self) -> bool {
+        match self {
+            Model::Dmg | Model::Mgb | Model::Sgb => false,
+            Model::Cgb | Model::Agb => true
+        }
+    }
+
+    /// Whether `rom` runs in color mode on this model. `force_dmg_mode` runs
+    /// color enhanced cartridges in DMG mode even on color models
+    pub fn color_mode_for(self, rom: &[u8], force_dmg_mode: bool) -> bool {
+        self.is_color() && cartridge_supports_color(rom) && !force_dmg_mode
+    }
+
+    /// Number of 4KB WRAM banks
+    pub fn wram_banks(self) -> usize {
+        if self.is_color() { 8 } else { 2 }
+    }
+}
+
+/// Header byte 0x143 has bit 7 set for CGB enhanced and CGB only cartridges
+fn cartridge_supports_color(rom: &[u8]) -> bool {
+    rom.len() > 0x143 && rom[0x143] & 0x80 > 0
+}
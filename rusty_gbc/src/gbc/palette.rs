@@ -0,0 +1,164 @@
+use crate::Color;
+
+/// The four shades used when rendering in DMG (non color) mode, one set each
+/// for the background/window and the two sprite palettes. Index 0 is the lightest shade.
+#[derive(Copy, Clone)]
+pub struct MonochromePalette {
+    pub bg: [Color; 4],
+    pub obj0: [Color; 4],
+    pub obj1: [Color; 4]
+}
+
+impl MonochromePalette {
+    /// Uses the same four shades for the background and both sprite palettes
+    pub const fn uniform(shades: [Color; 4]) -> Self {
+        MonochromePalette {
+            bg: shades,
+            obj0: shades,
+            obj1: shades
+        }
+    }
+
+    /// Green tinted shades similar to the original DMG screen
+    pub const fn original_green() -> Self {
+        MonochromePalette::uniform([
+            Color::rgb(0x9B, 0xBC, 0x0F),
+            Color::rgb(0x8B, 0xAC, 0x0F),
+            Color::rgb(0x30, 0x62, 0x30),
+            Color::rgb(0x0F, 0x38, 0x0F)
+        ])
+    }
+
+    /// Neutral grey shades of the Game Boy Pocket, lower contrast than pure greyscale
+    pub const fn pocket_grey() -> Self {
+        MonochromePalette::uniform([
+            Color::rgb(0xC8, 0xC8, 0xC8),
+            Color::rgb(0x8E, 0x8E, 0x8E),
+            Color::rgb(0x50, 0x50, 0x50),
+            Color::rgb(0x1F, 0x1F, 0x1F)
+        ])
+    }
+
+    /// Colors the CGB boot ROM gives DMG cartridges it has no specific palette for
+    pub const fn cgb_compatibility() -> Self {
+        let obj = [
+            Color::rgb(0xFF, 0xFF, 0xFF),
+            Color::rgb(0xFF, 0x84, 0x84),
+            Color::rgb(0x94, 0x3A, 0x3A),
+            Color::rgb(0x00, 0x00, 0x00)
+        ];
+        MonochromePalette {
+            bg: [
+                Color::rgb(0xFF, 0xFF, 0xFF),
+                Color::rgb(0x7B, 0xFF, 0x31),
+                Color::rgb(0x00, 0x63, 0xC5),
+                Color::rgb(0x00, 0x00, 0x00)
+            ],
+            obj0: obj,
+            obj1: obj
+        }
+    }
+
+    /// Pure black and white shades
+    pub const fn greyscale() -> Self {
+        MonochromePalette::uniform([
+            Color::rgb(0xFF, 0xFF, 0xFF),
+            Color::rgb(0xAA, 0xAA, 0xAA),
+            Color::rgb(0x55, 0x55, 0x55),
+            Color::rgb(0x00, 0x00, 0x00)
+        ])
+    }
+}
+
+impl Default for MonochromePalette {
+    /// The light green shades rusty_gbc has always used
+    fn default() -> Self {
+        MonochromePalette::uniform([
+            Color::rgb(0xE6, 0xFF, 0xE6),
+            Color::rgb(0x70, 0xDB, 0x70),
+            Color::rgb(0x40, 0x80, 0x00),
+            Color::rgb(0x00, 0x00, 0x00)
+        ])
+    }
+}
+
+/// How 15 bit CGB colors are converted to 24 bit RGB
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorCorrection {
+    /// Expand each 5 bit channel to 8 bits with no correction, colors appear more saturated than on hardware
+    Raw,
+    /// Mix channels to approximate the washed out colors of the CGB LCD
+    CgbLcd,
+    /// Darker curve approximating the unlit AGB screen running CGB games
+    AgbLcd
+}
+
+impl Default for ColorCorrection {
+    fn default() -> Self {
+        ColorCorrection::Raw
+    }
+}
+
+impl ColorCorrection {
+    /// Converts a little endian 15 bit CGB color (xBBBBBGGGGGRRRRR) to RGB
+    pub fn convert(self, color: u16) -> Color {
+        let r = (color & 0b11111) as u32;
+        let g = ((color >> 5) & 0b11111) as u32;
+        let b = ((color >> 10) & 0b11111) as u32;
+        match self {
+            ColorCorrection::Raw => {
+                let to_8_bit_color = |c: u32| ((c << 3) | (c >> 2)) as u8;
+                Color::rgb(to_8_bit_color(r), to_8_bit_color(g), to_8_bit_color(b))
+            },
+            ColorCorrection::CgbLcd => {
+                // Channel mixing used by gambatte and higan, results are at most 960 before scaling
+                let mix = |c: u32| (c.min(960) >> 2) as u8;
+                Color::rgb(
+                    mix(r * 26 + g * 4 + b * 2),
+                    mix(g * 24 + b * 8),
+                    mix(r * 6 + g * 4 + b * 22)
+                )
+            },
+            ColorCorrection::AgbLcd => {
+                // Linearise with the LCD gamma, mix channels, then re-encode for a 2.2 gamma display
+                let linear = |c: u32| (c as f32 / 31.0).powf(4.0);
+                let (lr, lg, lb) = (linear(r), linear(g), linear(b));
+                let encode = |c: f32| ((c.min(1.0).powf(1.0 / 2.2)) * 255.0).round() as u8;
+                Color::rgb(
+                    encode((lr * 200.0 + lg * 60.0 + lb * 10.0) / 255.0),
+                    encode((lr * 10.0 + lg * 250.0 + lb * 30.0) / 255.0),
+                    encode((lr * 5.0 + lg * 30.0 + lb * 220.0) / 255.0)
+                )
+            }
+        }
+    }
+
+    /// Precomputes the converted RGB value for every 15 bit color
+    pub(crate) fn lookup_table(self) -> Vec<Color> {
+        (0 ..= 0x7FFF).map(|color| self.convert(color)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_expands_full_range() {
+        let white = ColorCorrection::Raw.convert(0x7FFF);
+        assert_eq!((white.r, white.g, white.b), (0xFF, 0xFF, 0xFF));
+        let red = ColorCorrection::Raw.convert(0b11111);
+        assert_eq!((red.r, red.g, red.b), (0xFF, 0, 0));
+    }
+
+    #[test]
+    fn test_corrected_curves_keep_black_and_mix_channels() {
+        for correction in [ColorCorrection::CgbLcd, ColorCorrection::AgbLcd].iter() {
+            let black = correction.convert(0);
+            assert_eq!((black.r, black.g, black.b), (0, 0, 0));
+            // Pure red bleeds into the blue channel on both screens
+            let red = correction.convert(0b11111);
+            assert!(red.r > 0 && red.b > 0 && red.r > red.b);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use super::serial::SerialDevice;
+use crate::png;
+use std::path::PathBuf;
+
+/// Width of the paper in pixels, each DATA packet row holds 20 tiles
+pub const PRINTER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+/// Tile data the printer can hold, 9 packets of 2 tile rows
+const BUFFER_SIZE: usize = 0x280 * 9;
+/// Reply to the first byte after the checksum identifying the device as a printer
+const PRINTER_ID: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0b00000001;
+const STATUS_PRINTING: u8 = 0b00000010;
+const STATUS_DATA_FULL: u8 = 0b00000100;
+const STATUS_UNPROCESSED: u8 = 0b00001000;
+/// STATUS packets reporting busy after a PRINT before the printer reports it finished
+const PRINTING_POLLS: u8 = 4;
+/// Gray levels of the 4 shades in written images
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status
+}
+
+/// Game Boy Printer on the link port. Packets start with 0x88 0x33, followed by the command,
+/// compression flag, 16 bit length, data and 16 bit checksum, then two bytes where the printer
+/// replies with its ID and status. Printed strips are stacked until a PRINT feeds paper after
+/// them, which completes a page that is written as a PNG file to the output directory
+pub struct Printer {
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    printing_polls: u8,
+    /// Tile data received since the last PRINT
+    tile_data: Vec<u8>,
+    /// Shades of the page being printed, `PRINTER_WIDTH` per row
+    page: Vec<u8>,
+    last_page: Vec<u8>,
+    output_dir: Option<PathBuf>,
+    pages_printed: usize
+}
+
+impl Printer {
+    /// A printer writing pages as `print_N.png` into `output_dir`, or keeping only the last page if `None`
+    pub fn new(output_dir: Option<PathBuf>) -> Self {
+        Printer {
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            printing_polls: 0,
+            tile_data: Vec::new(),
+            page: Vec::new(),
+            last_page: Vec::new(),
+            output_dir,
+            pages_printed: 0
+        }
+    }
+
+    /// Shades 0-3 of the last completed page, `PRINTER_WIDTH` pixels per row, empty before the first page
+    pub fn last_page(&self) -> &[u8] {
+        &self.last_page
+    }
+
+    pub fn pages_printed(&self) -> usize {
+        self.pages_printed
+    }
+
+    fn execute(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+        match self.command {
+            COMMAND_INIT => {
+                self.tile_data.clear();
+                self.status = 0;
+                self.printing_polls = 0;
+            },
+            COMMAND_DATA if self.packet.is_empty() => {
+                // An empty DATA packet ends the data to print
+                self.status |= STATUS_DATA_FULL;
+            },
+            COMMAND_DATA => {
+                let data = if self.compressed { decompress(&self.packet) } else { self.packet.clone() };
+                let space = BUFFER_SIZE - self.tile_data.len();
+                self.tile_data.extend_from_slice(&data[.. data.len().min(space)]);
+                self.status |= STATUS_UNPROCESSED;
+            },
+            COMMAND_PRINT if self.packet.len() >= 4 => {
+                let margins = self.packet[1];
+                let palette = self.packet[2];
+                self.print_strip(palette);
+                if margins & 0x0F > 0 {
+                    self.finish_page();
+                }
+                self.status = (self.status & !(STATUS_UNPROCESSED | STATUS_DATA_FULL)) | STATUS_PRINTING;
+                self.printing_polls = PRINTING_POLLS;
+            },
+            COMMAND_STATUS if self.printing_polls > 0 => {
+                self.printing_polls -= 1;
+                if self.printing_polls == 0 {
+                    self.status &= !STATUS_PRINTING;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /// Decodes the buffered tiles, 20 per row of 8 pixels, and adds them to the page
+    fn print_strip(&mut self, palette: u8) {
+        let rows = self.tile_data.len() / (TILES_PER_ROW * 16);
+        for tile_row in 0 .. rows {
+            for y in 0 .. 8 {
+                for x in 0 .. PRINTER_WIDTH {
+                    let tile = tile_row * TILES_PER_ROW + x / 8;
+                    let offset = tile * 16 + y * 2;
+                    let bit = 7 - x % 8;
+                    let color = (self.tile_data[offset] >> bit) & 1 | ((self.tile_data[offset + 1] >> bit) & 1) << 1;
+                    self.page.push((palette >> (color * 2)) & 0b11);
+                }
+            }
+        }
+        self.tile_data.clear();
+    }
+
+    fn finish_page(&mut self) {
+        if self.page.is_empty() {
+            return;
+        }
+        let page = self.page.split_off(0);
+        self.pages_printed += 1;
+        if let Some(dir) = self.output_dir.as_ref() {
+            let pixels: Vec<u8> = page.iter().map(|shade| SHADES[*shade as usize]).collect();
+            let height = (page.len() / PRINTER_WIDTH) as u32;
+            let path = dir.join(format!("print_{}.png", self.pages_printed));
+            if let Err(e) = std::fs::write(&path, png::encode_grayscale(PRINTER_WIDTH as u32, height, &pixels)) {
+                println!("Could not write {}: {}", path.display(), e);
+            }
+        }
+        self.last_page = page;
+    }
+}
+
+/// Expands the printer RLE, where a control byte with bit 7 set repeats the next byte
+/// (control & 0x7F) + 2 times, and otherwise is followed by (control + 1) literal bytes
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(0x280);
+    let mut position = 0;
+    while position < data.len() {
+        let control = data[position];
+        position += 1;
+        if control & 0x80 > 0 {
+            if let Some(byte) = data.get(position) {
+                let len = output.len() + (control & 0x7F) as usize + 2;
+                output.resize(len, *byte);
+            }
+            position += 1;
+        } else {
+            let end = (position + control as usize + 1).min(data.len());
+            output.extend_from_slice(&data[position .. end]);
+            position = end;
+        }
+    }
+    output
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.state = match self.state {
+            PacketState::Magic1 if byte == 0x88 => PacketState::Magic2,
+            PacketState::Magic1 => PacketState::Magic1,
+            PacketState::Magic2 if byte == 0x33 => PacketState::Command,
+            PacketState::Magic2 => PacketState::Magic1,
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                PacketState::Compression
+            },
+            PacketState::Compression => {
+                self.compressed = byte & 1 > 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLow
+            },
+            PacketState::LengthLow => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHigh
+            },
+            PacketState::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length > 0 { PacketState::Data } else { PacketState::ChecksumLow }
+            },
+            PacketState::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length { PacketState::ChecksumLow } else { PacketState::Data }
+            },
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHigh
+            },
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.execute();
+                PacketState::Alive
+            },
+            PacketState::Alive => {
+                reply = PRINTER_ID;
+                PacketState::Status
+            },
+            PacketState::Status => {
+                reply = self.status;
+                PacketState::Magic1
+            }
+        };
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends a packet and returns the two reply bytes
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![command, if compressed { 1 } else { 0 }, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        printer.exchange(0x88);
+        printer.exchange(0x33);
+        for byte in packet.iter() {
+            assert_eq!(printer.exchange(*byte), 0);
+        }
+        printer.exchange(checksum as u8);
+        printer.exchange((checksum >> 8) as u8);
+        (printer.exchange(0), printer.exchange(0))
+    }
+
+    #[test]
+    fn test_print_page() {
+        let mut printer = Printer::new(None);
+        assert_eq!(send_packet(&mut printer, COMMAND_INIT, false, &[]), (PRINTER_ID, 0));
+        // Two rows of tiles with every pixel color 3
+        let (_, status) = send_packet(&mut printer, COMMAND_DATA, false, &[0xFF; 0x280]);
+        assert_eq!(status, STATUS_UNPROCESSED);
+        send_packet(&mut printer, COMMAND_DATA, false, &[]);
+        // One sheet with a margin after, palette mapping color 3 to shade 2
+        let (_, status) = send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x03, 0b10_00_00_00, 0x40]);
+        assert_eq!(status & STATUS_PRINTING, STATUS_PRINTING);
+        for _ in 0 .. PRINTING_POLLS {
+            send_packet(&mut printer, COMMAND_STATUS, false, &[]);
+        }
+        assert_eq!(send_packet(&mut printer, COMMAND_STATUS, false, &[]).1, 0);
+
+        let page = printer.last_page();
+        assert_eq!(page.len(), PRINTER_WIDTH * 16);
+        assert!(page.iter().all(|shade| *shade == 2));
+        assert_eq!(printer.pages_printed(), 1);
+    }
+
+    #[test]
+    fn test_strips_stack_until_margin() {
+        let mut printer = Printer::new(None);
+        send_packet(&mut printer, COMMAND_DATA, false, &[0x00; 0x280]);
+        send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x00, 0xE4, 0x40]);
+        assert!(printer.last_page().is_empty());
+        send_packet(&mut printer, COMMAND_DATA, false, &[0xFF; 0x280]);
+        send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x03, 0xE4, 0x40]);
+        let page = printer.last_page();
+        assert_eq!(page.len(), PRINTER_WIDTH * 32);
+        assert_eq!(page[0], 0);
+        assert_eq!(page[page.len() - 1], 3);
+    }
+
+    #[test]
+    fn test_rle_and_checksum() {
+        assert_eq!(decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34]), vec![0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+        let mut printer = Printer::new(None);
+        printer.exchange(0x88);
+        printer.exchange(0x33);
+        for byte in [COMMAND_INIT, 0, 0, 0, 0xFF, 0xFF].iter() {
+            printer.exchange(*byte);
+        }
+        assert_eq!(printer.exchange(0), PRINTER_ID);
+        assert_eq!(printer.exchange(0), STATUS_CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn test_writes_png() {
+        let dir = std::env::temp_dir().join("rusty_gbc_printer_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut printer = Printer::new(Some(dir.clone()));
+        send_packet(&mut printer, COMMAND_DATA, true, &[0xFF, 0x55, 0xFF, 0x55, 0xBE, 0x55]);
+        send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x01, 0xE4, 0x40]);
+        let png = std::fs::read(dir.join("print_1.png")).unwrap();
+        assert_eq!(&png[1 .. 4], b"PNG");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use super::SERIAL_INTERRUPT;
+use std::str;
+
+const TRANSFER_START: u8 = 0b10000000;
+const FAST_CLOCK: u8 = 0b00000010;
+const INTERNAL_CLOCK: u8 = 0b00000001;
+/// A byte takes 8 bits at 8192Hz with the internal clock, or 32 times faster with the CGB fast clock
+const TRANSFER_CYCLES: u32 = 4096;
+
+/// Something plugged into the link port
+pub trait SerialDevice {
+    /// Receives the byte the Game Boy shifts out and returns the byte shifted in at the same time
+    fn exchange(&mut self, byte: u8) -> u8;
+}
+
+/// Prints each byte sent as text, which is how test ROMs report their results. Nothing is sent back
+pub struct StdoutSerial;
+
+impl SerialDevice for StdoutSerial {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if let Ok(s) = str::from_utf8(&[byte]) {
+            print!("{}", s);
+        }
+        0xFF
+    }
+}
+
+/// The SB and SC registers. Transfers using the internal clock complete after 8 bit periods,
+/// exchanging SB with the connected device, or receiving 0xFF if there is none
+pub struct Serial {
+    data: u8,
+    control: u8,
+    color_mode: bool,
+    /// Cycles left in the transfer in progress, 0 when idle
+    cycles: u32,
+    device: Option<Box<dyn SerialDevice>>,
+    pub interrupt: u8
+}
+
+impl Serial {
+    pub fn new(color_mode: bool) -> Self {
+        Serial {
+            data: 0,
+            control: 0,
+            color_mode,
+            cycles: 0,
+            device: None,
+            interrupt: 0
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.device.take()
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.data = value;
+    }
+
+    pub fn read_control(&self) -> u8 {
+        // Unused bits read as 1, the clock speed bit only exists on color models
+        self.control | if self.color_mode { 0b01111100 } else { 0b01111110 }
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        let speed_mask = if self.color_mode { FAST_CLOCK } else { 0 };
+        self.control = value & (TRANSFER_START | speed_mask | INTERNAL_CLOCK);
+        // With the external clock the transfer waits for another Game Boy that never clocks it
+        if self.control & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START | INTERNAL_CLOCK {
+            self.cycles = if self.control & FAST_CLOCK > 0 { TRANSFER_CYCLES / 32 } else { TRANSFER_CYCLES };
+        } else {
+            self.cycles = 0;
+        }
+    }
+
+    pub fn step(&mut self, cycles: u8) {
+        if self.cycles == 0 {
+            return;
+        }
+        self.cycles = self.cycles.saturating_sub(cycles as u32);
+        if self.cycles == 0 {
+            self.data = match self.device.as_mut() {
+                Some(device) => device.exchange(self.data),
+                None => 0xFF
+            };
+            self.control &= !TRANSFER_START;
+            self.interrupt = SERIAL_INTERRUPT;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl SerialDevice for Echo {
+        fn exchange(&mut self, byte: u8) -> u8 {
+            byte.wrapping_add(1)
+        }
+    }
+
+    #[test]
+    fn test_transfer_timing() {
+        let mut serial = Serial::new(false);
+        serial.connect(Box::new(Echo));
+        serial.write_data(0x41);
+        serial.write_control(0x81);
+        for _ in 0 .. TRANSFER_CYCLES / 4 - 1 {
+            serial.step(4);
+        }
+        assert_eq!(serial.read_control(), 0xFF);
+        assert_eq!(serial.interrupt, 0);
+        serial.step(4);
+        assert_eq!(serial.read_data(), 0x42);
+        assert_eq!(serial.read_control(), 0x7F);
+        assert_eq!(serial.interrupt, SERIAL_INTERRUPT);
+    }
+
+    #[test]
+    fn test_no_device_and_external_clock() {
+        let mut serial = Serial::new(true);
+        serial.write_control(0x80);
+        serial.step(255);
+        assert_eq!(serial.read_control(), 0xFC);
+        serial.write_control(0x83);
+        for _ in 0 .. TRANSFER_CYCLES / 32 / 4 {
+            serial.step(4);
+        }
+        assert_eq!(serial.read_data(), 0xFF);
+        assert_eq!(serial.read_control(), 0x7F);
+    }
+}
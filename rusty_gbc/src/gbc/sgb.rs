@@ -0,0 +1,558 @@
+use crate::{Color, FrameBuffer, SCREEN_WIDTH, SCREEN_HEIGHT};
+use super::gpu::Gpu;
+use super::input::Input;
+use super::palette::ColorCorrection;
+
+/// Size of the SGB output including the border
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+/// Position of the Game Boy screen within the SGB output
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+const PACKET_SIZE: usize = 16;
+/// Attribute cells of 8x8 pixels covering the screen
+const ATTR_WIDTH: usize = 20;
+const ATTR_HEIGHT: usize = 18;
+const ATTR_FILE_SIZE: usize = 90;
+const ATTR_FILES: usize = 45;
+const SYSTEM_PALETTES: usize = 512;
+const BORDER_TILES: usize = 256;
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_MAP_HEIGHT: usize = 28;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+/// How the Game Boy screen is hidden while a game updates SGB data
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Mask {
+    None,
+    /// Keep showing the screen as it was when masking started
+    Freeze,
+    Black,
+    /// Fill the screen with color 0
+    Color0
+}
+
+/// Whether a cartridge header enables SGB functions, the SGB ignores packets from other cartridges
+pub fn supports_sgb(rom: &[u8]) -> bool {
+    rom.len() > 0x14B && rom[0x146] == 0x03 && rom[0x14B] == 0x33
+}
+
+/// Super Game Boy commands sent as packets over P14/P15 of the joypad register,
+/// the colorized screen and border are drawn by `render`
+pub struct Sgb {
+    /// P14/P15 as last written
+    lines: u8,
+    /// A reset pulse started a packet that is still being received
+    receiving: bool,
+    bit_count: usize,
+    packet: [u8; PACKET_SIZE],
+    /// Packets of the command being received
+    command: Vec<u8>,
+    /// 15 bit colors of the four screen palettes, color 0 of palette 0 is shared by all
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    /// Palette of each 8x8 cell of the screen
+    attributes: [u8; ATTR_WIDTH * ATTR_HEIGHT],
+    attribute_files: Vec<u8>,
+    /// 4bpp SNES tiles
+    border_tiles: Vec<u8>,
+    /// Tile number, palette and flip bits of each border tile
+    border_map: Vec<u16>,
+    /// Border palettes 4-7
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    frozen: Vec<u8>
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            lines: 0b00110000,
+            receiving: false,
+            bit_count: 0,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            // Shades of grey until a game sets its own palettes
+            palettes: [[0x7FFF, 0x56B5, 0x294A, 0x0000]; 4],
+            system_palettes: vec![[0; 4]; SYSTEM_PALETTES],
+            attributes: [0; ATTR_WIDTH * ATTR_HEIGHT],
+            attribute_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+            border_tiles: vec![0; BORDER_TILES * 32],
+            border_map: vec![0; BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            frozen: vec![0; SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize]
+        }
+    }
+
+    /// Receives packet bits from writes to the joypad register. Pulling P14 and P15 low
+    /// resets the transfer, then each pulse of P14 low sends a 0 and P15 low a 1, least
+    /// significant bit first, with both lines high in between
+    pub fn write_joypad(&mut self, value: u8, gpu: &Gpu, input: &mut Input) {
+        let lines = value & 0b00110000;
+        let previous = self.lines;
+        self.lines = lines;
+        if lines == 0 {
+            self.receiving = true;
+            self.bit_count = 0;
+            self.packet = [0; PACKET_SIZE];
+            return;
+        }
+        if !self.receiving || previous != 0b00110000 || lines == 0b00110000 {
+            return;
+        }
+        if self.bit_count == PACKET_SIZE * 8 {
+            // The stop bit after 128 data bits ends the packet
+            self.receiving = false;
+            self.packet_received(gpu, input);
+            return;
+        }
+        if lines == 0b00010000 {
+            self.packet[self.bit_count / 8] |= 1 << (self.bit_count % 8);
+        }
+        self.bit_count += 1;
+    }
+
+    fn packet_received(&mut self, gpu: &Gpu, input: &mut Input) {
+        self.command.extend_from_slice(&self.packet);
+        // The lower 3 bits of the first byte give the number of packets in the command
+        let length = (self.command[0] & 0b111).max(1) as usize;
+        if self.command.len() >= length * PACKET_SIZE {
+            let command = self.command.split_off(0);
+            self.execute(&command, gpu, input);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8], gpu: &Gpu, input: &mut Input) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palette_pair(0, 1, data),
+            PAL23 => self.set_palette_pair(2, 3, data),
+            PAL03 => self.set_palette_pair(0, 3, data),
+            PAL12 => self.set_palette_pair(1, 2, data),
+            ATTR_BLK => self.attribute_blocks(data),
+            ATTR_LIN => self.attribute_lines(data),
+            ATTR_DIV => self.attribute_division(data),
+            ATTR_CHR => self.attribute_characters(data),
+            PAL_SET => {
+                for palette in 0 .. 4 {
+                    let number = u16::from_le_bytes([data[1 + palette * 2], data[2 + palette * 2]]) as usize;
+                    self.palettes[palette] = self.system_palettes[number % SYSTEM_PALETTES];
+                }
+                if data[9] & 0b10000000 > 0 {
+                    self.apply_attribute_file(data[9] as usize & 0b00111111);
+                }
+                if data[9] & 0b01000000 > 0 {
+                    self.mask = Mask::None;
+                }
+            },
+            PAL_TRN => {
+                let transfer = gpu.background_tile_data();
+                for (palette, colors) in self.system_palettes.iter_mut().enumerate() {
+                    for (color_num, color) in colors.iter_mut().enumerate() {
+                        let offset = palette * 8 + color_num * 2;
+                        *color = u16::from_le_bytes([transfer[offset], transfer[offset + 1]]);
+                    }
+                }
+            },
+            MLT_REQ => input.set_player_count(match data[1] & 0b11 {
+                1 => 2,
+                3 => 4,
+                _ => 1
+            }),
+            CHR_TRN => {
+                let start = if data[1] & 1 > 0 { 0x1000 } else { 0 };
+                self.border_tiles[start .. start + 0x1000].copy_from_slice(gpu.background_tile_data());
+            },
+            PCT_TRN => {
+                let transfer = gpu.background_tile_data();
+                for (index, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = u16::from_le_bytes([transfer[index * 2], transfer[index * 2 + 1]]);
+                }
+                for (palette, colors) in self.border_palettes.iter_mut().enumerate() {
+                    for (color_num, color) in colors.iter_mut().enumerate() {
+                        let offset = 0x800 + palette * 32 + color_num * 2;
+                        *color = u16::from_le_bytes([transfer[offset], transfer[offset + 1]]);
+                    }
+                }
+            },
+            ATTR_TRN => {
+                let len = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&gpu.background_tile_data()[.. len]);
+            },
+            ATTR_SET => {
+                self.apply_attribute_file(data[1] as usize & 0b00111111);
+                if data[1] & 0b01000000 > 0 {
+                    self.mask = Mask::None;
+                }
+            },
+            MASK_EN => {
+                self.mask = match data[1] & 0b11 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::None
+                };
+                if self.mask == Mask::Freeze {
+                    self.frozen.copy_from_slice(gpu.frame().indices());
+                }
+            },
+            // Sound, SNES program and other commands have no effect on the picture
+            _ => {}
+        }
+    }
+
+    fn set_palette_pair(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |index: usize| u16::from_le_bytes([data[1 + index * 2], data[2 + index * 2]]);
+        self.palettes[0][0] = color(0);
+        for color_num in 1 .. 4 {
+            self.palettes[first][color_num] = color(color_num);
+            self.palettes[second][color_num] = color(color_num + 3);
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < ATTR_WIDTH && y < ATTR_HEIGHT {
+            self.attributes[y * ATTR_WIDTH + x] = palette & 0b11;
+        }
+    }
+
+    /// Colors the inside, border and outside of up to 18 rectangles
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        let count = (data[1] as usize).min((data.len() - 2) / 6);
+        for block in data[2 ..].chunks(6).take(count) {
+            let control = block[0] & 0b111;
+            let mut inside = block[1] & 0b11;
+            let mut border = (block[1] >> 2) & 0b11;
+            let mut outside = (block[1] >> 4) & 0b11;
+            // With only the inside or outside set, the border takes the same palette
+            match control {
+                0b001 => border = inside,
+                0b100 => border = outside,
+                _ => {}
+            }
+            if control & 0b001 == 0 { inside = 0xFF; }
+            if control & 0b010 == 0 && control != 0b001 && control != 0b100 { border = 0xFF; }
+            if control & 0b100 == 0 { outside = 0xFF; }
+
+            let (x1, y1, x2, y2) = (block[2] as usize, block[3] as usize, block[4] as usize, block[5] as usize);
+            for y in 0 .. ATTR_HEIGHT {
+                for x in 0 .. ATTR_WIDTH {
+                    let within = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let on_edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if on_edge { border } else if within { inside } else { outside };
+                    if palette != 0xFF {
+                        self.set_attribute(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Colors whole rows or columns
+    fn attribute_lines(&mut self, data: &[u8]) {
+        let count = (data[1] as usize).min(data.len() - 2);
+        for &line in data[2 .. 2 + count].iter() {
+            let number = (line & 0b11111) as usize;
+            let palette = (line >> 5) & 0b11;
+            if line & 0b10000000 > 0 {
+                for x in 0 .. ATTR_WIDTH {
+                    self.set_attribute(x, number, palette);
+                }
+            } else {
+                for y in 0 .. ATTR_HEIGHT {
+                    self.set_attribute(number, y, palette);
+                }
+            }
+        }
+    }
+
+    /// Splits the screen into two halves and the line between them
+    fn attribute_division(&mut self, data: &[u8]) {
+        let after = data[1] & 0b11;
+        let before = (data[1] >> 2) & 0b11;
+        let on_line = (data[1] >> 4) & 0b11;
+        let horizontal = data[1] & 0b01000000 > 0;
+        let split = data[2] as usize;
+        for y in 0 .. ATTR_HEIGHT {
+            for x in 0 .. ATTR_WIDTH {
+                let position = if horizontal { y } else { x };
+                let palette = if position < split { before } else if position == split { on_line } else { after };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    /// Sets individual cells from a start position, four 2 bit palettes per byte
+    fn attribute_characters(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 1 > 0;
+        for index in 0 .. count.min((data.len() - 6) * 4) {
+            let palette = data[6 + index / 4] >> (6 - (index % 4) * 2);
+            self.set_attribute(x, y, palette);
+            if vertical {
+                y += 1;
+                if y == ATTR_HEIGHT { y = 0; x += 1; }
+            } else {
+                x += 1;
+                if x == ATTR_WIDTH { x = 0; y += 1; }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: usize) {
+        if file >= ATTR_FILES {
+            return;
+        }
+        let start = file * ATTR_FILE_SIZE;
+        for cell in 0 .. ATTR_WIDTH * ATTR_HEIGHT {
+            let byte = self.attribute_files[start + cell / 4];
+            self.attributes[cell] = (byte >> (6 - (cell % 4) * 2)) & 0b11;
+        }
+    }
+
+    /// Draws `frame` colorized by the SGB palettes inside the border, `SGB_WIDTH` by `SGB_HEIGHT` pixels
+    pub fn render(&self, frame: &FrameBuffer) -> Vec<Color> {
+        let convert = |color: u16| ColorCorrection::Raw.convert(color);
+        let backdrop = convert(self.palettes[0][0]);
+        let mut output = Vec::with_capacity(SGB_WIDTH * SGB_HEIGHT);
+        for y in 0 .. SGB_HEIGHT {
+            for x in 0 .. SGB_WIDTH {
+                let border = self.border_color_number(x, y);
+                let color = if border.1 != 0 {
+                    convert(self.border_palettes[border.0][border.1])
+                } else if x >= SCREEN_X && x < SCREEN_X + SCREEN_WIDTH as usize && y >= SCREEN_Y && y < SCREEN_Y + SCREEN_HEIGHT as usize {
+                    self.screen_color(frame, x - SCREEN_X, y - SCREEN_Y)
+                } else {
+                    backdrop
+                };
+                output.push(color);
+            }
+        }
+        output
+    }
+
+    fn screen_color(&self, frame: &FrameBuffer, x: usize, y: usize) -> Color {
+        let convert = |color: u16| ColorCorrection::Raw.convert(color);
+        let index = y * SCREEN_WIDTH as usize + x;
+        let shade = match self.mask {
+            Mask::None => frame.indices()[index] & 0b11,
+            Mask::Freeze => self.frozen[index] & 0b11,
+            Mask::Black => return Color::rgb(0, 0, 0),
+            Mask::Color0 => 0
+        };
+        if shade == 0 {
+            return convert(self.palettes[0][0]);
+        }
+        let palette = self.attributes[(y / 8) * ATTR_WIDTH + x / 8] as usize;
+        convert(self.palettes[palette][shade as usize])
+    }
+
+    /// Border palette (0-3 for SGB palettes 4-7) and color number of a pixel, color 0 is transparent
+    fn border_color_number(&self, x: usize, y: usize) -> (usize, usize) {
+        let entry = self.border_map[(y / 8) * BORDER_MAP_WIDTH + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        let palette = ((entry >> 10) & 0b111) as usize;
+        let column = if entry & 0x4000 > 0 { 7 - x % 8 } else { x % 8 };
+        let row = if entry & 0x8000 > 0 { 7 - y % 8 } else { y % 8 };
+        // SNES 4bpp tiles store bitplanes 0 and 1 for each row, followed by bitplanes 2 and 3
+        let data = &self.border_tiles[tile * 32 ..];
+        let bit = |offset: usize| ((data[offset] >> (7 - column)) & 1) as usize;
+        let color_num = bit(row * 2) | bit(row * 2 + 1) << 1 | bit(16 + row * 2) << 2 | bit(17 + row * 2) << 3;
+        (palette.max(4) - 4, color_num)
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Sgb::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(sgb: &mut Sgb, gpu: &Gpu, input: &mut Input, packet: &[u8]) {
+        sgb.write_joypad(0x00, gpu, input);
+        sgb.write_joypad(0x30, gpu, input);
+        for bit in 0 .. PACKET_SIZE * 8 {
+            let value = packet.get(bit / 8).map_or(0, |byte| (byte >> (bit % 8)) & 1);
+            sgb.write_joypad(if value > 0 { 0x10 } else { 0x20 }, gpu, input);
+            sgb.write_joypad(0x30, gpu, input);
+        }
+        // Stop bit
+        sgb.write_joypad(0x20, gpu, input);
+        sgb.write_joypad(0x30, gpu, input);
+    }
+
+    fn same_color(a: Color, b: Color) -> bool {
+        a.r == b.r && a.g == b.g && a.b == b.b
+    }
+
+    fn screen_pixel(output: &[Color], x: usize, y: usize) -> Color {
+        output[(SCREEN_Y + y) * SGB_WIDTH + SCREEN_X + x]
+    }
+
+    #[test]
+    fn test_pal01_colors_screen() {
+        let gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        // Color 0 blue, palette 0 colors 1-3 red, palette 1 colors 1-3 green
+        send_packet(&mut sgb, &gpu, &mut input, &[PAL01 << 3 | 1, 0x00, 0x7C, 0x1F, 0x00, 0x1F, 0x00, 0x1F, 0x00,
+            0xE0, 0x03, 0xE0, 0x03, 0xE0, 0x03]);
+        assert_eq!(sgb.palettes[0], [0x7C00, 0x001F, 0x001F, 0x001F]);
+        assert_eq!(sgb.palettes[1], [0x7FFF, 0x03E0, 0x03E0, 0x03E0]);
+
+        let mut frame = FrameBuffer::new();
+        frame.set_pixel(1, 0, Color::rgb(0, 0, 0), 3);
+        let output = sgb.render(&frame);
+        assert!(same_color(screen_pixel(&output, 0, 0), ColorCorrection::Raw.convert(0x7C00)));
+        assert!(same_color(screen_pixel(&output, 1, 0), ColorCorrection::Raw.convert(0x001F)));
+        // Without a border the backdrop color surrounds the screen
+        assert!(same_color(output[0], ColorCorrection::Raw.convert(0x7C00)));
+    }
+
+    #[test]
+    fn test_attr_blk() {
+        let gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        // Inside palette 1, border palette 2, outside palette 3 for cells 2,2 to 5,5
+        send_packet(&mut sgb, &gpu, &mut input, &[ATTR_BLK << 3 | 1, 1, 0b111, 0b111001, 2, 2, 5, 5]);
+        assert_eq!(sgb.attributes[3 * ATTR_WIDTH + 3], 1);
+        assert_eq!(sgb.attributes[2 * ATTR_WIDTH + 4], 2);
+        assert_eq!(sgb.attributes[5 * ATTR_WIDTH + 5], 2);
+        assert_eq!(sgb.attributes[0], 3);
+        assert_eq!(sgb.attributes[17 * ATTR_WIDTH + 19], 3);
+    }
+
+    #[test]
+    fn test_attr_blk_outside_only() {
+        let gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        sgb.attributes = [1; ATTR_WIDTH * ATTR_HEIGHT];
+        // The border follows the outside palette 3, the inside palette bits are ignored
+        send_packet(&mut sgb, &gpu, &mut input, &[ATTR_BLK << 3 | 1, 1, 0b100, 0b110010, 2, 2, 5, 5]);
+        assert_eq!(sgb.attributes[3 * ATTR_WIDTH + 3], 1);
+        assert_eq!(sgb.attributes[2 * ATTR_WIDTH + 4], 3);
+        assert_eq!(sgb.attributes[0], 3);
+    }
+
+    #[test]
+    fn test_attr_blk_inside_only() {
+        let gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        sgb.attributes = [1; ATTR_WIDTH * ATTR_HEIGHT];
+        // The border follows the inside palette 2, the outside is left alone
+        send_packet(&mut sgb, &gpu, &mut input, &[ATTR_BLK << 3 | 1, 1, 0b001, 0b110010, 2, 2, 5, 5]);
+        assert_eq!(sgb.attributes[3 * ATTR_WIDTH + 3], 2);
+        assert_eq!(sgb.attributes[2 * ATTR_WIDTH + 4], 2);
+        assert_eq!(sgb.attributes[0], 1);
+    }
+
+    #[test]
+    fn test_multi_packet_command() {
+        let gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        // ATTR_CHR over two packets, the second packet carries cells 40 onwards
+        let mut first = [0; PACKET_SIZE];
+        first[0] = ATTR_CHR << 3 | 2;
+        first[3] = 44;
+        first[6 ..].copy_from_slice(&[0x55; 10]);
+        send_packet(&mut sgb, &gpu, &mut input, &first);
+        assert_eq!(sgb.attributes[0], 0);
+        send_packet(&mut sgb, &gpu, &mut input, &[0xFF; PACKET_SIZE]);
+        assert_eq!(sgb.attributes[0], 1);
+        assert_eq!(sgb.attributes[39], 1);
+        assert_eq!(sgb.attributes[43], 3);
+        assert_eq!(sgb.attributes[44], 0);
+    }
+
+    #[test]
+    fn test_mlt_req() {
+        let gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        send_packet(&mut sgb, &gpu, &mut input, &[MLT_REQ << 3 | 1, 1]);
+        assert_eq!(input.player_count(), 2);
+        input.write_joypad(0x30);
+        assert_eq!(input.read_joypad() & 0x0F, 0x0F);
+        // P15 going high after being low moves on to the next joypad
+        input.write_joypad(0x10);
+        input.write_joypad(0x30);
+        assert_eq!(input.read_joypad() & 0x0F, 0x0E);
+        send_packet(&mut sgb, &gpu, &mut input, &[MLT_REQ << 3 | 1, 0]);
+        assert_eq!(input.player_count(), 1);
+    }
+
+    #[test]
+    fn test_border_transfer() {
+        let mut gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        gpu.set_lcdc_control(0x10);
+        // Tile 1 has color 15 in every pixel of its first row
+        for offset in [32, 33, 48, 49].iter() {
+            gpu.dma_write_vram(*offset, 0xFF);
+        }
+        send_packet(&mut sgb, &gpu, &mut input, &[CHR_TRN << 3 | 1, 0]);
+
+        // Map entry 0 uses tile 1 with palette 4 flipped vertically, palette 4 color 15 is green
+        gpu.dma_write_vram(0, 0x01);
+        gpu.dma_write_vram(1, 0x90);
+        gpu.dma_write_vram(0x800 + 30, 0xE0);
+        gpu.dma_write_vram(0x800 + 31, 0x03);
+        send_packet(&mut sgb, &gpu, &mut input, &[PCT_TRN << 3 | 1]);
+
+        let output = sgb.render(gpu.frame());
+        let green = ColorCorrection::Raw.convert(0x03E0);
+        assert!(same_color(output[7 * SGB_WIDTH], green));
+        assert!(same_color(output[7 * SGB_WIDTH + 7], green));
+        assert!(!same_color(output[0], green));
+        assert!(!same_color(output[7 * SGB_WIDTH + 8], green));
+    }
+
+    #[test]
+    fn test_mask_en_black() {
+        let gpu = Gpu::new(false).unwrap();
+        let mut input = Input::new();
+        let mut sgb = Sgb::new();
+        send_packet(&mut sgb, &gpu, &mut input, &[MASK_EN << 3 | 1, 2]);
+        let output = sgb.render(gpu.frame());
+        assert!(same_color(screen_pixel(&output, 0, 0), Color::rgb(0, 0, 0)));
+        send_packet(&mut sgb, &gpu, &mut input, &[MASK_EN << 3 | 1, 0]);
+        let output = sgb.render(gpu.frame());
+        assert!(same_color(screen_pixel(&output, 0, 0), ColorCorrection::Raw.convert(0x7FFF)));
+    }
+
+    #[test]
+    fn test_supports_sgb() {
+        let mut rom = vec![0; 0x8000];
+        assert!(!supports_sgb(&rom));
+        rom[0x146] = 0x03;
+        rom[0x14B] = 0x33;
+        assert!(supports_sgb(&rom));
+    }
+}
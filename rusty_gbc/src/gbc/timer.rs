@@ -2,15 +2,22 @@ use super::TIMER_INTERRUPT;
 const TIMER_ENABLE:u8 = 0b00000100;
 const TIMER_CLOCK_SPEED:u8 = 0b00000011;
 
+/// DIV and TIMA are both driven by one 16 bit counter incremented every cycle. DIV is its
+/// upper byte and TIMA increments on a falling edge of the counter bit selected by TAC
+/// ANDed with the timer enable bit, so resetting DIV or changing TAC can also increment TIMA
 pub struct Timer {
+    /// Internal system counter
     div: u16,
     /// Timer counter
-    pub tima: u8,
+    tima: u8,
     // Timer modulo
-    pub tma: u8,
-    enabled: bool,
-    increment_in_cpu_cycles: u16,
-    counter: u16,
+    tma: u8,
+    /// Timer control
+    tac: u8,
+    /// TIMA overflowed last M-cycle and reads 0 until it is reloaded this M-cycle
+    overflow_pending: bool,
+    /// TIMA was reloaded from TMA this M-cycle, writes to TIMA are ignored and writes to TMA also go to TIMA
+    reloading: bool,
     pub interrupt: u8
 }
 
@@ -20,60 +27,168 @@ impl Timer {
             div: 0,
             tima: 0,
             tma: 0,
-            enabled: false,
-            increment_in_cpu_cycles: 1024,
-            counter: 0,
+            tac: 0,
+            overflow_pending: false,
+            reloading: false,
             interrupt: 0,
         }
     }
 
     pub fn timer_step(&mut self, cycles: u8) {
-        self.div = self.div.wrapping_add(cycles as u16);
-        if self.enabled {
-            self.counter += cycles as u16;
-            if self.counter >= self.increment_in_cpu_cycles {
-                self.counter -= self.increment_in_cpu_cycles;
-                if self.tima == 0xFF {
-                    self.interrupt = TIMER_INTERRUPT;
-                    self.tima = self.tma;
-                } else {
-                    self.tima += 1;
-                }
-            }
+        for _ in 0 .. cycles / 4 {
+            self.m_cycle();
         }
     }
 
+    fn m_cycle(&mut self) {
+        self.reloading = false;
+        if self.overflow_pending {
+            // Reload and interrupt happen one M-cycle after the overflow
+            self.overflow_pending = false;
+            self.tima = self.tma;
+            self.interrupt = TIMER_INTERRUPT;
+            self.reloading = true;
+        }
+        let signal = self.timer_signal();
+        self.div = self.div.wrapping_add(4);
+        self.detect_falling_edge(signal);
+    }
+
+    /// The counter bit selected by TAC ANDed with the enable bit
+    fn timer_signal(&self) -> bool {
+        let bit = match self.tac & TIMER_CLOCK_SPEED {
+            0 => 9, // 4096 Hz
+            1 => 3, // 262144 Hz
+            2 => 5, // 65536 Hz
+            _ => 7  // 16384 Hz
+        };
+        self.tac & TIMER_ENABLE > 0 && self.div & (1 << bit) > 0
+    }
+
+    fn detect_falling_edge(&mut self, previous_signal: bool) {
+        if previous_signal && !self.timer_signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.overflow_pending = overflow;
+        }
+    }
+
+    /// Sets the whole internal counter, used to start with the value the boot ROM leaves
+    pub fn set_system_counter(&mut self, value: u16) {
+        self.div = value;
+    }
+
     pub fn get_div(&self) -> u8 {
         (self.div >> 8) as u8
     }
 
+    /// Writing any value to DIV resets the whole internal counter
     pub fn reset_div(&mut self) {
+        let signal = self.timer_signal();
         self.div = 0;
+        self.detect_falling_edge(signal);
     }
 
-    pub fn get_timer_control(&self) -> u8 {
-        let mut tac = 0b11111000;
-        if self.enabled {
-            tac |= TIMER_ENABLE;
+    pub fn get_tima(&self) -> u8 {
+        self.tima
+    }
+
+    pub fn set_tima(&mut self, value: u8) {
+        if self.reloading {
+            return;
+        }
+        // Writing during the M-cycle after an overflow cancels the reload and interrupt
+        self.overflow_pending = false;
+        self.tima = value;
+    }
+
+    pub fn get_tma(&self) -> u8 {
+        self.tma
+    }
+
+    pub fn set_tma(&mut self, value: u8) {
+        self.tma = value;
+        if self.reloading {
+            self.tima = value;
         }
-        tac |= match self.increment_in_cpu_cycles {
-            1024 => 0,
-            16 => 1,
-            64 => 2,
-            256 => 3,
-            _ => panic!("Invalid Timer clock speed {}", self.increment_in_cpu_cycles)
-        };
-        tac
+    }
+
+    pub fn get_timer_control(&self) -> u8 {
+        0b11111000 | self.tac
     }
 
     pub fn set_timer_control(&mut self, value: u8) {
-        self.enabled = value & TIMER_ENABLE > 0;
-        self.increment_in_cpu_cycles = match value & TIMER_CLOCK_SPEED {
-            0 => 1024,
-            1 => 16,
-            2 => 64,
-            3 => 256,
-            _ => panic!("Invalid Timer clock speed {}", value)
-        };
+        let signal = self.timer_signal();
+        self.tac = value & (TIMER_ENABLE | TIMER_CLOCK_SPEED);
+        self.detect_falling_edge(signal);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tima_increments_every_16_cycles() {
+        let mut timer = Timer::new();
+        timer.set_timer_control(TIMER_ENABLE | 1);
+        timer.timer_step(64);
+        assert_eq!(timer.get_tima(), 4);
+    }
+
+    #[test]
+    fn test_div_reset_glitch() {
+        let mut timer = Timer::new();
+        timer.set_timer_control(TIMER_ENABLE | 1);
+        // Bit 3 is set after 8 cycles, resetting DIV is then a falling edge
+        timer.timer_step(8);
+        assert_eq!(timer.get_tima(), 0);
+        timer.reset_div();
+        assert_eq!(timer.get_tima(), 1);
+        assert_eq!(timer.get_div(), 0);
+    }
+
+    #[test]
+    fn test_disabling_timer_glitch() {
+        let mut timer = Timer::new();
+        timer.set_timer_control(TIMER_ENABLE);
+        for _ in 0 .. 128 {
+            timer.timer_step(4);
+        }
+        // Bit 9 set, disabling drops the signal
+        timer.set_timer_control(0);
+        assert_eq!(timer.get_tima(), 1);
+    }
+
+    #[test]
+    fn test_overflow_reload_is_delayed() {
+        let mut timer = Timer::new();
+        timer.set_tma(0x80);
+        timer.set_tima(0xFF);
+        timer.set_timer_control(TIMER_ENABLE | 1);
+        timer.timer_step(16);
+        // Reads 0 for one M-cycle before the reload
+        assert_eq!(timer.get_tima(), 0);
+        assert_eq!(timer.interrupt, 0);
+        timer.timer_step(4);
+        assert_eq!(timer.get_tima(), 0x80);
+        assert_eq!(timer.interrupt, TIMER_INTERRUPT);
+        // Writes to TIMA during the reload cycle are ignored, TMA writes go through
+        timer.set_tima(0x10);
+        timer.set_tma(0x90);
+        assert_eq!(timer.get_tima(), 0x90);
+    }
+
+    #[test]
+    fn test_write_cancels_reload() {
+        let mut timer = Timer::new();
+        timer.set_tma(0x80);
+        timer.set_tima(0xFF);
+        timer.set_timer_control(TIMER_ENABLE | 1);
+        timer.timer_step(16);
+        timer.set_tima(0x10);
+        timer.timer_step(4);
+        assert_eq!(timer.get_tima(), 0x10);
+        assert_eq!(timer.interrupt, 0);
+    }
+}
@@ -1,5 +1,9 @@
 pub mod gbc;
 pub mod debugger;
+pub mod framebuffer;
+pub mod png;
+
+pub use framebuffer::{FrameBuffer, DebugImage};
 
 pub const SCREEN_WIDTH: u8 = 160;
 pub const SCREEN_HEIGHT: u8 = 144;
@@ -13,7 +17,7 @@ pub struct Color {
 }
 
 impl Color {
-    const fn rgb(r: u8, g: u8, b: u8) -> Self {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
         Color {
             r: r,
             g: g,
@@ -23,6 +27,5 @@ impl Color {
 }
 
 pub trait Display {
-    fn render_frame(&mut self);
-    fn update_line_from_buffer(&mut self, buffer: [Color; SCREEN_WIDTH as usize], line: u8);
+    fn render_frame(&mut self, frame: &FrameBuffer);
 }
\ No newline at end of file